@@ -1,11 +1,157 @@
-use crate::math::Ray;
+use crate::math::{Float, Ray};
 use crate::shape::{Shape, Hit};
 
+pub mod bvh;
+pub mod bvh8;
 pub mod kd;
 pub mod linear;
 
+pub use self::bvh::Bvh;
+pub use self::bvh8::Bvh8;
+pub use self::kd::KdTree;
 pub use self::linear::ShapeVec;
 
 pub trait Accelerator : Send + Sync {
-    fn trace(&self, ray: &Ray) -> Option<Hit>;
+    /// Finds the closest intersection along the ray
+    fn trace(&self, ray: &Ray) -> Option<Hit<'_>>;
+
+    /// Checks whether anything blocks the ray closer than `max_distance`
+    ///
+    /// Returns as soon as any blocker is found, which makes it much cheaper than
+    /// `trace` for visibility tests.
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::color::Color3;
+    use crate::material::Lambertian;
+    use crate::math::{INFINITY, Vector3};
+    use crate::random::RandomGenerator;
+    use crate::scene::{MaterialId, Scene};
+    use crate::shape::plane::Plane;
+    use crate::shape::{Sphere, Triangle};
+
+    pub fn triangle(a: Vector3, b: Vector3, c: Vector3, material: MaterialId) -> Triangle {
+        let normal = (b - a).cross(c - a).normalize();
+        Triangle { a, b, c, na: normal, nb: normal, nc: normal, material }
+    }
+
+    fn random_point(rng: &mut RandomGenerator, extent: Float) -> Vector3 {
+        Vector3::new(rng.range(-extent, extent), rng.range(-extent, extent), rng.range(-extent, extent))
+    }
+
+    /// Random spheres and triangles within 10 units of the origin, plus unbounded planes
+    ///
+    /// Triangles come in all sizes, so many straddle any split plane.
+    pub fn random_scene(seed: u64, sphere_count: usize, triangle_count: usize, plane_count: usize) -> Scene {
+        let mut rng = RandomGenerator::with_seed(seed);
+        let mut scene = Scene::new();
+        let material = scene.add_material(Lambertian { color: Color3::new(0.5, 0.5, 0.5) });
+
+        for _ in 0..sphere_count {
+            let center = random_point(&mut rng, 9.0);
+            let radius = rng.range(0.05, 1.0);
+            scene.add_shape(Sphere { center, radius, material });
+        }
+
+        for _ in 0..triangle_count {
+            let a = random_point(&mut rng, 9.0);
+            let size = if rng.unit() < 0.2 { 8.0 } else { 1.0 };
+            let b = a + random_point(&mut rng, size);
+            let c = a + random_point(&mut rng, size);
+            scene.add_shape(triangle(a, b, c, material));
+        }
+
+        for _ in 0..plane_count {
+            let point = random_point(&mut rng, 12.0);
+            let normal = random_point(&mut rng, 1.0).normalize();
+            scene.add_shape(Plane { point, normal, material });
+        }
+
+        scene
+    }
+
+    /// Rays starting inside and outside the scene, a quarter of them parallel to one or two axes
+    pub fn random_rays(seed: u64, count: usize) -> Vec<Ray> {
+        let mut rng = RandomGenerator::with_seed(seed);
+
+        (0..count)
+            .map(|i| {
+                let origin = random_point(&mut rng, 15.0);
+                let mut direction = random_point(&mut rng, 1.0);
+                match i % 8 {
+                    0 => direction.x = 0.0,
+                    1 => {
+                        direction.y = 0.0;
+                        direction.z = 0.0;
+                    }
+                    _ => (),
+                }
+                Ray::new(origin, direction.normalize())
+            })
+            .collect()
+    }
+
+    pub fn same_shape(a: &dyn Shape, b: &dyn Shape) -> bool {
+        std::ptr::eq(a as *const dyn Shape as *const u8, b as *const dyn Shape as *const u8)
+    }
+
+    /// Checks that `accel` finds the same closest hits and blockers as testing every shape
+    pub fn assert_matches_linear<A: Accelerator>(accel: &A, scene: &Scene, rays: &[Ray]) {
+        let linear = ShapeVec::new(scene);
+
+        for ray in rays {
+            match (accel.trace(ray), linear.trace(ray)) {
+                (Some(hit), Some(expected)) => {
+                    assert!((hit.t - expected.t).abs() <= 1e-9 * expected.t, "{:?}: t {} != {}", ray, hit.t, expected.t);
+                    assert!(same_shape(hit.shape, expected.shape), "{:?}: different shape at t {}", ray, hit.t);
+                }
+                (None, None) => (),
+                (hit, expected) => panic!("{:?}: hit at {:?}, expected {:?}", ray, hit.map(|hit| hit.t), expected.map(|hit| hit.t)),
+            }
+
+            for &max_distance in &[0.5, 4.0, 12.0, INFINITY] {
+                assert_eq!(accel.occluded(ray, max_distance), linear.occluded(ray, max_distance), "{:?} within {}", ray, max_distance);
+            }
+        }
+    }
+
+    /// Blockers at known distances along the axes from the origin, next to a grid of clutter
+    ///
+    /// A sphere is hit at t = 4 along +X and a triangle at t = 3 along +Z. The only
+    /// thing on the Y axis is a sphere behind the origin.
+    pub fn occlusion_scene() -> Scene {
+        let mut scene = Scene::new();
+        let material = scene.add_material(Lambertian { color: Color3::new(0.5, 0.5, 0.5) });
+
+        // Odd coordinates keep the clutter off the axes
+        for i in 0..64 {
+            let center = Vector3::new(-7.0 + 2.0 * (i % 8) as Float, 8.0, -7.0 + 2.0 * (i / 8) as Float);
+            scene.add_shape(Sphere { center, radius: 0.5, material });
+        }
+
+        scene.add_shape(Sphere { center: Vector3::new(5.0, 0.0, 0.0), radius: 1.0, material });
+        scene.add_shape(Sphere { center: Vector3::new(0.0, -5.0, 0.0), radius: 1.0, material });
+        scene.add_shape(triangle(
+            Vector3::new(-1.0, -1.0, 3.0),
+            Vector3::new(1.0, -1.0, 3.0),
+            Vector3::new(0.0, 1.0, 3.0),
+            material,
+        ));
+
+        scene
+    }
+
+    pub fn assert_occlusion<A: Accelerator>(accel: &A) {
+        for (direction, distance) in [(Vector3::new(1.0, 0.0, 0.0), 4.0), (Vector3::new(0.0, 0.0, 1.0), 3.0)] {
+            let ray = Ray::new(Vector3::ZERO, direction);
+            assert!(accel.occluded(&ray, distance + 1e-6), "blocker just before the end along {:?}", direction);
+            assert!(!accel.occluded(&ray, distance - 1e-6), "blocker just past the end along {:?}", direction);
+        }
+
+        let up = Ray::new(Vector3::ZERO, Vector3::new(0.0, 1.0, 0.0));
+        assert!(!accel.occluded(&up, INFINITY), "blocker behind the origin");
+    }
 }
@@ -0,0 +1,707 @@
+use crate::accelerator::Accelerator;
+use crate::math::*;
+use crate::scene::*;
+use crate::shape::{Shape, Hit};
+
+const BIN_COUNT: usize = 16;
+const MAX_LEAF_SIZE: usize = 8;
+
+// Spatial splits are attempted when children overlap by more than this fraction of the root area
+const SPATIAL_SPLIT_THRESHOLD: Float = 1e-5;
+// Spatial splits may add at most this many references per primitive
+const DUPLICATE_BUDGET: Float = 0.3;
+const MAX_SPATIAL_DEPTH: usize = 48;
+
+// SAH cost model, relative cost of a traversal step and a primitive intersection
+const TRAVERSAL_COST: Float = 1.0;
+const INTERSECTION_COST: Float = 1.0;
+
+/// Node of a flattened BVH
+///
+/// The first child of a branch is stored right after it, so only the index of
+/// the second child is kept.
+#[derive(Clone)]
+pub(super) struct Node {
+    pub(super) bounding_box: Aabb,
+    // First primitive for leaves, second child for branches
+    pub(super) offset: u32,
+    // Zero for branches
+    pub(super) count: u32,
+}
+
+impl Node {
+    pub(super) fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+#[derive(Clone)]
+struct Reference {
+    index: usize,
+    bounding_box: Aabb,
+    centroid: Vector3,
+}
+
+impl Reference {
+    fn new(index: usize, bounding_box: Aabb) -> Self {
+        Self {
+            index,
+            centroid: bounding_box.center(),
+            bounding_box,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Bin {
+    bounding_box: Option<Aabb>,
+    count: usize,
+}
+
+#[derive(Clone, Default)]
+struct SpatialBin {
+    bounding_box: Option<Aabb>,
+    // Number of references starting and ending in this bin
+    entries: usize,
+    exits: usize,
+}
+
+struct ObjectSplit {
+    axis: Axis,
+    bin: usize,
+    cost: Float,
+    left_bounds: Aabb,
+    right_bounds: Aabb,
+}
+
+struct SpatialSplit {
+    axis: Axis,
+    position: Float,
+    cost: Float,
+}
+
+enum Split {
+    Object(ObjectSplit),
+    Spatial(SpatialSplit),
+}
+
+fn extend(a: Option<Aabb>, b: &Aabb) -> Option<Aabb> {
+    Some(match a {
+        Some(a) => a.extend(b.clone()),
+        None => b.clone(),
+    })
+}
+
+fn bounds_of(references: &[Reference]) -> Aabb {
+    references.iter()
+        .fold(None, |bounds, reference| extend(bounds, &reference.bounding_box))
+        .unwrap_or_default()
+}
+
+fn area(bounding_box: &Option<Aabb>) -> Float {
+    bounding_box.as_ref().map_or(0.0, Aabb::surface_area)
+}
+
+struct Builder<'s, 'a> {
+    shapes: &'s [&'a dyn Shape],
+    nodes: Vec<Node>,
+    primitives: Vec<&'a dyn Shape>,
+    spatial_splits: bool,
+    root_area: Float,
+    // Number of reference duplicates spatial splits are still allowed to create
+    duplicate_budget: usize,
+}
+
+impl<'s, 'a> Builder<'s, 'a> {
+    fn new(shapes: &'s [&'a dyn Shape], spatial_splits: bool) -> Self {
+        Self {
+            shapes,
+            nodes: Vec::with_capacity(2 * shapes.len()),
+            primitives: Vec::with_capacity(shapes.len()),
+            spatial_splits,
+            root_area: 0.0,
+            duplicate_budget: (shapes.len() as Float * DUPLICATE_BUDGET) as usize,
+        }
+    }
+
+    fn build(&mut self, mut references: Vec<Reference>, depth: usize) -> usize {
+        let node_index = self.nodes.len();
+        let bounding_box = bounds_of(&references);
+        self.nodes.push(Node {
+            bounding_box: bounding_box.clone(),
+            offset: 0,
+            count: 0,
+        });
+
+        if node_index == 0 {
+            self.root_area = bounding_box.surface_area();
+        }
+
+        let count = references.len();
+        let leaf_cost = INTERSECTION_COST * count as Float;
+
+        let split = self.find_object_split(&references, &bounding_box).map(Split::Object);
+        let split = match split {
+            Some(Split::Object(object)) if self.should_try_spatial_split(&object, depth) => {
+                match self.find_spatial_split(&references, &bounding_box) {
+                    Some(spatial) if spatial.cost < object.cost => Some(Split::Spatial(spatial)),
+                    _ => Some(Split::Object(object)),
+                }
+            }
+            split => split,
+        };
+
+        let cost = match &split {
+            Some(Split::Object(split)) => split.cost,
+            Some(Split::Spatial(split)) => split.cost,
+            None => INFINITY,
+        };
+
+        let (left, right) = match split {
+            Some(split) if cost < leaf_cost || count > MAX_LEAF_SIZE => match split {
+                Split::Object(split) => self.partition(references, &split),
+                Split::Spatial(split) => {
+                    let (left, right) = self.spatial_partition(references, &split);
+                    if left.is_empty() || right.is_empty() {
+                        // Clipping disagreed with binning, nothing was actually split
+                        let mut references = if left.is_empty() { right } else { left };
+                        let right = references.split_off(references.len() / 2);
+                        (references, right)
+                    } else {
+                        (left, right)
+                    }
+                }
+            },
+            None if count > MAX_LEAF_SIZE => {
+                // All centroids coincide, any split is as good as another
+                let right = references.split_off(count / 2);
+                (references, right)
+            }
+            _ => {
+                self.make_leaf(node_index, references);
+                return node_index;
+            }
+        };
+
+        self.build(left, depth + 1);
+        let right_index = self.build(right, depth + 1);
+        self.nodes[node_index].offset = right_index as u32;
+
+        node_index
+    }
+
+    fn make_leaf(&mut self, node_index: usize, references: Vec<Reference>) {
+        let node = &mut self.nodes[node_index];
+        node.offset = self.primitives.len() as u32;
+        node.count = references.len() as u32;
+
+        for reference in references {
+            self.primitives.push(self.shapes[reference.index]);
+        }
+    }
+
+    fn centroid_bounds(references: &[Reference]) -> Aabb {
+        let first = references[0].centroid;
+        let (min, max) = references.iter()
+            .fold((first, first), |(min, max), reference| (min.min(reference.centroid), max.max(reference.centroid)));
+        Aabb::new(min, max)
+    }
+
+    fn bin_index(centroid_bounds: &Aabb, axis: Axis, centroid: Vector3) -> usize {
+        let min = centroid_bounds.min()[axis];
+        let extent = centroid_bounds.max()[axis] - min;
+        let bin = ((centroid[axis] - min) / extent * BIN_COUNT as Float) as usize;
+        bin.min(BIN_COUNT - 1)
+    }
+
+    /// Finds the best partition of references by centroid using binned SAH
+    fn find_object_split(&self, references: &[Reference], bounding_box: &Aabb) -> Option<ObjectSplit> {
+        if references.len() < 2 {
+            return None;
+        }
+
+        let total_area = bounding_box.surface_area();
+        let centroid_bounds = Self::centroid_bounds(references);
+        let mut best: Option<ObjectSplit> = None;
+
+        for &axis in &Axis::ALL {
+            if centroid_bounds.size()[axis] <= 0.0 {
+                continue;
+            }
+
+            let mut bins = vec![Bin::default(); BIN_COUNT];
+            for reference in references {
+                let bin = &mut bins[Self::bin_index(&centroid_bounds, axis, reference.centroid)];
+                bin.bounding_box = extend(bin.bounding_box.take(), &reference.bounding_box);
+                bin.count += 1;
+            }
+
+            // Sweep from the right, then evaluate every bin boundary from the left
+            let mut right_bounds = vec![None; BIN_COUNT];
+            for i in (1..BIN_COUNT).rev() {
+                let bounds = if i + 1 < BIN_COUNT { right_bounds[i + 1].clone() } else { None };
+                right_bounds[i] = match &bins[i].bounding_box {
+                    Some(bin_bounds) => extend(bounds, bin_bounds),
+                    None => bounds,
+                };
+            }
+
+            let mut left_bounds = None;
+            let mut left_count = 0;
+            for i in 0..BIN_COUNT - 1 {
+                if let Some(bin_bounds) = &bins[i].bounding_box {
+                    left_bounds = extend(left_bounds, bin_bounds);
+                }
+                left_count += bins[i].count;
+                let right_count = references.len() - left_count;
+
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST + INTERSECTION_COST *
+                    (area(&left_bounds) * left_count as Float + area(&right_bounds[i + 1]) * right_count as Float) / total_area;
+
+                if best.as_ref().is_none_or(|best| cost < best.cost) {
+                    best = Some(ObjectSplit {
+                        axis,
+                        bin: i,
+                        cost,
+                        left_bounds: left_bounds.clone().unwrap_or_default(),
+                        right_bounds: right_bounds[i + 1].clone().unwrap_or_default(),
+                    });
+                }
+            }
+        }
+
+        best
+    }
+
+    fn partition(&self, references: Vec<Reference>, split: &ObjectSplit) -> (Vec<Reference>, Vec<Reference>) {
+        let centroid_bounds = Self::centroid_bounds(&references);
+        references.into_iter()
+            .partition(|reference| Self::bin_index(&centroid_bounds, split.axis, reference.centroid) <= split.bin)
+    }
+
+    /// Spatial splits only pay off when the children of the best object split overlap noticeably
+    fn should_try_spatial_split(&self, split: &ObjectSplit, depth: usize) -> bool {
+        if !self.spatial_splits || self.duplicate_budget == 0 || depth >= MAX_SPATIAL_DEPTH {
+            return false;
+        }
+
+        let overlap = split.left_bounds.intersection(&split.right_bounds)
+            .map_or(0.0, |overlap| overlap.surface_area());
+
+        overlap / self.root_area > SPATIAL_SPLIT_THRESHOLD
+    }
+
+    /// Finds the best split plane using binned SAH over references clipped to the bins
+    fn find_spatial_split(&self, references: &[Reference], bounding_box: &Aabb) -> Option<SpatialSplit> {
+        let total_area = bounding_box.surface_area();
+        let mut best: Option<SpatialSplit> = None;
+
+        for &axis in &Axis::ALL {
+            let node_min = bounding_box.min()[axis];
+            let bin_width = bounding_box.size()[axis] / BIN_COUNT as Float;
+            if bin_width <= 0.0 {
+                continue;
+            }
+
+            let bin_of = |position: Float| (((position - node_min) / bin_width) as usize).min(BIN_COUNT - 1);
+
+            let mut bins = vec![SpatialBin::default(); BIN_COUNT];
+            for reference in references {
+                let shape = self.shapes[reference.index];
+                let first = bin_of(reference.bounding_box.min()[axis]);
+                let last = bin_of(reference.bounding_box.max()[axis]);
+
+                // Chop the reference into pieces that fit the bins
+                let mut remainder = Some(reference.bounding_box.clone());
+                for (i, bin) in bins.iter_mut().enumerate().take(last).skip(first) {
+                    let current = match remainder {
+                        Some(current) => current,
+                        None => break,
+                    };
+
+                    let plane = node_min + bin_width * (i + 1) as Float;
+                    let (left, right) = shape.split_bounding_box(&current, axis, plane);
+                    if let Some(left) = &left {
+                        bin.bounding_box = extend(bin.bounding_box.take(), left);
+                    }
+                    remainder = right;
+                }
+
+                if let Some(remainder) = &remainder {
+                    bins[last].bounding_box = extend(bins[last].bounding_box.take(), remainder);
+                }
+
+                bins[first].entries += 1;
+                bins[last].exits += 1;
+            }
+
+            let mut right_bounds = vec![None; BIN_COUNT];
+            let mut right_counts = [0; BIN_COUNT];
+            for i in (1..BIN_COUNT).rev() {
+                let (bounds, count) = if i + 1 < BIN_COUNT {
+                    (right_bounds[i + 1].clone(), right_counts[i + 1])
+                } else {
+                    (None, 0)
+                };
+                right_bounds[i] = match &bins[i].bounding_box {
+                    Some(bin_bounds) => extend(bounds, bin_bounds),
+                    None => bounds,
+                };
+                right_counts[i] = count + bins[i].exits;
+            }
+
+            let mut left_bounds = None;
+            let mut left_count = 0;
+            for i in 0..BIN_COUNT - 1 {
+                if let Some(bin_bounds) = &bins[i].bounding_box {
+                    left_bounds = extend(left_bounds, bin_bounds);
+                }
+                left_count += bins[i].entries;
+                let right_count = right_counts[i + 1];
+
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+
+                let duplicates = (left_count + right_count).saturating_sub(references.len());
+                if duplicates > self.duplicate_budget {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST + INTERSECTION_COST *
+                    (area(&left_bounds) * left_count as Float + area(&right_bounds[i + 1]) * right_count as Float) / total_area;
+
+                if best.as_ref().is_none_or(|best| cost < best.cost) {
+                    let position = node_min + bin_width * (i + 1) as Float;
+                    best = Some(SpatialSplit { axis, position, cost });
+                }
+            }
+        }
+
+        best
+    }
+
+    fn spatial_partition(&mut self, references: Vec<Reference>, split: &SpatialSplit) -> (Vec<Reference>, Vec<Reference>) {
+        let SpatialSplit { axis, position, .. } = *split;
+        let count = references.len();
+
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+
+        for reference in references {
+            if reference.bounding_box.max()[axis] <= position {
+                left.push(reference);
+            } else if reference.bounding_box.min()[axis] >= position {
+                right.push(reference);
+            } else {
+                let shape = self.shapes[reference.index];
+                match shape.split_bounding_box(&reference.bounding_box, axis, position) {
+                    (Some(left_box), Some(right_box)) => {
+                        left.push(Reference::new(reference.index, left_box));
+                        right.push(Reference::new(reference.index, right_box));
+                    }
+                    (None, Some(_)) => right.push(reference),
+                    _ => left.push(reference),
+                }
+            }
+        }
+
+        let duplicates = left.len() + right.len() - count;
+        self.duplicate_budget = self.duplicate_budget.saturating_sub(duplicates);
+
+        (left, right)
+    }
+}
+
+/// Bounding volume hierarchy built with binned SAH
+///
+/// Optionally uses spatial splits (SBVH) that duplicate references straddling
+/// the split plane, which helps with large overlapping triangles.
+pub struct Bvh<'a> {
+    pub(super) nodes: Vec<Node>,
+    pub(super) primitives: Vec<&'a dyn Shape>,
+    // Shapes with infinite extent (e.g. planes) can't be bounded and are tested separately
+    pub(super) unbounded: Vec<&'a dyn Shape>,
+}
+
+impl<'a> Bvh<'a> {
+    pub fn new(scene: &'a Scene) -> Self {
+        Self::build(scene, false)
+    }
+
+    pub fn with_spatial_splits(scene: &'a Scene) -> Self {
+        Self::build(scene, true)
+    }
+
+    fn build(scene: &'a Scene, spatial_splits: bool) -> Self {
+        let mut shapes = Vec::new();
+        let mut references = Vec::new();
+        let mut unbounded = Vec::new();
+
+        for shape in scene.shapes() {
+            let bounding_box = shape.bounding_box();
+            if bounding_box.is_finite() {
+                references.push(Reference::new(shapes.len(), bounding_box));
+                shapes.push(shape);
+            } else {
+                unbounded.push(shape);
+            }
+        }
+
+        let mut builder = Builder::new(&shapes, spatial_splits);
+
+        if !references.is_empty() {
+            builder.build(references, 0);
+        }
+
+        Self {
+            nodes: builder.nodes,
+            primitives: builder.primitives,
+            unbounded,
+        }
+    }
+}
+
+impl Accelerator for Bvh<'_> {
+    fn trace(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let mut ray = ray.clone();
+        let mut closest_hit: Option<Hit> = None;
+
+        // Every hit shrinks the ray, so only closer hits are reported afterwards
+        for &shape in &self.unbounded {
+            if let Some(hit) = shape.hit(&ray) {
+                ray.t_max = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return closest_hit;
+        }
+
+        let inv_direction = ray.inverse_direction();
+
+        let mut stack = Vec::with_capacity(64);
+        if let Some((t_near, _)) = self.nodes[0].bounding_box.intersect_ray_inv(&ray, inv_direction) {
+            stack.push((0, t_near));
+        }
+
+        while let Some((index, t_near)) = stack.pop() {
+            // A closer hit may have been found since the node was pushed
+            if t_near > ray.t_max {
+                continue;
+            }
+
+            let node = &self.nodes[index];
+
+            if node.is_leaf() {
+                let first = node.offset as usize;
+                for &shape in &self.primitives[first..first + node.count as usize] {
+                    if let Some(hit) = shape.hit(&ray) {
+                        ray.t_max = hit.t;
+                        closest_hit = Some(hit);
+                    }
+                }
+                continue;
+            }
+
+            let left = index + 1;
+            let right = node.offset as usize;
+
+            let t_left = self.nodes[left].bounding_box.intersect_ray_inv(&ray, inv_direction)
+                .map(|(t_near, _)| t_near);
+            let t_right = self.nodes[right].bounding_box.intersect_ray_inv(&ray, inv_direction)
+                .map(|(t_near, _)| t_near);
+
+            // Visit the closer child first
+            match (t_left, t_right) {
+                (Some(t_left), Some(t_right)) => if t_left <= t_right {
+                    stack.push((right, t_right));
+                    stack.push((left, t_left));
+                } else {
+                    stack.push((left, t_left));
+                    stack.push((right, t_right));
+                },
+                (Some(t_left), None) => stack.push((left, t_left)),
+                (None, Some(t_right)) => stack.push((right, t_right)),
+                (None, None) => (),
+            }
+        }
+
+        closest_hit
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        let ray = &ray.shortened(max_distance);
+        let blocks = |shape: &&dyn Shape| shape.hit(ray).is_some();
+
+        if self.unbounded.iter().any(blocks) {
+            return true;
+        }
+
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inv_direction = ray.inverse_direction();
+        let hits_box = |index: usize| {
+            self.nodes[index].bounding_box.intersect_ray_inv(ray, inv_direction).is_some()
+        };
+
+        // Order doesn't matter here, the first blocker ends the search
+        let mut stack = Vec::with_capacity(64);
+        if hits_box(0) {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+
+            if node.is_leaf() {
+                let first = node.offset as usize;
+                if self.primitives[first..first + node.count as usize].iter().any(blocks) {
+                    return true;
+                }
+                continue;
+            }
+
+            for child in [index + 1, node.offset as usize] {
+                if hits_box(child) {
+                    stack.push(child);
+                }
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerator::Bvh8;
+    use crate::accelerator::tests::*;
+    use crate::color::Color3;
+    use crate::material::Lambertian;
+    use crate::random::RandomGenerator;
+    use crate::shape::Sphere;
+
+    #[test]
+    fn matches_linear() {
+        for seed in 0..4 {
+            let scene = random_scene(seed, 60, 60, 0);
+            let bvh = Bvh::new(&scene);
+            assert!(bvh.nodes.len() > 1);
+            assert_matches_linear(&bvh, &scene, &random_rays(seed, 500));
+        }
+    }
+
+    #[test]
+    fn empty_scene() {
+        let scene = Scene::new();
+        let bvh = Bvh::new(&scene);
+        assert!(bvh.nodes.is_empty());
+        assert_matches_linear(&bvh, &scene, &random_rays(1, 20));
+    }
+
+    #[test]
+    fn single_primitive() {
+        let scene = random_scene(2, 1, 0, 0);
+        let bvh = Bvh::new(&scene);
+        assert_eq!(bvh.nodes.len(), 1);
+        assert!(bvh.nodes[0].is_leaf());
+
+        let sphere = bvh.primitives[0].bounding_box().center();
+        let mut rays = random_rays(2, 100);
+        rays.extend(random_rays(3, 100).into_iter().map(|ray| Ray::new(ray.origin, (sphere - ray.origin).normalize())));
+        assert_matches_linear(&bvh, &scene, &rays);
+    }
+
+    #[test]
+    fn coincident_centroids() {
+        let mut scene = Scene::new();
+        let material = scene.add_material(Lambertian { color: Color3::new(0.5, 0.5, 0.5) });
+        for i in 0..40 {
+            let radius = 0.5 + 0.25 * i as Float;
+            scene.add_shape(Sphere { center: Vector3::new(1.0, 2.0, 3.0), radius, material });
+        }
+
+        // Binning can't separate the spheres, they are split in halves instead
+        let bvh = Bvh::new(&scene);
+        assert!(bvh.nodes.len() > 1);
+        assert!(bvh.nodes.iter().all(|node| node.count as usize <= MAX_LEAF_SIZE));
+        assert_matches_linear(&bvh, &scene, &random_rays(4, 500));
+    }
+
+    #[test]
+    fn unbounded_shapes() {
+        let scene = random_scene(5, 30, 30, 3);
+        let bvh = Bvh::new(&scene);
+        assert_eq!(bvh.unbounded.len(), 3);
+        assert_matches_linear(&bvh, &scene, &random_rays(5, 500));
+
+        let planes_only = random_scene(6, 0, 0, 2);
+        assert_matches_linear(&Bvh::new(&planes_only), &planes_only, &random_rays(6, 100));
+    }
+
+    /// Long, thin triangles crossing the scene in all directions
+    fn slivers(seed: u64, count: usize) -> Scene {
+        let mut rng = RandomGenerator::with_seed(seed);
+        let mut scene = Scene::new();
+        let material = scene.add_material(Lambertian { color: Color3::new(0.5, 0.5, 0.5) });
+
+        for _ in 0..count {
+            let direction = rng.unit_sphere();
+            let center = rng.unit_sphere() * 2.0;
+            let a = center - direction * 10.0;
+            let b = center + direction * 10.0;
+            let c = a + direction.coordinate_system().0 * 0.05;
+            scene.add_shape(triangle(a, b, c, material));
+        }
+
+        scene
+    }
+
+    #[test]
+    fn spatial_splits_match_linear() {
+        for seed in 0..4 {
+            let scene = slivers(seed, 100);
+            let rays = random_rays(seed, 1000);
+
+            let sbvh = Bvh::with_spatial_splits(&scene);
+            assert!(sbvh.primitives.len() > 100, "no reference was split");
+            assert_matches_linear(&sbvh, &scene, &rays);
+            assert_matches_linear(&Bvh8::with_spatial_splits(&scene), &scene, &rays);
+        }
+
+        let scene = random_scene(9, 30, 60, 2);
+        assert_matches_linear(&Bvh::with_spatial_splits(&scene), &scene, &random_rays(9, 500));
+    }
+
+    #[test]
+    fn duplicate_budget() {
+        for (seed, count) in [(0, 40), (1, 100), (2, 400), (3, 2000)] {
+            let scene = slivers(seed, count);
+            let sbvh = Bvh::with_spatial_splits(&scene);
+
+            let budget = (count as Float * DUPLICATE_BUDGET) as usize;
+            assert!(sbvh.primitives.len() <= count + budget, "{} references for {} shapes", sbvh.primitives.len(), count);
+        }
+
+        // Enough slivers use up the whole budget
+        let scene = slivers(3, 2000);
+        assert_eq!(Bvh::with_spatial_splits(&scene).primitives.len(), 2600);
+        assert_eq!(Bvh::new(&scene).primitives.len(), 2000);
+    }
+
+    #[test]
+    fn occlusion() {
+        let scene = occlusion_scene();
+        assert_occlusion(&Bvh::new(&scene));
+        assert_occlusion(&Bvh::with_spatial_splits(&scene));
+    }
+}
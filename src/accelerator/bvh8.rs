@@ -0,0 +1,446 @@
+use crate::accelerator::Accelerator;
+use crate::accelerator::bvh::{Bvh, Node};
+use crate::math::*;
+use crate::scene::*;
+use crate::shape::{Shape, Hit};
+use wide::CmpLe;
+
+const WIDTH: usize = 8;
+
+// Compensates for rounding in the single precision slab test
+const ROBUST_SCALE: f32 = 1.0 + 4.0 * f32::EPSILON;
+// Boxes are padded by this fraction of the scene size to cover rounding of the ray origin
+const PADDING: Float = 1e-6;
+
+#[derive(Clone, Copy, Default)]
+struct Child {
+    // Node index for branches, first primitive for leaves
+    offset: u32,
+    // Zero for branches
+    count: u32,
+}
+
+/// 8-wide node storing child boxes in SoA layout
+struct Node8 {
+    min: Vector3x8,
+    max: Vector3x8,
+    children: [Child; WIDTH],
+    child_count: usize,
+}
+
+impl Node8 {
+    /// Children selected by `mask` with their entry distances, sorted far to near
+    fn sorted_children(&self, mut mask: i32, t_enter: &[f32; WIDTH]) -> ([(Child, f32); WIDTH], usize) {
+        let mut hits = [(Child::default(), 0.0f32); WIDTH];
+        let mut hit_count = 0;
+        while mask != 0 {
+            let slot = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+
+            let mut i = hit_count;
+            while i > 0 && hits[i - 1].1 < t_enter[slot] {
+                hits[i] = hits[i - 1];
+                i -= 1;
+            }
+            hits[i] = (self.children[slot], t_enter[slot]);
+            hit_count += 1;
+        }
+
+        (hits, hit_count)
+    }
+}
+
+struct Collapser<'n> {
+    binary_nodes: &'n [Node],
+    nodes: Vec<Node8>,
+    padding: Float,
+}
+
+impl Collapser<'_> {
+    /// Turns a binary BVH subtree into 8-wide nodes by repeatedly opening the largest inner child
+    fn collapse(&mut self, index: usize) -> u32 {
+        let binary_nodes = self.binary_nodes;
+        let node = &binary_nodes[index];
+
+        let mut children = if node.is_leaf() {
+            vec![index]
+        } else {
+            vec![index + 1, node.offset as usize]
+        };
+
+        while children.len() < WIDTH {
+            let largest = children.iter()
+                .enumerate()
+                .filter(|(_, &child)| !binary_nodes[child].is_leaf())
+                .max_by(|(_, &a), (_, &b)| {
+                    cmp_float(&binary_nodes[a].bounding_box.surface_area(), &binary_nodes[b].bounding_box.surface_area())
+                })
+                .map(|(i, _)| i);
+
+            match largest {
+                Some(i) => {
+                    let child = children.swap_remove(i);
+                    children.push(child + 1);
+                    children.push(binary_nodes[child].offset as usize);
+                }
+                None => break,
+            }
+        }
+
+        let node_index = self.nodes.len();
+        self.nodes.push(Node8 {
+            min: Vector3x8::ZERO,
+            max: Vector3x8::ZERO,
+            children: [Child::default(); WIDTH],
+            child_count: children.len(),
+        });
+
+        let mut min = [[0.0f32; WIDTH]; 3];
+        let mut max = [[0.0f32; WIDTH]; 3];
+        let mut packed = [Child::default(); WIDTH];
+
+        for (slot, &child) in children.iter().enumerate() {
+            let binary_node = &binary_nodes[child];
+
+            for (i, &axis) in Axis::ALL.iter().enumerate() {
+                min[i][slot] = round_down(binary_node.bounding_box.min()[axis] - self.padding);
+                max[i][slot] = round_up(binary_node.bounding_box.max()[axis] + self.padding);
+            }
+
+            packed[slot] = if binary_node.is_leaf() {
+                Child { offset: binary_node.offset, count: binary_node.count }
+            } else {
+                Child { offset: self.collapse(child), count: 0 }
+            };
+        }
+
+        let node = &mut self.nodes[node_index];
+        node.min = Vector3x8::new(min[0].into(), min[1].into(), min[2].into());
+        node.max = Vector3x8::new(max[0].into(), max[1].into(), max[2].into());
+        node.children = packed;
+
+        node_index as u32
+    }
+}
+
+fn round_down(value: Float) -> f32 {
+    let rounded = value as f32;
+    if rounded as Float > value { rounded.next_down() } else { rounded }
+}
+
+fn round_up(value: Float) -> f32 {
+    let rounded = value as f32;
+    if (rounded as Float) < value { rounded.next_up() } else { rounded }
+}
+
+/// Ray broadcast to all lanes
+struct WideRay {
+    origin: Vector3x8,
+    inv_direction: Vector3x8,
+    t_min: Float8,
+}
+
+impl WideRay {
+    fn new(ray: &Ray) -> Self {
+        // Avoid infinities (and NaNs from 0 * inf) for axis-parallel rays
+        let inverse = |d: Float| {
+            let d = if d.abs() < 1e-20 { (1e-20 as Float).copysign(d) } else { d };
+            Float8::from((1.0 / d) as f32)
+        };
+
+        Self {
+            origin: Vector3x8::new(
+                Float8::from(ray.origin.x as f32),
+                Float8::from(ray.origin.y as f32),
+                Float8::from(ray.origin.z as f32),
+            ),
+            inv_direction: Vector3x8::new(
+                inverse(ray.direction.x),
+                inverse(ray.direction.y),
+                inverse(ray.direction.z),
+            ),
+            t_min: Float8::from(ray.t_min as f32),
+        }
+    }
+
+    /// Slab test against all children of a node at once
+    ///
+    /// Returns a bit mask of children hit closer than `t_max` and entry distances.
+    fn intersect(&self, node: &Node8, t_max: Float) -> (i32, [f32; WIDTH]) {
+        let tx0 = (node.min.x - self.origin.x) * self.inv_direction.x;
+        let tx1 = (node.max.x - self.origin.x) * self.inv_direction.x;
+        let ty0 = (node.min.y - self.origin.y) * self.inv_direction.y;
+        let ty1 = (node.max.y - self.origin.y) * self.inv_direction.y;
+        let tz0 = (node.min.z - self.origin.z) * self.inv_direction.z;
+        let tz1 = (node.max.z - self.origin.z) * self.inv_direction.z;
+
+        let t_enter = tx0.min(tx1).max(ty0.min(ty1)).max(tz0.min(tz1)).max(self.t_min);
+        let t_exit = tx0.max(tx1).min(ty0.max(ty1)).min(tz0.max(tz1)).min(Float8::from(t_max as f32));
+
+        let valid = (1 << node.child_count) - 1;
+        let mask = t_enter.cmp_le(t_exit * ROBUST_SCALE).move_mask() & valid;
+
+        (mask, t_enter.into())
+    }
+}
+
+/// BVH with 8-wide nodes, all children of a node are tested against a ray at once
+pub struct Bvh8<'a> {
+    nodes: Vec<Node8>,
+    primitives: Vec<&'a dyn Shape>,
+    unbounded: Vec<&'a dyn Shape>,
+}
+
+impl<'a> Bvh8<'a> {
+    pub fn new(scene: &'a Scene) -> Self {
+        Bvh::new(scene).into()
+    }
+
+    pub fn with_spatial_splits(scene: &'a Scene) -> Self {
+        Bvh::with_spatial_splits(scene).into()
+    }
+}
+
+impl<'a> From<Bvh<'a>> for Bvh8<'a> {
+    fn from(bvh: Bvh<'a>) -> Self {
+        let mut collapser = Collapser {
+            binary_nodes: &bvh.nodes,
+            nodes: Vec::with_capacity(bvh.nodes.len() / 4 + 1),
+            padding: 0.0,
+        };
+
+        if let Some(root) = bvh.nodes.first() {
+            let bounds = &root.bounding_box;
+            let scale = bounds.min().x.abs().max(bounds.min().y.abs()).max(bounds.min().z.abs())
+                .max(bounds.max().x.abs()).max(bounds.max().y.abs()).max(bounds.max().z.abs());
+            collapser.padding = scale * PADDING;
+            collapser.collapse(0);
+        }
+
+        Self {
+            nodes: collapser.nodes,
+            primitives: bvh.primitives,
+            unbounded: bvh.unbounded,
+        }
+    }
+}
+
+impl Accelerator for Bvh8<'_> {
+    fn trace(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let mut ray = ray.clone();
+        let mut closest_hit: Option<Hit> = None;
+
+        // Every hit shrinks the ray, so only closer hits are reported afterwards
+        for &shape in &self.unbounded {
+            if let Some(hit) = shape.hit(&ray) {
+                ray.t_max = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return closest_hit;
+        }
+
+        let wide_ray = WideRay::new(&ray);
+
+        let mut stack: Vec<(Child, f32)> = Vec::with_capacity(64);
+        stack.push((Child::default(), 0.0));
+
+        while let Some((child, t_near)) = stack.pop() {
+            // A closer hit may have been found since the child was pushed
+            if t_near > ray.t_max as f32 * ROBUST_SCALE {
+                continue;
+            }
+
+            if child.count > 0 {
+                let first = child.offset as usize;
+                for &shape in &self.primitives[first..first + child.count as usize] {
+                    if let Some(hit) = shape.hit(&ray) {
+                        ray.t_max = hit.t;
+                        closest_hit = Some(hit);
+                    }
+                }
+                continue;
+            }
+
+            let node = &self.nodes[child.offset as usize];
+            let (mask, t_enter) = wide_ray.intersect(node, ray.t_max);
+
+            // Push hit children far to near so the nearest one is popped first
+            let (hits, hit_count) = node.sorted_children(mask, &t_enter);
+            stack.extend_from_slice(&hits[..hit_count]);
+        }
+
+        closest_hit
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        let ray = &ray.shortened(max_distance);
+        let blocks = |shape: &&dyn Shape| shape.hit(ray).is_some();
+
+        if self.unbounded.iter().any(blocks) {
+            return true;
+        }
+
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let wide_ray = WideRay::new(ray);
+
+        // Order doesn't matter here, the first blocker ends the search
+        let mut stack = Vec::with_capacity(64);
+        stack.push(Child::default());
+
+        while let Some(child) = stack.pop() {
+            if child.count > 0 {
+                let first = child.offset as usize;
+                if self.primitives[first..first + child.count as usize].iter().any(blocks) {
+                    return true;
+                }
+                continue;
+            }
+
+            let node = &self.nodes[child.offset as usize];
+            let (mut mask, _) = wide_ray.intersect(node, ray.t_max);
+
+            while mask != 0 {
+                let slot = mask.trailing_zeros() as usize;
+                mask &= mask - 1;
+                stack.push(node.children[slot]);
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerator::tests::*;
+    use crate::color::Color3;
+    use crate::material::Lambertian;
+    use crate::shape::Sphere;
+
+    /// Checks that the 8-wide BVH finds the same hits and blockers as the binary one
+    fn assert_matches_bvh(scene: &Scene, rays: &[Ray]) {
+        let bvh = Bvh::new(scene);
+        let bvh8 = Bvh8::new(scene);
+
+        for ray in rays {
+            match (bvh8.trace(ray), bvh.trace(ray)) {
+                (Some(hit), Some(expected)) => {
+                    assert_eq!(hit.t, expected.t, "{:?}", ray);
+                    assert!(same_shape(hit.shape, expected.shape), "{:?}: different shape at t {}", ray, hit.t);
+                }
+                (None, None) => (),
+                (hit, expected) => panic!("{:?}: hit at {:?}, expected {:?}", ray, hit.map(|hit| hit.t), expected.map(|hit| hit.t)),
+            }
+
+            for &max_distance in &[1.0, 8.0, INFINITY] {
+                assert_eq!(bvh8.occluded(ray, max_distance), bvh.occluded(ray, max_distance), "{:?} within {}", ray, max_distance);
+            }
+        }
+    }
+
+    fn spheres_on_x_axis(count: usize) -> Scene {
+        let mut scene = Scene::new();
+        let material = scene.add_material(Lambertian { color: Color3::new(0.5, 0.5, 0.5) });
+        for i in 0..count {
+            let center = Vector3::new(2.0 + 3.0 * i as Float, 0.0, 0.0);
+            scene.add_shape(Sphere { center, radius: 1.0, material });
+        }
+        scene
+    }
+
+    #[test]
+    fn matches_bvh() {
+        for seed in 0..4 {
+            let scene = random_scene(seed, 100, 100, 1);
+            let rays = random_rays(seed, 500);
+            assert_matches_bvh(&scene, &rays);
+            assert_matches_linear(&Bvh8::new(&scene), &scene, &rays);
+        }
+
+        assert_matches_bvh(&Scene::new(), &random_rays(5, 10));
+    }
+
+    #[test]
+    fn padding_lanes() {
+        // Unused lanes hold empty boxes at the origin, rays through it must not enter them
+        let through_origin: Vec<_> = random_rays(1, 200).into_iter()
+            .map(|ray| Ray::new(ray.origin, -ray.origin.normalize()))
+            .collect();
+
+        for count in [1, 3, 5, 30] {
+            let scene = spheres_on_x_axis(count);
+            let bvh8 = Bvh8::new(&scene);
+            assert!(bvh8.nodes.iter().any(|node| node.child_count < WIDTH));
+
+            for ray in &through_origin {
+                let wide_ray = WideRay::new(ray);
+                for node in &bvh8.nodes {
+                    let (mask, _) = wide_ray.intersect(node, INFINITY);
+                    assert_eq!(mask >> node.child_count, 0);
+                }
+            }
+
+            assert_matches_bvh(&scene, &through_origin);
+        }
+    }
+
+    #[test]
+    fn degenerate_directions() {
+        let scene = random_scene(2, 100, 100, 0);
+        let bvh8 = Bvh8::new(&scene);
+
+        let mut rays = Vec::new();
+        for ray in random_rays(2, 100) {
+            for &axis in &Axis::ALL {
+                let mut direction = ray.direction;
+                direction[axis] = 0.0;
+                rays.push(Ray::new(ray.origin, direction.normalize()));
+
+                let mut direction = Vector3::ZERO;
+                direction[axis] = -1.0;
+                rays.push(Ray::new(ray.origin, direction));
+            }
+        }
+        assert_matches_bvh(&scene, &rays);
+
+        for direction in [Vector3::new(Float::NAN, 0.0, 1.0), Vector3::new(Float::NAN, Float::NAN, Float::NAN)] {
+            let ray = Ray::new(Vector3::new(0.5, -0.5, -20.0), direction);
+            assert!(bvh8.trace(&ray).is_none());
+            assert!(!bvh8.occluded(&ray, INFINITY));
+        }
+    }
+
+    #[test]
+    fn nearest_child_first() {
+        let scene = spheres_on_x_axis(64);
+        let bvh8 = Bvh8::new(&scene);
+
+        for direction in [1.0, -1.0] {
+            let origin = Vector3::new(if direction > 0.0 { -10.0 } else { 250.0 }, 0.0, 0.0);
+            let ray = Ray::new(origin, Vector3::new(direction, 0.0, 0.0));
+            let root = &bvh8.nodes[0];
+            let (mask, t_enter) = WideRay::new(&ray).intersect(root, INFINITY);
+            let (hits, hit_count) = root.sorted_children(mask, &t_enter);
+
+            assert_eq!(hit_count, root.child_count);
+            assert!(hits[..hit_count].windows(2).all(|pair| pair[0].1 >= pair[1].1));
+
+            // The last child pushed is popped first and holds the nearest sphere
+            let nearest = if direction > 0.0 { 11.0 } else { 58.0 };
+            assert_eq!(bvh8.trace(&ray).unwrap().t, nearest);
+        }
+    }
+
+    #[test]
+    fn occlusion() {
+        let scene = occlusion_scene();
+        assert_occlusion(&Bvh8::new(&scene));
+    }
+}
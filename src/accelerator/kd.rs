@@ -5,53 +5,378 @@ use crate::shape::{Shape, Hit};
 
 const LEAF_SIZE: usize = 8;
 
-pub struct Leaf<'a> {
-    shapes: Vec<&'a dyn Shape>,
+// SAH cost model, relative cost of a traversal step and a primitive intersection
+const TRAVERSAL_COST: Float = 1.0;
+const INTERSECTION_COST: Float = 4.0;
+const EMPTY_BONUS: Float = 0.2;
+
+#[derive(Clone)]
+struct Primitive<'a> {
+    shape: &'a dyn Shape,
+    bounding_box: Aabb,
+}
+
+struct Split {
+    axis: Axis,
+    position: Float,
+    cost: Float,
 }
 
 pub enum Node<'a> {
     Leaf {
-        bounding_box: Aabb,
-        shapes: Vec<&'a dyn Shape>
+        shapes: Vec<&'a dyn Shape>,
     },
     Branch {
-        bounding_box: Aabb,
+        axis: Axis,
+        split: Float,
         left: Box<Node<'a>>,
         right: Box<Node<'a>>,
     },
 }
 
 impl<'a> Node<'a> {
-    pub fn new(shapes: Vec<&'a dyn Shape>) -> Self {
-        let bounding_box = shapes.iter()
-            .nth(0)
-            .map(|shape| shape.bounding_box())
-            .unwrap_or_default();
-        let bounding_box = shapes.iter()
-            .fold(bounding_box, |aabb, shape| shape.bounding_box().extend(aabb));
+    fn new(primitives: Vec<Primitive<'a>>, bounding_box: &Aabb, depth: usize) -> Self {
+        if primitives.len() <= LEAF_SIZE || depth == 0 {
+            return Self::leaf(primitives);
+        }
+
+        let split = match Self::find_split(&primitives, bounding_box) {
+            Some(split) => split,
+            None => return Self::leaf(primitives),
+        };
+
+        let leaf_cost = INTERSECTION_COST * primitives.len() as Float;
+        if split.cost >= leaf_cost {
+            return Self::leaf(primitives);
+        }
+
+        let Split { axis, position, .. } = split;
+
+        let mut left_primitives = Vec::new();
+        let mut right_primitives = Vec::new();
+
+        for primitive in primitives {
+            let min = primitive.bounding_box.min()[axis];
+            let max = primitive.bounding_box.max()[axis];
+
+            // Primitives lying in the split plane go to both sides
+            let left = min < position || max <= position;
+            let right = max > position || min >= position;
 
-        for axis in &[Axis::X, Axis::Y, Axis::Z] {
-            
+            if left && right {
+                left_primitives.push(primitive.clone());
+                right_primitives.push(primitive);
+            } else if left {
+                left_primitives.push(primitive);
+            } else {
+                right_primitives.push(primitive);
+            }
         }
 
-        unimplemented!()
+        let (left_box, right_box) = bounding_box.split(axis, position);
+
+        Node::Branch {
+            axis,
+            split: position,
+            left: Box::new(Node::new(left_primitives, &left_box, depth - 1)),
+            right: Box::new(Node::new(right_primitives, &right_box, depth - 1)),
+        }
+    }
+
+    fn leaf(primitives: Vec<Primitive<'a>>) -> Self {
+        Node::Leaf {
+            shapes: primitives.into_iter().map(|primitive| primitive.shape).collect(),
+        }
+    }
+
+    /// Finds the cheapest split plane according to the surface area heuristic
+    fn find_split(primitives: &[Primitive], bounding_box: &Aabb) -> Option<Split> {
+        let total_area = bounding_box.surface_area();
+        if total_area <= 0.0 {
+            return None;
+        }
+
+        let mut best: Option<Split> = None;
+
+        for &axis in &Axis::ALL {
+            let node_min = bounding_box.min()[axis];
+            let node_max = bounding_box.max()[axis];
+
+            // Primitive extents clamped to the node
+            let mut mins: Vec<Float> = primitives.iter()
+                .map(|primitive| max(primitive.bounding_box.min()[axis], node_min))
+                .collect();
+            let mut maxs: Vec<Float> = primitives.iter()
+                .map(|primitive| min(primitive.bounding_box.max()[axis], node_max))
+                .collect();
+            mins.sort_by(cmp_float);
+            maxs.sort_by(cmp_float);
+
+            let candidates = mins.iter()
+                .chain(maxs.iter())
+                .filter(|&&position| position > node_min && position < node_max);
+
+            for &position in candidates {
+                let left_count = mins.partition_point(|&x| x < position);
+                let right_count = maxs.len() - maxs.partition_point(|&x| x <= position);
+
+                let (left_box, right_box) = bounding_box.split(axis, position);
+                let left_probability = left_box.surface_area() / total_area;
+                let right_probability = right_box.surface_area() / total_area;
+
+                let bonus = if left_count == 0 || right_count == 0 { 1.0 - EMPTY_BONUS } else { 1.0 };
+                let cost = TRAVERSAL_COST + bonus * INTERSECTION_COST *
+                    (left_probability * left_count as Float + right_probability * right_count as Float);
+
+                if best.as_ref().is_none_or(|best| cost < best.cost) {
+                    best = Some(Split { axis, position, cost });
+                }
+            }
+        }
+
+        best
     }
 }
 
+fn any_hit(shapes: &[&dyn Shape], ray: &Ray) -> bool {
+    shapes.iter().any(|shape| shape.hit(ray).is_some())
+}
+
+/// Finds the closest hit among shapes, shortening the ray to it
+fn closest<'a>(shapes: &[&'a dyn Shape], ray: &mut Ray, mut closest_hit: Option<Hit<'a>>) -> Option<Hit<'a>> {
+    for &shape in shapes {
+        if let Some(hit) = shape.hit(ray) {
+            ray.t_max = hit.t;
+            closest_hit = Some(hit);
+        }
+    }
+
+    closest_hit
+}
+
 pub struct KdTree<'a> {
     root: Node<'a>,
+    bounding_box: Aabb,
+    // Shapes with infinite extent (e.g. planes) can't be partitioned and are tested separately
+    unbounded: Vec<&'a dyn Shape>,
 }
 
 impl<'a> KdTree<'a> {
     pub fn new(scene: &'a Scene) -> Self {
+        let mut primitives = Vec::new();
+        let mut unbounded = Vec::new();
+
+        for shape in scene.shapes() {
+            let bounding_box = shape.bounding_box();
+            if bounding_box.is_finite() {
+                primitives.push(Primitive { shape, bounding_box });
+            } else {
+                unbounded.push(shape);
+            }
+        }
+
+        let bounding_box = primitives.iter()
+            .map(|primitive| primitive.bounding_box.clone())
+            .reduce(|a, b| a.extend(b))
+            .unwrap_or_default();
+
+        let max_depth = (8.0 + 1.3 * (primitives.len().max(1) as Float).log2()) as usize;
+
         Self {
-            root: Node::new(scene.shapes().collect())
+            root: Node::new(primitives, &bounding_box, max_depth),
+            bounding_box,
+            unbounded,
+        }
+    }
+}
+
+impl<'a> KdTree<'a> {
+    /// Visits leaves pierced by the ray front to back until `visit` returns true
+    ///
+    /// `visit` may shorten the ray, traversal stops once the ray ends inside the current leaf.
+    fn traverse<F>(&self, ray: &mut Ray, mut visit: F)
+        where F: FnMut(&[&'a dyn Shape], &mut Ray) -> bool
+    {
+        let (t_near, t_far) = match self.bounding_box.intersect_ray(ray) {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let mut stack = vec![(&self.root, max(t_near, ray.t_min), min(t_far, ray.t_max))];
+
+        while let Some((mut node, t_min, mut t_max)) = stack.pop() {
+            if t_min > ray.t_max {
+                return;
+            }
+
+            loop {
+                match node {
+                    Node::Branch { axis, split, left, right } => {
+                        let origin = ray.origin[*axis];
+                        let direction = ray.direction[*axis];
+
+                        let left_first = origin < *split || (origin == *split && direction <= 0.0);
+                        let (near, far) = if left_first { (left, right) } else { (right, left) };
+
+                        if direction == 0.0 {
+                            node = near;
+                            continue;
+                        }
+
+                        let t_split = (split - origin) / direction;
+
+                        if t_split > t_max || t_split <= 0.0 {
+                            node = near;
+                        } else if t_split < t_min {
+                            node = far;
+                        } else {
+                            stack.push((far, t_split, t_max));
+                            node = near;
+                            t_max = t_split;
+                        }
+                    }
+                    Node::Leaf { shapes } => {
+                        if visit(shapes, ray) {
+                            return;
+                        }
+                        break;
+                    }
+                }
+            }
+
+            // Leaves are visited front to back, so nothing further away can be closer
+            if ray.t_max <= t_max {
+                return;
+            }
         }
     }
 }
 
 impl Accelerator for KdTree<'_> {
-    fn trace(&self, ray: &Ray) -> Option<Hit> {
-        unimplemented!()
+    fn trace(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let mut ray = ray.clone();
+        let mut closest_hit = closest(&self.unbounded, &mut ray, None);
+
+        self.traverse(&mut ray, |shapes, ray| {
+            closest_hit = closest(shapes, ray, closest_hit.take());
+            false
+        });
+
+        closest_hit
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        let mut ray = ray.shortened(max_distance);
+        if any_hit(&self.unbounded, &ray) {
+            return true;
+        }
+
+        let mut occluded = false;
+        self.traverse(&mut ray, |shapes, ray| {
+            occluded = any_hit(shapes, ray);
+            occluded
+        });
+
+        occluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerator::tests::*;
+
+    fn leaf_references(node: &Node) -> usize {
+        match node {
+            Node::Leaf { shapes } => shapes.len(),
+            Node::Branch { left, right, .. } => leaf_references(left) + leaf_references(right),
+        }
+    }
+
+    fn split_planes(node: &Node, planes: &mut Vec<(Axis, Float)>) {
+        if let Node::Branch { axis, split, left, right } = node {
+            planes.push((*axis, *split));
+            split_planes(left, planes);
+            split_planes(right, planes);
+        }
+    }
+
+    #[test]
+    fn matches_linear() {
+        for seed in 0..4 {
+            let scene = random_scene(seed, 60, 60, 0);
+            let kd = KdTree::new(&scene);
+            assert!(matches!(kd.root, Node::Branch { .. }));
+            assert_matches_linear(&kd, &scene, &random_rays(seed, 500));
+        }
+    }
+
+    #[test]
+    fn unbounded_shapes() {
+        let scene = random_scene(11, 30, 30, 3);
+        let kd = KdTree::new(&scene);
+        assert_eq!(kd.unbounded.len(), 3);
+        assert_matches_linear(&kd, &scene, &random_rays(11, 500));
+
+        let planes_only = random_scene(12, 0, 0, 2);
+        assert_matches_linear(&KdTree::new(&planes_only), &planes_only, &random_rays(12, 100));
+    }
+
+    #[test]
+    fn rays_starting_inside() {
+        let scene = random_scene(21, 80, 40, 0);
+        let kd = KdTree::new(&scene);
+
+        let inside = |ray: &Ray| {
+            let (min, max) = (kd.bounding_box.min(), kd.bounding_box.max());
+            Axis::ALL.iter().all(|&axis| min[axis] < ray.origin[axis] && ray.origin[axis] < max[axis])
+        };
+        let rays: Vec<_> = random_rays(21, 1000).into_iter().filter(inside).collect();
+        assert!(rays.len() > 100);
+
+        assert_matches_linear(&kd, &scene, &rays);
+    }
+
+    #[test]
+    fn axis_parallel_rays() {
+        let scene = random_scene(31, 60, 60, 0);
+        let kd = KdTree::new(&scene);
+
+        let mut planes = Vec::new();
+        split_planes(&kd.root, &mut planes);
+        assert!(!planes.is_empty());
+
+        // Rays lying in split planes and crossing them at right angles
+        let mut rays = Vec::new();
+        for (i, &(axis, split)) in planes.iter().enumerate() {
+            let mut origin = Vector3::new(-15.0 + (i % 7) as Float * 4.0, 9.0 - (i % 5) as Float * 4.0, -3.0 + (i % 3) as Float * 3.0);
+            origin[axis] = split;
+
+            for &direction_axis in &Axis::ALL {
+                for &sign in &[1.0, -1.0] {
+                    let mut direction = Vector3::ZERO;
+                    direction[direction_axis] = sign;
+                    rays.push(Ray::new(origin, direction));
+                }
+            }
+        }
+
+        assert_matches_linear(&kd, &scene, &rays);
+    }
+
+    #[test]
+    fn straddling_primitives() {
+        let scene = random_scene(41, 40, 120, 0);
+        let kd = KdTree::new(&scene);
+
+        // Primitives crossing a split plane are referenced from both sides
+        assert!(leaf_references(&kd.root) > scene.shapes().count());
+        assert_matches_linear(&kd, &scene, &random_rays(41, 1000));
+    }
+
+    #[test]
+    fn occlusion() {
+        let scene = occlusion_scene();
+        assert_occlusion(&KdTree::new(&scene));
     }
 }
@@ -16,16 +16,34 @@ impl<'a> ShapeVec<'a> {
 }
 
 impl Accelerator for ShapeVec<'_> {
-    fn trace(&self, ray: &Ray) -> Option<Hit> {
+    fn trace(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let mut ray = ray.clone();
+
+        // Every hit shrinks the ray, so only closer hits are reported afterwards
         self.shapes.iter()
-            .fold((None, MAX), |(closest_hit, t), shape| {
-                if let Some(hit) = shape.hit(ray) {
-                    if hit.t < t {
-                        let new_t = hit.t;
-                        return (Some(hit), new_t);
-                    }
+            .fold(None, |closest_hit, shape| {
+                if let Some(hit) = shape.hit(&ray) {
+                    ray.t_max = hit.t;
+                    return Some(hit);
                 }
-                (closest_hit, t)
-            }).0
+                closest_hit
+            })
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        let ray = ray.shortened(max_distance);
+        self.shapes.iter().any(|shape| shape.hit(&ray).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerator::tests::*;
+
+    #[test]
+    fn occlusion() {
+        let scene = occlusion_scene();
+        assert_occlusion(&ShapeVec::new(&scene));
     }
 }
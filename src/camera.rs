@@ -10,11 +10,18 @@ pub struct Camera {
 
 impl Camera {
     pub fn look_at(origin: Vector3, at: Vector3, up: Vector3, fov: Float, aspect_ratio: Float) -> Self {
-        // Create orthonormal basis
+        // Create right-handed orthonormal basis
         let forward = (at - origin).normalize();
-        let right = up.cross(forward).normalize();
-        let up = forward.cross(right).normalize();
+        let right = forward.cross(up).normalize();
+        let up = right.cross(forward).normalize();
 
+        Self::from_basis(origin, forward, right, up, fov, aspect_ratio)
+    }
+
+    /// Camera looking along `forward` with the image's +X along `right` and +Y along `up`
+    ///
+    /// The basis is expected to be orthonormal, `fov` is the vertical field of view in degrees.
+    pub fn from_basis(origin: Vector3, forward: Vector3, right: Vector3, up: Vector3, fov: Float, aspect_ratio: Float) -> Self {
         // Calculate film size
         let fov = fov.to_radians();
         let vertical_size = (fov / 2.0).tan();
@@ -31,11 +38,15 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, u: Float, v: Float, rng: &mut RandomGenerator) -> Ray {
+    /// Widens or narrows the image to `aspect_ratio`, keeping the vertical field of view
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: Float) {
+        let center = self.top_left + self.horizontal / 2.0 + self.vertical / 2.0;
+        self.horizontal = self.horizontal * (aspect_ratio * self.vertical.len() / self.horizontal.len());
+        self.top_left = center - self.horizontal / 2.0 - self.vertical / 2.0;
+    }
+
+    pub fn get_ray(&self, u: Float, v: Float, _rng: &mut RandomGenerator) -> Ray {
         let direction = self.top_left + self.horizontal * u + self.vertical * v - self.origin;
-        Ray {
-            origin: self.origin,
-            direction: direction.normalize(),
-        }
+        Ray::new(self.origin, direction.normalize())
     }
 }
@@ -15,6 +15,7 @@ impl Rgba {
         Rgba { r, g, b, a: 255 }
     }
 
+    #[allow(clippy::self_named_constructors)]
     pub fn rgba(r: u8, g: u8, b: u8, a: u8) -> Rgba {
         Rgba { r, g, b, a }
     }
@@ -28,7 +29,7 @@ pub trait Color {
     fn store(&self, data: &mut [Self::Component]);
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color3 {
     pub r: Float,
     pub g: Float,
@@ -36,10 +37,22 @@ pub struct Color3 {
 }
 
 impl Color3 {
-    pub fn new(r: Float, g: Float, b: Float) -> Self {
+    pub const fn new(r: Float, g: Float, b: Float) -> Self {
         Color3 { r, g, b }
     }
 
+    pub fn luminance(&self) -> Float {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn max_component(&self) -> Float {
+        max(self.r, max(self.g, self.b))
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite()
+    }
+
     pub fn clamp(&self, min: Color3, max: Color3) -> Color3 {
         Color3 {
             r: clamp(self.r, min.r, max.r),
@@ -50,20 +63,210 @@ impl Color3 {
 }
 
 fn srgb_to_linear(component: Float) -> Float {
-    component.powf(2.2)
+    component.powf(GAMMA)
 }
 
 fn linear_to_srgb(component: Float) -> Float {
-    component.powf(1.0 / 2.2)
+    component.powf(1.0 / GAMMA)
+}
+
+/// Transfer function of the sRGB standard, linear near black
+fn linear_to_srgb_exact(component: Float) -> Float {
+    if component <= 0.0031308 {
+        12.92 * component
+    } else {
+        1.055 * component.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn transform(m: &[[Float; 3]; 3], c: Color3) -> Color3 {
+    Color3 {
+        r: m[0][0] * c.r + m[0][1] * c.g + m[0][2] * c.b,
+        g: m[1][0] * c.r + m[1][1] * c.g + m[1][2] * c.b,
+        b: m[2][0] * c.r + m[2][1] * c.g + m[2][2] * c.b,
+    }
+}
+
+/// Scales the color so that its luminance becomes `target`
+fn with_luminance(c: Color3, target: Float) -> Color3 {
+    let luminance = c.luminance();
+    if luminance > 0.0 {
+        c * (target / luminance)
+    } else {
+        Color3::new(0.0, 0.0, 0.0)
+    }
+}
+
+/// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms
+fn aces(c: Color3) -> Color3 {
+    const INPUT: [[Float; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[Float; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+
+    let fit = |v: Float| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+
+    let c = transform(&INPUT, c);
+    transform(&OUTPUT, Color3::new(fit(c.r), fit(c.g), fit(c.b)))
+}
+
+/// John Hable's filmic curve from Uncharted 2
+fn hable(c: Color3) -> Color3 {
+    const EXPOSURE_BIAS: Float = 2.0;
+    const WHITE: Float = 11.2;
+
+    let curve = |x: Float| {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+    };
+
+    let white_scale = 1.0 / curve(WHITE);
+    Color3::new(
+        curve(c.r * EXPOSURE_BIAS) * white_scale,
+        curve(c.g * EXPOSURE_BIAS) * white_scale,
+        curve(c.b * EXPOSURE_BIAS) * white_scale,
+    )
+}
+
+/// Troy Sobotka's AgX with the default look, returning linear values
+fn agx(c: Color3) -> Color3 {
+    const INSET: [[Float; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ];
+    const OUTSET: [[Float; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ];
+    const MIN_EV: Float = -12.47393;
+    const MAX_EV: Float = 4.026069;
+
+    // Log encoding followed by a polynomial fit of the sigmoid contrast curve
+    let curve = |v: Float| {
+        let x = (clamp(max(v, 1e-10).log2(), MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    };
+
+    let c = transform(&INSET, c);
+    let c = transform(&OUTSET, Color3::new(curve(c.r), curve(c.g), curve(c.b)));
+
+    // The curve produces display-encoded values
+    let c = c.clamp(Color3::new(0.0, 0.0, 0.0), Color3::new(1.0, 1.0, 1.0));
+    Color3::new(c.r.powf(GAMMA), c.g.powf(GAMMA), c.b.powf(GAMMA))
+}
+
+/// Operator compressing scene radiance into the displayable range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapper {
+    Clamp,
+    // Reinhard et al. 2002 on luminance, approaching 1 for infinite luminance
+    Reinhard,
+    // Reinhard with luminance `white` mapped to 1
+    ExtendedReinhard { white: Float },
+    Hable,
+    Aces,
+    AgX,
+}
+
+impl ToneMapper {
+    pub const NAMES: &'static str = "'clamp', 'reinhard', 'reinhard-extended', 'hable', 'aces' or 'agx'";
+    // Luminance mapped to white by the extended Reinhard operator unless specified
+    pub const DEFAULT_WHITE: Float = 4.0;
+
+    pub fn from_name(name: &str) -> Option<ToneMapper> {
+        Some(match name {
+            "clamp" => ToneMapper::Clamp,
+            "reinhard" => ToneMapper::Reinhard,
+            "reinhard-extended" => ToneMapper::ExtendedReinhard { white: Self::DEFAULT_WHITE },
+            "hable" => ToneMapper::Hable,
+            "aces" => ToneMapper::Aces,
+            "agx" => ToneMapper::AgX,
+            _ => return None,
+        })
+    }
+
+    /// Maps linear radiance to linear display values in `[0, 1]`
+    pub fn apply(&self, c: Color3) -> Color3 {
+        let c = Color3::new(max(c.r, 0.0), max(c.g, 0.0), max(c.b, 0.0));
+
+        let mapped = match *self {
+            ToneMapper::Clamp => c,
+            ToneMapper::Reinhard => {
+                let luminance = c.luminance();
+                with_luminance(c, luminance / (1.0 + luminance))
+            }
+            ToneMapper::ExtendedReinhard { white } => {
+                let luminance = c.luminance();
+                with_luminance(c, luminance * (1.0 + luminance / (white * white)) / (1.0 + luminance))
+            }
+            ToneMapper::Hable => hable(c),
+            ToneMapper::Aces => aces(c),
+            ToneMapper::AgX => agx(c),
+        };
+
+        mapped.clamp(Color3::new(0.0, 0.0, 0.0), Color3::new(1.0, 1.0, 1.0))
+    }
+}
+
+/// Encoding of linear display values into 8-bit components
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferFunction {
+    // Pure power law with an exponent of 1 / 2.2
+    Gamma,
+    // Piecewise sRGB curve
+    Srgb,
+}
+
+impl TransferFunction {
+    pub fn encode(&self, component: Float) -> Float {
+        match self {
+            TransferFunction::Gamma => linear_to_srgb(component),
+            TransferFunction::Srgb => linear_to_srgb_exact(component),
+        }
+    }
+}
+
+/// Conversion of rendered radiance to 8-bit display colors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub tone_mapper: ToneMapper,
+    // Exposure adjustment in stops, each one doubles the brightness
+    pub exposure: Float,
+    pub transfer: TransferFunction,
 }
 
-fn aces(x: Color3) -> Color3 {
-    let a = 2.51;
-    let b = Color3::new(0.03, 0.03, 0.03);
-    let c = 2.43;
-    let d = Color3::new(0.59, 0.59, 0.59);
-    let e = Color3::new(0.14, 0.14, 0.14);
-    ((x*(x*a+b))/(x*(x*c+d)+e)).clamp(Color3::new(0.0, 0.0, 0.0), Color3::new(1.0, 1.0, 1.0))
+impl ToneMapping {
+    pub fn apply(&self, c: Color3) -> Rgba {
+        let c = self.tone_mapper.apply(c * (2.0 as Float).powf(self.exposure));
+        let quantize = |component: Float| (self.transfer.encode(component) * 255.0).round() as u8;
+
+        Rgba {
+            r: quantize(c.r),
+            g: quantize(c.g),
+            b: quantize(c.b),
+            a: 255,
+        }
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            tone_mapper: ToneMapper::Aces,
+            exposure: 0.0,
+            transfer: TransferFunction::Gamma,
+        }
+    }
 }
 
 impl From<Vector3> for Color3 {
@@ -86,15 +289,9 @@ impl From<Rgba> for Color3 {
     }
 }
 
-impl Into<Rgba> for Color3 {
-    fn into(self) -> Rgba {
-        let c = aces(self);
-        Rgba {
-            r: (linear_to_srgb(c.r) * 255.0) as u8,
-            g: (linear_to_srgb(c.g) * 255.0) as u8,
-            b: (linear_to_srgb(c.b) * 255.0) as u8,
-            a: 255,
-        }
+impl From<Color3> for Rgba {
+    fn from(c: Color3) -> Rgba {
+        ToneMapping::default().apply(c)
     }
 }
 
@@ -164,3 +361,108 @@ impl Div<Float> for Color3 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONE_MAPPERS: [ToneMapper; 7] = [
+        ToneMapper::Clamp,
+        ToneMapper::Reinhard,
+        ToneMapper::ExtendedReinhard { white: ToneMapper::DEFAULT_WHITE },
+        ToneMapper::ExtendedReinhard { white: 100.0 },
+        ToneMapper::Hable,
+        ToneMapper::Aces,
+        ToneMapper::AgX,
+    ];
+
+    fn in_unit_range(c: Color3) -> bool {
+        [c.r, c.g, c.b].iter().all(|component| (0.0..=1.0).contains(component))
+    }
+
+    #[test]
+    fn black_stays_black() {
+        for tone_mapper in TONE_MAPPERS {
+            let black = tone_mapper.apply(Color3::new(0.0, 0.0, 0.0));
+            assert!(black.max_component() < 1e-12, "{:?}: {:?}", tone_mapper, black);
+            assert!(in_unit_range(black), "{:?}: {:?}", tone_mapper, black);
+
+            // Negative radiance is treated as black
+            assert_eq!(tone_mapper.apply(Color3::new(-1.0, -5.0, 0.0)), black, "{:?}", tone_mapper);
+        }
+    }
+
+    #[test]
+    fn monotonic() {
+        let colors = [Color3::new(1.0, 1.0, 1.0), Color3::new(1.0, 0.6, 0.3), Color3::new(0.1, 0.2, 1.0)];
+
+        for tone_mapper in TONE_MAPPERS {
+            // Once a channel of AgX saturates, its outset matrix subtracts the others still
+            // rising from it, so luminance may dip by about 2e-4 just below white
+            let tolerance = if tone_mapper == ToneMapper::AgX { 1e-3 } else { 1e-12 };
+
+            for color in colors {
+                let mut previous = 0.0;
+                // Luminance sweep over 2^-14 to 2^14 in quarter stops
+                for step in -56..=56 {
+                    let luminance = tone_mapper.apply(color * (2.0 as Float).powf(step as Float / 4.0)).luminance();
+                    assert!(luminance >= previous - tolerance, "{:?} {:?} at step {}", tone_mapper, color, step);
+                    previous = luminance;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bright_input_stays_in_range() {
+        for tone_mapper in TONE_MAPPERS {
+            for scale in [1.0, 10.0, 1e3, 1e6, 1e12] {
+                for color in [Color3::new(1.0, 1.0, 1.0), Color3::new(1.0, 0.0, 0.0), Color3::new(0.2, 5.0, 0.01)] {
+                    let mapped = tone_mapper.apply(color * scale);
+                    assert!(in_unit_range(mapped), "{:?} at {}: {:?}", tone_mapper, scale, mapped);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reinhard_white_point() {
+        let white = ToneMapper::ExtendedReinhard { white: 4.0 }.apply(Color3::new(4.0, 4.0, 4.0));
+        assert!((white.luminance() - 1.0).abs() < 1e-9);
+
+        let half = ToneMapper::Reinhard.apply(Color3::new(1.0, 1.0, 1.0));
+        assert!((half.luminance() - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn srgb_knee() {
+        let knee = 0.0031308;
+        let linear = 12.92 * knee;
+        let power = 1.055 * (knee as Float).powf(1.0 / 2.4) - 0.055;
+
+        // Both pieces meet at the knee
+        assert!((linear - power).abs() < 1e-6);
+        assert_eq!(TransferFunction::Srgb.encode(knee), linear);
+        assert_eq!(TransferFunction::Srgb.encode(knee / 2.0), linear / 2.0);
+        assert!((TransferFunction::Srgb.encode(knee + 1e-9) - linear).abs() < 1e-6);
+
+        assert_eq!(TransferFunction::Srgb.encode(0.0), 0.0);
+        assert!((TransferFunction::Srgb.encode(1.0) - 1.0).abs() < 1e-12);
+        assert!((TransferFunction::Srgb.encode(0.18) - 0.461356).abs() < 1e-6);
+    }
+
+    #[test]
+    fn quantization() {
+        let mapping = ToneMapping {
+            tone_mapper: ToneMapper::Clamp,
+            exposure: 1.0,
+            transfer: TransferFunction::Srgb,
+        };
+
+        let rgba = mapping.apply(Color3::new(0.5, 0.0, 2.0));
+        assert_eq!((rgba.r, rgba.g, rgba.b, rgba.a), (255, 0, 255, 255));
+
+        let rgba = mapping.apply(Color3::new(0.09, 0.0015654, 0.25));
+        assert_eq!((rgba.r, rgba.g, rgba.b), (118, 10, 188));
+    }
+}
@@ -1,8 +1,8 @@
 use std::io;
-use png;
 use std::num::ParseIntError;
 
 #[derive(thiserror::Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
@@ -16,8 +16,41 @@ pub enum Error {
     #[error("HDR decoding error")]
     HdrDecodingError,
 
+    #[error("PFM decoding error: {0}")]
+    PfmDecodingError(String),
+
+    #[error("EXR encoding error: {0}")]
+    ExrEncodingError(String),
+
     #[error("Parse error: {0}")]
-    ParseIntError(#[from] ParseIntError)
+    ParseIntError(#[from] ParseIntError),
+
+    #[error("OBJ parse error at line {line}: {message}")]
+    ObjParseError { line: usize, message: String },
+
+    #[error("MTL parse error at line {line}: {message}")]
+    MtlParseError { line: usize, message: String },
+
+    #[error("PLY parse error: {0}")]
+    PlyParseError(String),
+
+    #[error("JSON parse error at line {line}, column {column}: {message}")]
+    JsonParseError { line: usize, column: usize, message: String },
+
+    #[error("Invalid mesh: {0}")]
+    MeshError(String),
+
+    #[error("glTF error: {0}")]
+    GltfError(String),
+
+    #[error("Scene file error: {0}")]
+    SceneFileError(String),
+
+    #[error("Invalid argument: {0}")]
+    ArgumentError(String),
+
+    #[error("pbrt parse error in {file} at line {line}: {message}")]
+    PbrtParseError { file: String, line: usize, message: String },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
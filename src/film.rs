@@ -0,0 +1,299 @@
+use crate::color::Color3;
+use crate::filter::Filter;
+use crate::image::Image;
+use crate::math::*;
+use std::sync::Mutex;
+
+#[derive(Copy, Clone)]
+struct FilmPixel {
+    color_sum: Color3,
+    weight_sum: Float,
+}
+
+impl FilmPixel {
+    const EMPTY: FilmPixel = FilmPixel {
+        color_sum: Color3::new(0.0, 0.0, 0.0),
+        weight_sum: 0.0,
+    };
+
+    /// Weighted average of the samples, black if there are none
+    ///
+    /// Filters with negative lobes can produce slightly negative values, which are
+    /// clamped unless the data is `signed`.
+    fn color(&self, signed: bool) -> Color3 {
+        if self.weight_sum <= 0.0 {
+            return Color3::new(0.0, 0.0, 0.0);
+        }
+
+        let color = self.color_sum / self.weight_sum;
+        if signed {
+            color
+        } else {
+            Color3::new(max(color.r, 0.0), max(color.g, 0.0), max(color.b, 0.0))
+        }
+    }
+}
+
+/// Accumulates filtered radiance samples per pixel
+///
+/// Values stay linear and unbounded, conversion to a displayable image happens
+/// only when the film is turned into an `Image` for output. Samples are splatted
+/// into every pixel within the filter radius, so threads render into separate
+/// `FilmTile`s that are merged into the film when done.
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Box<dyn Filter>,
+    pixels: Mutex<Vec<FilmPixel>>,
+    // Negative averages are kept for signed data such as normals
+    signed: bool,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Box<dyn Filter>) -> Self {
+        Self {
+            width,
+            height,
+            filter,
+            pixels: Mutex::new(vec![FilmPixel::EMPTY; width * height]),
+            signed: false,
+        }
+    }
+
+    /// Film for values that may be negative, such as normals, which are not clamped
+    pub fn new_signed(width: usize, height: usize, filter: Box<dyn Filter>) -> Self {
+        Self {
+            signed: true,
+            ..Self::new(width, height, filter)
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Buffer for the samples of a block of pixels
+    ///
+    /// The buffer is padded by the filter radius since samples near the border of
+    /// the block also contribute to pixels of neighboring blocks.
+    pub fn tile(&self, x: usize, y: usize, width: usize, height: usize) -> FilmTile<'_> {
+        let padding = self.filter.radius().ceil() as usize;
+
+        let x0 = x.saturating_sub(padding);
+        let y0 = y.saturating_sub(padding);
+        let x1 = (x + width + padding).min(self.width);
+        let y1 = (y + height + padding).min(self.height);
+
+        FilmTile {
+            filter: self.filter.as_ref(),
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+            pixels: vec![FilmPixel::EMPTY; (x1 - x0) * (y1 - y0)],
+        }
+    }
+
+    /// Adds the samples of a tile to the film
+    pub fn merge_tile(&self, tile: FilmTile) {
+        let mut pixels = self.pixels.lock().unwrap();
+
+        for y in 0..tile.height {
+            for x in 0..tile.width {
+                let source = &tile.pixels[y * tile.width + x];
+                let pixel = &mut pixels[(tile.y + y) * self.width + tile.x + x];
+
+                pixel.color_sum = pixel.color_sum + source.color_sum;
+                pixel.weight_sum += source.weight_sum;
+            }
+        }
+    }
+
+    /// Weighted average of the samples of a pixel, black if it has none
+    pub fn get_pixel(&self, x: usize, y: usize) -> Color3 {
+        self.pixels.lock().unwrap()[y * self.width + x].color(self.signed)
+    }
+
+    /// Linear HDR image of the current estimate
+    pub fn to_image(&self) -> Image {
+        let pixels = self.pixels.lock().unwrap();
+
+        let mut image = Image::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.set_pixel(x, y, pixels[y * self.width + x].color(self.signed));
+            }
+        }
+
+        image
+    }
+}
+
+/// Padded block of a `Film` owned by a single thread
+pub struct FilmTile<'a> {
+    filter: &'a dyn Filter,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    pixels: Vec<FilmPixel>,
+}
+
+impl FilmTile<'_> {
+    /// Splats a sample taken at `position` in film pixel coordinates
+    ///
+    /// Pixel `(x, y)` covers `[x, x + 1) x [y, y + 1)`, so its center is at `(x + 0.5, y + 0.5)`.
+    /// NaN and infinite samples are dropped, the filter would smear them over the
+    /// whole footprint.
+    pub fn add_sample(&mut self, position: Vector2, color: Color3) {
+        if !color.is_finite() {
+            return;
+        }
+
+        let radius = self.filter.radius();
+
+        // Range of pixels whose center lies within the filter radius, clipped to the tile
+        let px = position.x - 0.5;
+        let py = position.y - 0.5;
+        let x0 = max((px - radius).ceil(), self.x as Float) as usize;
+        let y0 = max((py - radius).ceil(), self.y as Float) as usize;
+        let x1 = min((px + radius).floor() + 1.0, (self.x + self.width) as Float);
+        let y1 = min((py + radius).floor() + 1.0, (self.y + self.height) as Float);
+
+        for y in y0..max(y1, 0.0) as usize {
+            for x in x0..max(x1, 0.0) as usize {
+                let weight = self.filter.evaluate(x as Float - px, y as Float - py);
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let pixel = &mut self.pixels[(y - self.y) * self.width + x - self.x];
+                pixel.color_sum = pixel.color_sum + color * weight;
+                pixel.weight_sum += weight;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{BoxFilter, MitchellFilter, TentFilter};
+
+    const BLACK: Color3 = Color3::new(0.0, 0.0, 0.0);
+
+    fn weight_sum(film: &Film, x: usize, y: usize) -> Float {
+        film.pixels.lock().unwrap()[y * film.width + x].weight_sum
+    }
+
+    #[test]
+    fn padded_border_tiles() {
+        // Radius 1.5 pads tiles by 2 pixels, clipped at the film border
+        let film = Film::new(5, 4, Box::new(TentFilter { radius: 1.5 }));
+
+        let corner = film.tile(0, 0, 2, 2);
+        assert_eq!((corner.x, corner.y, corner.width, corner.height), (0, 0, 4, 4));
+        assert_eq!(corner.pixels.len(), 16);
+
+        let opposite = film.tile(3, 2, 2, 2);
+        assert_eq!((opposite.x, opposite.y, opposite.width, opposite.height), (1, 0, 4, 4));
+
+        let inner = film.tile(2, 1, 1, 1);
+        assert_eq!((inner.x, inner.y, inner.width, inner.height), (0, 0, 5, 4));
+
+        // Samples at the film corners only reach pixels inside the film
+        let color = Color3::new(1.0, 2.0, 3.0);
+        let mut corner = corner;
+        corner.add_sample(Vector2::new(0.01, 0.01), color);
+        // Lands in the padding of the corner tile, in the block of the opposite tile
+        corner.add_sample(Vector2::new(1.99, 1.5), color);
+        film.merge_tile(corner);
+
+        let mut opposite = opposite;
+        opposite.add_sample(Vector2::new(4.99, 3.99), color);
+        film.merge_tile(opposite);
+
+        for (x, y) in [(0, 0), (1, 1), (2, 1), (4, 3), (3, 2)] {
+            assert!(weight_sum(&film, x, y) > 0.0, "({}, {})", x, y);
+            let difference = film.get_pixel(x, y) - color;
+            assert!(difference.r.abs() + difference.g.abs() + difference.b.abs() < 1e-12, "({}, {})", x, y);
+        }
+        assert_eq!(weight_sum(&film, 4, 0), 0.0);
+        assert_eq!(film.get_pixel(4, 0), BLACK);
+    }
+
+    #[test]
+    fn accumulates_tiles() {
+        // A box of radius 0.5 includes both pixels whose center is half a pixel away
+        let film = Film::new(3, 1, Box::new(BoxFilter { radius: 0.5 }));
+        let red = Color3::new(1.0, 0.0, 0.0);
+        let blue = Color3::new(0.0, 0.0, 1.0);
+
+        let mut left = film.tile(0, 0, 1, 1);
+        left.add_sample(Vector2::new(0.5, 0.5), red);
+        film.merge_tile(left);
+
+        // Shared edge of pixels 0 and 1, pixel 0 is in the padding of this tile
+        let mut middle = film.tile(1, 0, 1, 1);
+        middle.add_sample(Vector2::new(1.0, 0.5), blue);
+        film.merge_tile(middle);
+
+        assert_eq!(film.get_pixel(0, 0), Color3::new(0.5, 0.0, 0.5));
+        assert_eq!(film.get_pixel(1, 0), blue);
+        assert_eq!(film.get_pixel(2, 0), BLACK);
+
+        // A second pass over the same blocks keeps averaging instead of overwriting
+        for _ in 0..3 {
+            let mut right = film.tile(2, 0, 1, 1);
+            right.add_sample(Vector2::new(2.5, 0.5), red);
+            right.add_sample(Vector2::new(2.25, 0.5), blue);
+            film.merge_tile(right);
+        }
+
+        let mut middle = film.tile(1, 0, 1, 1);
+        middle.add_sample(Vector2::new(1.5, 0.5), red);
+        film.merge_tile(middle);
+
+        assert_eq!(weight_sum(&film, 0, 0), 2.0);
+        assert_eq!(weight_sum(&film, 1, 0), 2.0);
+        assert_eq!(weight_sum(&film, 2, 0), 6.0);
+        assert_eq!(film.get_pixel(1, 0), Color3::new(0.5, 0.0, 0.5));
+        assert_eq!(film.get_pixel(2, 0), Color3::new(0.5, 0.0, 0.5));
+    }
+
+    #[test]
+    fn empty_and_negative_weights() {
+        let pixel = |color_sum: Color3, weight_sum: Float| FilmPixel { color_sum, weight_sum };
+        for signed in [false, true] {
+            assert_eq!(FilmPixel::EMPTY.color(signed), BLACK);
+            assert_eq!(pixel(Color3::new(1.0, 1.0, 1.0), 0.0).color(signed), BLACK);
+            assert_eq!(pixel(Color3::new(-1.0, 2.0, -3.0), -0.5).color(signed), BLACK);
+        }
+
+        // Pixels one and a half pixels away only see the negative lobe of the Mitchell filter
+        for film in [
+            Film::new(5, 5, Box::new(MitchellFilter { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 })),
+            Film::new_signed(5, 5, Box::new(MitchellFilter { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 })),
+        ] {
+            let mut tile = film.tile(0, 0, 5, 5);
+            tile.add_sample(Vector2::new(2.0, 2.5), Color3::new(1.0, 1.0, 1.0));
+            film.merge_tile(tile);
+
+            assert!(weight_sum(&film, 0, 2) < 0.0);
+            assert_eq!(film.get_pixel(0, 2), BLACK);
+            assert_eq!(film.get_pixel(2, 0), BLACK);
+
+            let image = film.to_image();
+            for y in 0..5 {
+                for x in 0..5 {
+                    assert!(image.get_pixel(x, y).is_finite(), "({}, {})", x, y);
+                }
+            }
+            assert_eq!(image.get_pixel(0, 2), BLACK);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::math::*;
+
+/// Pixel reconstruction filter
+///
+/// Offsets are measured in pixels from the pixel center, the filter is zero
+/// outside of `[-radius, radius]` on both axes.
+pub trait Filter : Send + Sync {
+    fn radius(&self) -> Float;
+    fn evaluate(&self, x: Float, y: Float) -> Float;
+}
+
+/// Equal weight for every sample inside the footprint
+pub struct BoxFilter {
+    pub radius: Float,
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        if x.abs() <= self.radius && y.abs() <= self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Weight falling off linearly to zero at the radius
+pub struct TentFilter {
+    pub radius: Float,
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        max(self.radius - x.abs(), 0.0) * max(self.radius - y.abs(), 0.0)
+    }
+}
+
+/// Gaussian of falloff `alpha`, shifted down to reach zero at the radius
+pub struct GaussianFilter {
+    pub radius: Float,
+    pub alpha: Float,
+}
+
+impl GaussianFilter {
+    fn gaussian(&self, d: Float) -> Float {
+        max((-self.alpha * d * d).exp() - (-self.alpha * self.radius * self.radius).exp(), 0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.gaussian(x) * self.gaussian(y)
+    }
+}
+
+/// Mitchell-Netravali cubic, sharper than a Gaussian at the cost of slight ringing
+pub struct MitchellFilter {
+    pub radius: Float,
+    pub b: Float,
+    pub c: Float,
+}
+
+impl MitchellFilter {
+    /// Cubic over `[-1, 1]`
+    fn mitchell(&self, x: Float) -> Float {
+        let (b, c) = (self.b, self.c);
+        let x = (2.0 * x).abs();
+
+        if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+        } else {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.mitchell(x / self.radius) * self.mitchell(y / self.radius)
+    }
+}
+
+/// Four-term Blackman-Harris window, close to a Gaussian with compact support
+pub struct BlackmanHarrisFilter {
+    pub radius: Float,
+}
+
+impl BlackmanHarrisFilter {
+    fn window(&self, d: Float) -> Float {
+        if d.abs() > self.radius {
+            return 0.0;
+        }
+
+        let t = 2.0 * PI * (d / (2.0 * self.radius) + 0.5);
+        0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+    }
+}
+
+impl Filter for BlackmanHarrisFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.window(x) * self.window(y)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    BlackmanHarris,
+}
+
+impl FilterKind {
+    pub const NAMES: &'static str = "'box', 'tent', 'gaussian', 'mitchell' or 'blackman-harris'";
+
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        Some(match name {
+            "box" => FilterKind::Box,
+            "tent" => FilterKind::Tent,
+            "gaussian" => FilterKind::Gaussian,
+            "mitchell" => FilterKind::Mitchell,
+            "blackman-harris" => FilterKind::BlackmanHarris,
+            _ => return None,
+        })
+    }
+
+    pub fn default_radius(self) -> Float {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::BlackmanHarris => 1.5,
+        }
+    }
+
+    /// Creates the filter with the given radius or its default one
+    pub fn create(self, radius: Option<Float>) -> Box<dyn Filter> {
+        let radius = radius.unwrap_or_else(|| self.default_radius());
+
+        match self {
+            FilterKind::Box => Box::new(BoxFilter { radius }),
+            FilterKind::Tent => Box::new(TentFilter { radius }),
+            FilterKind::Gaussian => Box::new(GaussianFilter { radius, alpha: 2.0 }),
+            FilterKind::Mitchell => Box::new(MitchellFilter { radius, b: 1.0 / 3.0, c: 1.0 / 3.0 }),
+            FilterKind::BlackmanHarris => Box::new(BlackmanHarrisFilter { radius }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color3;
+    use crate::film::Film;
+
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::BlackmanHarris,
+    ];
+
+    fn assert_close(a: Color3, b: Color3, kind: FilterKind) {
+        let difference = max(max((a.r - b.r).abs(), (a.g - b.g).abs()), (a.b - b.b).abs());
+        assert!(difference < 1e-9, "{:?}: {:?} != {:?}", kind, a, b);
+    }
+
+    /// Renders a `width` x `height` film in tiles of `tile_size`, with 4x4 samples per pixel
+    fn render(kind: FilterKind, width: usize, height: usize, tile_size: usize, color: impl Fn(Vector2) -> Color3) -> Film {
+        let film = Film::new(width, height, kind.create(None));
+
+        for tile_y in (0..height).step_by(tile_size) {
+            for tile_x in (0..width).step_by(tile_size) {
+                let tile_width = tile_size.min(width - tile_x);
+                let tile_height = tile_size.min(height - tile_y);
+                let mut tile = film.tile(tile_x, tile_y, tile_width, tile_height);
+
+                for y in tile_y..tile_y + tile_height {
+                    for x in tile_x..tile_x + tile_width {
+                        for i in 0..16 {
+                            let position = Vector2::new(
+                                x as Float + (i % 4) as Float / 4.0 + 0.125,
+                                y as Float + (i / 4) as Float / 4.0 + 0.125,
+                            );
+                            tile.add_sample(position, color(position));
+                        }
+                    }
+                }
+
+                film.merge_tile(tile);
+            }
+        }
+
+        film
+    }
+
+    #[test]
+    fn symmetric() {
+        for kind in KINDS {
+            let filter = kind.create(None);
+            let r = filter.radius();
+
+            for &(x, y) in &[(0.1, 0.0), (0.3, 0.2), (0.45 * r, 0.8 * r), (0.9 * r, 0.05)] {
+                let value = filter.evaluate(x, y);
+                for mirrored in [filter.evaluate(-x, y), filter.evaluate(x, -y), filter.evaluate(y, x)] {
+                    assert!((mirrored - value).abs() < 1e-12, "{:?}", kind);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zero_at_radius() {
+        for kind in KINDS {
+            for radius in [None, Some(0.75), Some(2.5)] {
+                let filter = kind.create(radius);
+                let r = filter.radius();
+                assert!(filter.evaluate(0.0, 0.0) > 0.0, "{:?}", kind);
+                assert_eq!(filter.evaluate(r * 1.01, 0.0), 0.0, "{:?}", kind);
+                assert_eq!(filter.evaluate(0.0, -r * 1.01), 0.0, "{:?}", kind);
+
+                // The box filter is the only one with a step at its edge, Blackman-Harris
+                // ends at a tiny positive value
+                if kind != FilterKind::Box {
+                    assert!(filter.evaluate(r, 0.0).abs() < 1e-4, "{:?}", kind);
+                    assert!(filter.evaluate(0.3, r).abs() < 1e-4, "{:?}", kind);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reproduces_constant_image() {
+        let constant = Color3::new(0.25, 1.5, 4.0);
+
+        for kind in KINDS {
+            let film = render(kind, 13, 7, 4, |_| constant);
+            for y in 0..7 {
+                for x in 0..13 {
+                    assert_close(film.get_pixel(x, y), constant, kind);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_match_single_tile() {
+        // Samples near tile borders must reach the pixels of neighboring tiles
+        let gradient = |p: Vector2| Color3::new(p.x, p.y * p.y, (p.x * 3.0).sin() + 1.0);
+
+        for kind in KINDS {
+            let tiled = render(kind, 11, 9, 3, gradient).to_image();
+            let whole = render(kind, 11, 9, 16, gradient).to_image();
+
+            for y in 0..9 {
+                for x in 0..11 {
+                    assert_close(tiled.get_pixel(x, y), whole.get_pixel(x, y), kind);
+                }
+            }
+        }
+    }
+}
@@ -2,7 +2,7 @@ use crate::color::*;
 use crate::error::*;
 use crate::math::*;
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufReader, BufWriter, Read};
 use std::path::Path;
 use std::cell::UnsafeCell;
 use std::alloc::handle_alloc_error;
@@ -44,7 +44,7 @@ impl RgbaImage {
         let data = unsafe { &*self.data.get() };
 
         Rgba {
-            r: data[offset + 0],
+            r: data[offset],
             g: data[offset + 1],
             b: data[offset + 2],
             a: data[offset + 3],
@@ -56,7 +56,7 @@ impl RgbaImage {
 
         let data = unsafe { &mut *self.data.get() };
 
-        data[offset + 0] = color.r;
+        data[offset] = color.r;
         data[offset + 1] = color.g;
         data[offset + 2] = color.b;
         data[offset + 3] = color.a;
@@ -72,7 +72,7 @@ impl RgbaImage {
 
         let data = &mut *self.data.get();
 
-        data[offset + 0] = color.r;
+        data[offset] = color.r;
         data[offset + 1] = color.g;
         data[offset + 2] = color.b;
         data[offset + 3] = color.a;
@@ -92,6 +92,26 @@ impl RgbaImage {
     }
 }
 
+// Range and resolution of the log2 luminance histogram used for metering
+const HISTOGRAM_MIN_LOG: Float = -16.0;
+const HISTOGRAM_MAX_LOG: Float = 16.0;
+const HISTOGRAM_BINS: usize = 512;
+
+/// Metering method choosing an exposure from the image content
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutoExposure {
+    // Maps the log-average luminance to the key value, 0.18 being middle gray
+    Key(Float),
+    // Maps the luminance at the given percentile to 1
+    Percentile(Float),
+}
+
+impl AutoExposure {
+    pub const DEFAULT_KEY: Float = 0.18;
+    pub const DEFAULT_PERCENTILE: Float = 95.0;
+}
+
+/// Linear floating-point RGB image, rows are stored from top to bottom
 pub struct Image {
     width: usize,
     height: usize,
@@ -129,18 +149,111 @@ impl Image {
         let data = unsafe { &*self.data.get() };
 
         Color3 {
-            r: data[offset + 0],
+            r: data[offset],
             g: data[offset + 1],
             b: data[offset + 2],
         }
     }
 
+    /// Loads an 8-bit PNG, converting colors from sRGB to linear
+    pub fn load_png<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_png(BufReader::new(File::open(path)?))
+    }
+
+    /// Decodes an 8-bit PNG from memory or any other reader, see `load_png`
+    pub fn read_png<R: Read>(reader: R) -> Result<Self> {
+        // The decoder expands palettes and strips 16-bit samples by default
+        let decoder = png::Decoder::new(reader);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data)?;
+
+        let channels = info.color_type.samples();
+
+        let width = info.width as usize;
+        let height = info.height as usize;
+        let mut image = Self::new(width, height);
+
+        for y in 0..height {
+            let row = &data[y * info.line_size..];
+            for x in 0..width {
+                let pixel = &row[x * channels..];
+                let rgba = if channels < 3 {
+                    Rgba::rgb(pixel[0], pixel[0], pixel[0])
+                } else {
+                    Rgba::rgb(pixel[0], pixel[1], pixel[2])
+                };
+                image.set_pixel(x, y, rgba.into());
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Tone-mapped 8-bit copy for display formats
+    pub fn to_rgba(&self, tone_mapping: &ToneMapping) -> RgbaImage {
+        let mut image = RgbaImage::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.set_pixel(x, y, tone_mapping.apply(self.get_pixel(x, y)));
+            }
+        }
+
+        image
+    }
+
+    /// Exposure in stops bringing the image to the level chosen by `method`
+    ///
+    /// Metering works on log2 luminance, pixels darker than the histogram range are
+    /// ignored so black backgrounds don't drag the exposure up and brighter ones count
+    /// as its upper end. An image without any lit pixel gets an exposure of 0.
+    pub fn auto_exposure(&self, method: AutoExposure) -> Float {
+        let bin_width = (HISTOGRAM_MAX_LOG - HISTOGRAM_MIN_LOG) / HISTOGRAM_BINS as Float;
+        let mut bins = vec![0usize; HISTOGRAM_BINS];
+        let mut log_sum = 0.0;
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let log_luminance = self.get_pixel(x, y).luminance().log2();
+                if log_luminance.is_nan() || log_luminance < HISTOGRAM_MIN_LOG {
+                    continue;
+                }
+
+                let log_luminance = min(log_luminance, HISTOGRAM_MAX_LOG);
+                let bin = ((log_luminance - HISTOGRAM_MIN_LOG) / bin_width) as usize;
+                bins[bin.min(HISTOGRAM_BINS - 1)] += 1;
+                log_sum += log_luminance;
+            }
+        }
+
+        let count: usize = bins.iter().sum();
+        if count == 0 {
+            return 0.0;
+        }
+
+        let bin_center = |bin: usize| HISTOGRAM_MIN_LOG + (bin as Float + 0.5) * bin_width;
+
+        match method {
+            AutoExposure::Key(key) => key.log2() - log_sum / count as Float,
+            AutoExposure::Percentile(percentile) => {
+                let target = (clamp(percentile, 0.0, 100.0) / 100.0 * count as Float).ceil().max(1.0) as usize;
+                let mut seen = 0;
+                let bin = bins.iter().position(|&n| {
+                    seen += n;
+                    seen >= target
+                });
+                -bin_center(bin.unwrap_or(HISTOGRAM_BINS - 1))
+            }
+        }
+    }
+
     pub fn get_pixel_spherical(&self, phi: Float, theta: Float) -> Color3 {
         let w = self.width as Float;
         let h = self.height as Float;
 
+        // Theta is the elevation, so the top row is straight up
         let u = 1.0 - (phi + PI) / (2.0 * PI);
-        let v = (theta + PI/2.0) / PI;
+        let v = 0.5 - theta / PI;
 
         let x = modulo(w * u, w) as usize;
         let y = modulo(h * v, h) as usize;
@@ -153,7 +266,7 @@ impl Image {
 
         let data = unsafe { &mut *self.data.get() };
 
-        data[offset + 0] = color.r;
+        data[offset] = color.r;
         data[offset + 1] = color.g;
         data[offset + 2] = color.b;
     }
@@ -168,8 +281,79 @@ impl Image {
 
         let data = &mut *self.data.get();
 
-        data[offset + 0] = color.r;
+        data[offset] = color.r;
         data[offset + 1] = color.g;
         data[offset + 2] = color.b;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sky_orientation() {
+        // Top row is the sky, bottom row the ground
+        let mut image = Image::new(4, 2);
+        for x in 0..4 {
+            image.set_pixel(x, 0, Color3::new(0.0, 0.0, 1.0));
+            image.set_pixel(x, 1, Color3::new(0.0, 1.0, 0.0));
+        }
+
+        assert_eq!(image.get_pixel_spherical(0.0, PI / 4.0), Color3::new(0.0, 0.0, 1.0));
+        assert_eq!(image.get_pixel_spherical(1.0, PI / 3.0), Color3::new(0.0, 0.0, 1.0));
+        assert_eq!(image.get_pixel_spherical(0.0, -PI / 4.0), Color3::new(0.0, 1.0, 0.0));
+        assert_eq!(image.get_pixel_spherical(-2.0, -PI / 3.0), Color3::new(0.0, 1.0, 0.0));
+    }
+
+    /// Image with `count` pixels of each gray level, in order
+    fn gray_levels(levels: &[(Float, usize)]) -> Image {
+        let total: usize = levels.iter().map(|&(_, count)| count).sum();
+        let mut image = Image::new(total, 1);
+        let pixels = levels.iter().flat_map(|&(level, count)| std::iter::repeat_n(level, count));
+        for (x, level) in pixels.enumerate() {
+            image.set_pixel(x, 0, Color3::new(level, level, level));
+        }
+        image
+    }
+
+    #[test]
+    fn key_of_constant_image() {
+        for luminance in [0.003, 0.18, 1.0, 3.7, 2500.0] {
+            let image = gray_levels(&[(luminance, 12)]);
+            for key in [AutoExposure::DEFAULT_KEY, 0.5] {
+                let exposure = image.auto_exposure(AutoExposure::Key(key));
+                assert!((exposure - (key / luminance).log2()).abs() < 1e-9, "{} at key {}: {}", luminance, key, exposure);
+            }
+        }
+    }
+
+    #[test]
+    fn key_ignores_black_pixels() {
+        let lit = gray_levels(&[(0.5, 4)]).auto_exposure(AutoExposure::Key(0.18));
+        let mixed = gray_levels(&[(0.0, 10), (0.5, 4), (1e-9, 3)]).auto_exposure(AutoExposure::Key(0.18));
+        assert!((lit - mixed).abs() < 1e-12);
+
+        assert_eq!(gray_levels(&[(0.0, 8)]).auto_exposure(AutoExposure::Key(0.18)), 0.0);
+        assert_eq!(gray_levels(&[(0.0, 8)]).auto_exposure(AutoExposure::Percentile(50.0)), 0.0);
+    }
+
+    #[test]
+    fn percentile_bin() {
+        // Luminances on bin boundaries, 2^-4 and 2^3 start the bins centered 1/32 stop higher
+        let image = gray_levels(&[(0.0625, 90), (8.0, 10)]);
+
+        assert_eq!(image.auto_exposure(AutoExposure::Percentile(0.0)), 4.0 - 1.0 / 32.0);
+        assert_eq!(image.auto_exposure(AutoExposure::Percentile(90.0)), 4.0 - 1.0 / 32.0);
+        assert_eq!(image.auto_exposure(AutoExposure::Percentile(90.5)), -3.0 - 1.0 / 32.0);
+        assert_eq!(image.auto_exposure(AutoExposure::Percentile(100.0)), -3.0 - 1.0 / 32.0);
+    }
+
+    #[test]
+    fn percentile_clips_bright_pixels() {
+        // Pixels beyond the histogram range land in its last bin
+        let image = gray_levels(&[(1e9, 5)]);
+        let last_bin_center = HISTOGRAM_MAX_LOG - (HISTOGRAM_MAX_LOG - HISTOGRAM_MIN_LOG) / HISTOGRAM_BINS as Float / 2.0;
+        assert_eq!(image.auto_exposure(AutoExposure::Percentile(50.0)), -last_bin_center);
+    }
+}
@@ -6,21 +6,24 @@ pub use primary_ray::*;
 
 use crate::accelerator::Accelerator;
 use crate::math::{Ray, Vector3};
+use crate::random::RandomGenerator;
 use crate::scene::Scene;
 use crate::shape::Shape;
 use crate::color::Color3;
 
 pub struct Output {
     pub color: Color3,
+    // Albedo and shading normal of the first hit
+    pub albedo: Color3,
     pub normal: Vector3,
 }
 
 impl Output {
-    pub fn new(color: Color3, normal: Vector3) -> Self {
-        Self { color, normal }
+    pub fn new(color: Color3, albedo: Color3, normal: Vector3) -> Self {
+        Self { color, albedo, normal }
     }
 }
 
 pub trait Integrator : Send + Sync + Clone {
-    fn integrate<A: Accelerator>(&mut self, scene: &Scene, ray: &Ray, accel: &A) -> Output;
+    fn integrate<A: Accelerator>(&mut self, scene: &Scene, ray: &Ray, accel: &A, rng: &mut RandomGenerator) -> Output;
 }
@@ -1,71 +1,364 @@
 use crate::accelerator::*;
 use crate::color::Color3;
 use crate::integrator::{Integrator, Output};
+use crate::material::Material;
 use crate::math::*;
 use crate::random::*;
 use crate::scene::*;
+use crate::shape::Hit;
 
+// Shadow rays stop this fraction short of the sampled light point, so they don't hit the light itself
+const SHADOW_RAY_SHORTENING: Float = 1e-4;
+
+/// Power heuristic with an exponent of 2 for the strategy of density `f` against one of density `g`
+///
+/// Written in terms of the ratio of the densities, since their squares can overflow or underflow.
+fn power_heuristic(f: Float, g: Float) -> Float {
+    if f > g {
+        let ratio = g / f;
+        1.0 / (1.0 + ratio * ratio)
+    } else if g > 0.0 && g.is_finite() {
+        let ratio = f / g;
+        ratio * ratio / (1.0 + ratio * ratio)
+    } else {
+        0.0
+    }
+}
+
+/// How the ray being traced was generated
+#[derive(Clone, Copy)]
+enum Scattering {
+    // Camera rays and rays leaving vertices where lights weren't sampled, like delta materials
+    Specular,
+    // Sampled from the BRDF at `point` with solid angle density `pdf`, after lights were sampled there too
+    Brdf { point: Vector3, pdf: Float },
+}
+
+/// Unidirectional path tracer with next-event estimation
+///
+/// At every non-specular vertex a point on an emissive shape is sampled and
+/// connected with a shadow ray. Light sampling and the BRDF-sampled continuation
+/// of the path can both find the same emission, so they are combined with
+/// multiple importance sampling.
+///
+/// Paths longer than `roulette_depth` rays are terminated randomly with a probability
+/// depending on their throughput, and the surviving ones are weighted up to keep
+/// the estimate unbiased. `bounces` is only a hard limit on the path length.
 #[derive(Clone)]
 pub struct PathTracer {
     bounces: usize,
-    rng: RandomGenerator,
+    roulette_depth: usize,
 }
 
 impl PathTracer {
-    pub fn new(bounces: usize) -> Self {
-        PathTracer {
-            bounces,
-            rng: RandomGenerator::new(),
-        }
+    pub fn new(bounces: usize, roulette_depth: usize) -> Self {
+        PathTracer { bounces, roulette_depth }
     }
 
-    pub fn trace<A: Accelerator>(&mut self, scene: &Scene, ray: &Ray, accel: &A, bounce: usize) -> Output {
-        if bounce >= self.bounces {
-            return Output::new(Color3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
-        }
-        let hit = accel.trace(ray);
-        let hit = if let Some(hit) = hit {
-            hit
+    fn background(scene: &Scene, ray: &Ray) -> Color3 {
+        if let Some(image) = &scene.sky {
+            let phi = ray.direction.z.atan2(ray.direction.x);
+            let theta = ray.direction.y.atan2((ray.direction.x * ray.direction.x + ray.direction.z * ray.direction.z).sqrt());
+            image.get_pixel_spherical(phi, theta)
         } else {
-            return if let Some(image) = &scene.sky {
-                let phi = ray.direction.z.atan2(ray.direction.x);
-                let theta = ray.direction.y.atan2((ray.direction.x * ray.direction.x + ray.direction.z * ray.direction.z).sqrt());
-                let color = image.get_pixel_spherical(phi, theta);
-                Output::new(color, Vector3::new(0.0, 0.0, 0.0))
-            } else {
-                Output::new(scene.world_color, Vector3::new(0.0, 0.0, 0.0))
-            }
+            scene.world_color
+        }
+    }
+
+    /// Direct light arriving at `hit` from a sampled point on an emissive shape
+    fn sample_direct<A: Accelerator>(scene: &Scene, hit: &Hit, material: &dyn Material, accel: &A, rng: &mut RandomGenerator) -> Color3 {
+        let black = Color3::new(0.0, 0.0, 0.0);
+
+        let (light, selection_pdf) = match scene.sample_light(rng) {
+            Some(light) => light,
+            None => return black,
         };
 
         let point = hit.point();
-        let normal = hit.shape.normal_at(point);
-        let material = scene.get_material(hit.shape.material());
+        let sample = match light.sample(point, rng) {
+            Some(sample) if sample.pdf > 0.0 && sample.pdf.is_finite() => sample,
+            _ => return black,
+        };
 
-        let wo = ray.direction;
-        let wi = material.next_ray_direction(normal, wo, &mut self.rng);
+        let to_light = sample.point - point;
+        let distance = to_light.len();
+        if distance == 0.0 || !distance.is_finite() {
+            return black;
+        }
+        let wi = to_light / distance;
 
-        let next_ray = Ray {
-            origin: point,
-            direction: wi,
-        };
+        let brdf = material.brdf(hit, wi);
+        if brdf == black {
+            return black;
+        }
+
+        if accel.occluded(&hit.spawn_ray(wi), distance * (1.0 - SHADOW_RAY_SHORTENING)) {
+            return black;
+        }
+
+        let light_pdf = sample.pdf * selection_pdf;
+        let weight = power_heuristic(light_pdf, material.pdf(hit, wi));
+
+        let emittance = scene.get_material(light.material()).emittance();
+        brdf * emittance * (wi.dot(hit.oriented_shading_normal()).abs() * weight / light_pdf)
+    }
+
+    /// Radiance arriving along `ray`, and the albedo and shading normal of the first hit
+    pub fn trace<A: Accelerator>(&mut self, scene: &Scene, ray: &Ray, accel: &A, rng: &mut RandomGenerator) -> Output {
+        let black = Color3::new(0.0, 0.0, 0.0);
 
-        let next_color = self.trace(scene, &next_ray, accel, bounce + 1).color;
+        let mut radiance = black;
+        // Product of the BRDF, cosine and pdf terms of the path so far
+        let mut throughput = Color3::new(1.0, 1.0, 1.0);
+        let mut first_albedo = black;
+        let mut first_normal = Vector3::ZERO;
 
-        let color = material.brdf(normal, wi, wo) * wi.dot(normal);
-        let pdf = material.pdf(normal, wi);
+        let mut ray = ray.clone();
+        let mut scattering = Scattering::Specular;
 
-        let color = /*if pdf > 0.0 {*/
-            material.emittance() + color / pdf * next_color;
-        /*} else {
-            material.emittance()
-        };*/
+        for bounce in 0..self.bounces {
+            let hit = match accel.trace(&ray) {
+                Some(hit) => hit,
+                None => {
+                    radiance = radiance + throughput * Self::background(scene, &ray);
+                    break;
+                }
+            };
 
-        Output::new(color, normal)
+            let normal = hit.oriented_shading_normal();
+            let material = scene.get_material(hit.shape.material());
+            if bounce == 0 {
+                first_albedo = material.albedo(&hit);
+                first_normal = normal;
+            }
+
+            // Weighted against the chance of light sampling at the previous vertex finding this emission
+            let emittance = match scattering {
+                Scattering::Specular => material.emittance(),
+                Scattering::Brdf { point, pdf } => {
+                    let light_pdf = scene.light_pdf(hit.shape) * hit.shape.pdf(point, hit.point(), hit.normal);
+                    material.emittance() * power_heuristic(pdf, light_pdf)
+                }
+            };
+            radiance = radiance + throughput * emittance;
+
+            // The light sample makes the path one bounce longer
+            let sample_lights = !material.is_delta() && bounce + 1 < self.bounces;
+            if sample_lights {
+                radiance = radiance + throughput * Self::sample_direct(scene, &hit, material, accel, rng);
+            }
+
+            let wi = material.next_ray_direction(&hit, rng);
+            let pdf = material.pdf(&hit, wi);
+            if !(pdf > 0.0 && pdf.is_finite()) {
+                break;
+            }
+
+            // Transmitted directions are below the surface, hence the absolute cosine
+            throughput = throughput * material.brdf(&hit, wi) * (wi.dot(normal).abs() / pdf);
+            if !throughput.is_finite() || throughput == black {
+                break;
+            }
+
+            // Dim paths likely end here, survivors are scaled back up to a throughput of 1 at most
+            if bounce + 1 >= self.roulette_depth {
+                let survival_probability = min(throughput.max_component(), 1.0);
+                if rng.unit() >= survival_probability {
+                    break;
+                }
+                throughput = throughput / survival_probability;
+            }
+
+            scattering = if sample_lights {
+                Scattering::Brdf { point: hit.point(), pdf }
+            } else {
+                Scattering::Specular
+            };
+            ray = hit.spawn_ray(wi);
+        }
+
+        Output::new(radiance, first_albedo, first_normal)
     }
 }
 
 impl Integrator for PathTracer {
-    fn integrate<A: Accelerator>(&mut self, scene: &Scene, ray: &Ray, accel: &A) -> Output {
-        self.trace(scene, ray, accel, 0)
+    fn integrate<A: Accelerator>(&mut self, scene: &Scene, ray: &Ray, accel: &A, rng: &mut RandomGenerator) -> Output {
+        self.trace(scene, ray, accel, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerator::ShapeVec;
+    use crate::material::{Lambertian, LightEmitter};
+    use crate::shape::Sphere;
+    use crate::shape::plane::Plane;
+
+    /// Perfect mirror, the simplest delta material
+    struct Mirror;
+
+    impl Material for Mirror {
+        fn albedo(&self, _hit: &Hit) -> Color3 {
+            Color3::new(1.0, 1.0, 1.0)
+        }
+
+        fn next_ray_direction(&self, hit: &Hit, _rng: &mut RandomGenerator) -> Vector3 {
+            hit.ray.direction.reflect(hit.oriented_shading_normal())
+        }
+
+        // Nonzero for any direction above the surface, so light sampling here would add light
+        fn brdf(&self, hit: &Hit, wi: Vector3) -> Color3 {
+            let cos = hit.oriented_shading_normal().dot(wi);
+            if cos > 0.0 {
+                Color3::new(1.0, 1.0, 1.0) / cos
+            } else {
+                Color3::new(0.0, 0.0, 0.0)
+            }
+        }
+
+        fn pdf(&self, _hit: &Hit, _wi: Vector3) -> Float {
+            1.0
+        }
+
+        fn emittance(&self) -> Color3 {
+            Color3::new(0.0, 0.0, 0.0)
+        }
+
+        fn is_delta(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn power_heuristic_edge_cases() {
+        assert_eq!(power_heuristic(1.0, 1.0), 0.5);
+        assert!((power_heuristic(3.0, 1.0) - 0.9).abs() < 1e-12);
+        assert!((power_heuristic(1.0, 3.0) - 0.1).abs() < 1e-12);
+
+        // A strategy that can't produce the sample gets no weight, the other one all of it
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+        assert_eq!(power_heuristic(2.0, 0.0), 1.0);
+        assert_eq!(power_heuristic(0.0, 2.0), 0.0);
+        assert_eq!(power_heuristic(INFINITY, 2.0), 1.0);
+        assert_eq!(power_heuristic(2.0, INFINITY), 0.0);
+
+        // Squaring these would overflow or underflow
+        for scale in [1e-200, 1e200] {
+            assert_eq!(power_heuristic(scale, scale), 0.5);
+            assert!((power_heuristic(3.0 * scale, scale) - 0.9).abs() < 1e-12);
+        }
+
+        for (f, g) in [(0.3, 7.0), (1e-3, 1e3), (5.0, 0.25)] {
+            assert!((power_heuristic(f, g) + power_heuristic(g, f) - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn delta_materials_skip_light_sampling() {
+        let mut scene = Scene::new();
+        let mirror = scene.add_material(Mirror);
+        let light = scene.add_material(LightEmitter { color: Color3::new(3.0, 3.0, 3.0) });
+        scene.add_shape(Plane { point: Vector3::ZERO, normal: Vector3::Y, material: mirror });
+        scene.add_shape(Sphere { center: Vector3::new(2.0, 2.0, 0.0), radius: 0.5, material: light });
+
+        let accel = ShapeVec::new(&scene);
+        let mut tracer = PathTracer::new(5, 5);
+        let mut rng = RandomGenerator::with_seed(7);
+
+        for _ in 0..100 {
+            // Reflected into the light, whose emission counts in full without a light sample to weigh against
+            let ray = Ray::new(Vector3::new(-2.0, 2.0, 0.0), Vector3::new(1.0, -1.0, 0.0).normalize());
+            let color = tracer.trace(&scene, &ray, &accel, &mut rng).color;
+            assert!((color.r - 3.0).abs() < 1e-9 && color.r == color.g && color.g == color.b, "{:?}", color);
+
+            // Reflected away from the light, which a light sample at the mirror would still reach
+            let ray = Ray::new(Vector3::new(2.0, 1.0, 0.0), Vector3::new(-1.0, -1.0, 0.0).normalize());
+            assert_eq!(tracer.trace(&scene, &ray, &accel, &mut rng).color, Color3::new(0.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn direct_lighting() {
+        // Diffuse floor below a sphere light, which covers a projected solid angle of pi (r / d)^2
+        let mut scene = Scene::new();
+        let floor = scene.add_material(Lambertian { color: Color3::new(0.5, 0.5, 0.5) });
+        let light = scene.add_material(LightEmitter { color: Color3::new(4.0, 4.0, 4.0) });
+        scene.add_shape(Plane { point: Vector3::ZERO, normal: Vector3::Y, material: floor });
+        scene.add_shape(Sphere { center: Vector3::new(0.0, 2.0, 0.0), radius: 0.5, material: light });
+        let expected = 0.5 * 4.0 * (0.5 / 2.0) * (0.5 / 2.0);
+
+        let accel = ShapeVec::new(&scene);
+        // Two rays, so only light sampling and the BRDF ray at the floor can find the light
+        let mut tracer = PathTracer::new(2, 2);
+        let mut rng = RandomGenerator::with_seed(11);
+        let ray = Ray::new(Vector3::new(0.0, 1.0, -1.0), Vector3::new(0.0, -1.0, 1.0).normalize());
+
+        let count = 50_000;
+        let sum: Float = (0..count).map(|_| tracer.trace(&scene, &ray, &accel, &mut rng).color.g).sum();
+        let estimate = sum / count as Float;
+        assert!((estimate / expected - 1.0).abs() < 0.02, "{} != {}", estimate, expected);
+    }
+
+    /// Diffuse surface that also emits light
+    struct Glowing {
+        diffuse: Lambertian,
+        emittance: Color3,
+    }
+
+    impl Material for Glowing {
+        fn albedo(&self, hit: &Hit) -> Color3 {
+            self.diffuse.albedo(hit)
+        }
+
+        fn next_ray_direction(&self, hit: &Hit, rng: &mut RandomGenerator) -> Vector3 {
+            self.diffuse.next_ray_direction(hit, rng)
+        }
+
+        fn brdf(&self, hit: &Hit, wi: Vector3) -> Color3 {
+            self.diffuse.brdf(hit, wi)
+        }
+
+        fn pdf(&self, hit: &Hit, wi: Vector3) -> Float {
+            self.diffuse.pdf(hit, wi)
+        }
+
+        fn emittance(&self) -> Color3 {
+            self.emittance
+        }
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        // Inside a closed sphere emitting 1 with albedo a, the radiance is the sum of a^n, 1 / (1 - a)
+        let color = Color3::new(0.75, 0.5, 0.25);
+        let mut scene = Scene::new();
+        let material = scene.add_material(Glowing {
+            diffuse: Lambertian { color },
+            emittance: Color3::new(1.0, 1.0, 1.0),
+        });
+        scene.add_shape(Sphere { center: Vector3::ZERO, radius: 1.0, material });
+        let expected = Color3::new(1.0 / (1.0 - color.r), 1.0 / (1.0 - color.g), 1.0 / (1.0 - color.b));
+
+        let accel = ShapeVec::new(&scene);
+        let ray = Ray::new(Vector3::new(0.2, -0.1, 0.3), Vector3::new(1.0, 2.0, 3.0).normalize());
+        let count = 20_000;
+
+        // The bounce limit only cuts off a^64 of the radiance, roulette decides the path length
+        for roulette_depth in [1, 3, 64] {
+            let mut tracer = PathTracer::new(64, roulette_depth);
+            let mut rng = RandomGenerator::with_seed(13);
+
+            let mut sum = Color3::new(0.0, 0.0, 0.0);
+            for _ in 0..count {
+                let color = tracer.trace(&scene, &ray, &accel, &mut rng).color;
+                assert!(color.is_finite());
+                sum = sum + color;
+            }
+
+            let estimate = sum / count as Float;
+            let error = max(max((estimate.r / expected.r - 1.0).abs(), (estimate.g / expected.g - 1.0).abs()), (estimate.b / expected.b - 1.0).abs());
+            assert!(error < 0.03, "depth {}: {:?} != {:?}", roulette_depth, estimate, expected);
+        }
     }
 }
@@ -2,6 +2,7 @@ use crate::accelerator::Accelerator;
 use crate::color::Color3;
 use crate::integrator::{Integrator, Output};
 use crate::math::*;
+use crate::random::RandomGenerator;
 use crate::scene::Scene;
 use crate::shape::Shape;
 
@@ -15,17 +16,17 @@ impl PrimaryRayIntegrator {
 }
 
 impl Integrator for PrimaryRayIntegrator {
-    fn integrate<A: Accelerator>(&mut self, scene: &Scene, ray: &Ray, accel: &A) -> Output {
+    fn integrate<A: Accelerator>(&mut self, scene: &Scene, ray: &Ray, accel: &A, _rng: &mut RandomGenerator) -> Output {
         let hit = accel.trace(ray);
         let hit = if let Some(hit) = hit {
             hit
         } else {
-            return Output::new(scene.world_color, Vector3::new(-1.0, -1.0, -1.0));
+            return Output::new(scene.world_color, scene.world_color, Vector3::new(-1.0, -1.0, -1.0));
         };
 
-        let albedo = scene.get_material(hit.shape.material()).albedo();
-        let normal = hit.shape.normal_at(hit.point());
+        let albedo = scene.get_material(hit.shape.material()).albedo(&hit);
+        let normal = hit.shading_normal;
 
-        Output::new(albedo, normal)
+        Output::new(albedo, albedo, normal)
     }
 }
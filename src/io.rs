@@ -1,3 +1,19 @@
+pub mod exr;
+pub mod gltf;
 pub mod hdr;
+pub mod json;
+pub mod obj;
+pub mod pbrt;
+pub mod pfm;
+pub mod ply;
+pub mod scene_file;
 
+pub use self::exr::*;
+pub use self::gltf::*;
 pub use self::hdr::*;
+pub use self::json::*;
+pub use self::obj::*;
+pub use self::pbrt::*;
+pub use self::pfm::*;
+pub use self::ply::*;
+pub use self::scene_file::*;
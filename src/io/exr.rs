@@ -0,0 +1,578 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use crate::error::*;
+use crate::image::Image;
+use crate::math::Float;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: u32 = 20000630;
+const VERSION: u32 = 2;
+// Version flag required when attribute or channel names exceed 31 bytes
+const LONG_NAMES: u32 = 0x400;
+
+// Shortest run worth encoding as a repeated byte, and the longest run of any kind
+const MIN_RUN_LENGTH: usize = 3;
+const MAX_RUN_LENGTH: usize = 127;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+impl ExrPixelType {
+    fn id(self) -> i32 {
+        match self {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ExrPixelType::Half => 2,
+            ExrPixelType::Float => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrCompression {
+    None,
+    Rle,
+}
+
+fn exr_error(message: impl Into<String>) -> Error {
+    Error::ExrEncodingError(message.into())
+}
+
+struct Channel {
+    name: String,
+    values: Vec<f32>,
+}
+
+/// Single-part scanline OpenEXR image
+///
+/// Channels are stored with the same pixel type, layers are groups of channels
+/// sharing a `layer.` name prefix as understood by compositing software.
+pub struct Exr {
+    width: usize,
+    height: usize,
+    pixel_type: ExrPixelType,
+    compression: ExrCompression,
+    channels: Vec<Channel>,
+}
+
+impl Exr {
+    pub fn new(width: usize, height: usize, pixel_type: ExrPixelType, compression: ExrCompression) -> Self {
+        Self {
+            width,
+            height,
+            pixel_type,
+            compression,
+            channels: Vec::new(),
+        }
+    }
+
+    /// Adds the `R`, `G` and `B` channels of an image, prefixed by `layer.` unless `layer` is empty
+    pub fn add_layer(&mut self, layer: &str, image: &Image) -> Result<()> {
+        if image.width() != self.width || image.height() != self.height {
+            return Err(exr_error(format!(
+                "layer '{}' is {}x{}, expected {}x{}",
+                layer, image.width(), image.height(), self.width, self.height,
+            )));
+        }
+
+        let prefix = if layer.is_empty() { String::new() } else { format!("{}.", layer) };
+        let pixels: Vec<_> = (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| image.get_pixel(x, y))
+            .collect();
+
+        self.add_channel(&format!("{}R", prefix), pixels.iter().map(|color| color.r).collect())?;
+        self.add_channel(&format!("{}G", prefix), pixels.iter().map(|color| color.g).collect())?;
+        self.add_channel(&format!("{}B", prefix), pixels.iter().map(|color| color.b).collect())
+    }
+
+    /// Adds a single channel such as `A`, values are in scanline order
+    pub fn add_channel(&mut self, name: &str, values: Vec<Float>) -> Result<()> {
+        if name.is_empty() || name.contains('\0') {
+            return Err(exr_error(format!("invalid channel name '{}'", name)));
+        }
+        if self.channels.iter().any(|channel| channel.name == name) {
+            return Err(exr_error(format!("duplicate channel '{}'", name)));
+        }
+        if values.len() != self.width * self.height {
+            return Err(exr_error(format!("channel '{}' has {} values, expected {}", name, values.len(), self.width * self.height)));
+        }
+
+        self.channels.push(Channel {
+            name: name.to_string(),
+            values: values.into_iter().map(|value| value as f32).collect(),
+        });
+
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        if self.channels.is_empty() {
+            return Err(exr_error("image has no channels"));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(exr_error("image is empty"));
+        }
+
+        // Readers expect channels in alphabetical order, in the header and in the pixel data
+        let mut channels: Vec<&Channel> = self.channels.iter().collect();
+        channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let header = self.header(&channels);
+
+        // Every scanline is a separate chunk, preceded by a table of their file offsets
+        let chunks: Vec<Vec<u8>> = (0..self.height).map(|y| self.scanline(&channels, y)).collect();
+
+        let mut offset = (8 + header.len() + 8 * self.height) as u64;
+        let long_names = channels.iter().any(|channel| channel.name.len() > 31);
+
+        writer.write_u32::<LittleEndian>(MAGIC)?;
+        writer.write_u32::<LittleEndian>(if long_names { VERSION | LONG_NAMES } else { VERSION })?;
+        writer.write_all(&header)?;
+
+        for chunk in &chunks {
+            writer.write_u64::<LittleEndian>(offset)?;
+            offset += (8 + chunk.len()) as u64;
+        }
+
+        for (y, chunk) in chunks.iter().enumerate() {
+            writer.write_i32::<LittleEndian>(y as i32)?;
+            writer.write_i32::<LittleEndian>(chunk.len() as i32)?;
+            writer.write_all(chunk)?;
+        }
+
+        Ok(())
+    }
+
+    fn header(&self, channels: &[&Channel]) -> Vec<u8> {
+        let mut header = Vec::new();
+
+        let mut list = Vec::new();
+        for channel in channels {
+            list.extend_from_slice(channel.name.as_bytes());
+            list.push(0);
+            list.extend_from_slice(&self.pixel_type.id().to_le_bytes());
+            // pLinear and reserved bytes, then x and y sampling
+            list.extend_from_slice(&[0, 0, 0, 0]);
+            list.extend_from_slice(&1i32.to_le_bytes());
+            list.extend_from_slice(&1i32.to_le_bytes());
+        }
+        list.push(0);
+
+        let compression = match self.compression {
+            ExrCompression::None => 0u8,
+            ExrCompression::Rle => 1u8,
+        };
+
+        let mut window = Vec::new();
+        for value in [0, 0, self.width as i32 - 1, self.height as i32 - 1] {
+            window.extend_from_slice(&value.to_le_bytes());
+        }
+
+        attribute(&mut header, "channels", "chlist", &list);
+        attribute(&mut header, "compression", "compression", &[compression]);
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+        header.push(0);
+
+        header
+    }
+
+    /// Pixel data of one scanline, channel after channel
+    fn scanline(&self, channels: &[&Channel], y: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(channels.len() * self.width * self.pixel_type.size());
+
+        for channel in channels {
+            for &value in &channel.values[y * self.width..(y + 1) * self.width] {
+                match self.pixel_type {
+                    ExrPixelType::Half => data.extend_from_slice(&f32_to_half(value).to_le_bytes()),
+                    ExrPixelType::Float => data.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+
+        match self.compression {
+            ExrCompression::None => data,
+            ExrCompression::Rle => {
+                // Chunks that do not shrink are stored uncompressed
+                let compressed = rle_compress(&data);
+                if compressed.len() < data.len() { compressed } else { data }
+            }
+        }
+    }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Converts to IEEE 754 half precision, rounding to nearest even
+fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    // Infinity and NaN, keeping NaNs quiet
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    let (half, remainder, halfway) = if exponent <= 0 {
+        // Subnormal half, including the implicit leading bit of the float
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (mantissa >> shift, mantissa & ((1 << shift) - 1), 1 << (shift - 1))
+    } else {
+        (((exponent as u32) << 10) | (mantissa >> 13), mantissa & 0x1fff, 0x1000)
+    };
+
+    // A carry out of the mantissa correctly bumps the exponent, up to infinity
+    let round_up = remainder > halfway || remainder == halfway && half & 1 == 1;
+    sign | (half + round_up as u32) as u16
+}
+
+/// OpenEXR RLE: bytes are split into even and odd halves, delta encoded and run-length encoded
+fn rle_compress(data: &[u8]) -> Vec<u8> {
+    let half = data.len().div_ceil(2);
+    let mut bytes = vec![0; data.len()];
+    for (i, &byte) in data.iter().enumerate() {
+        let index = if i % 2 == 0 { i / 2 } else { half + i / 2 };
+        bytes[index] = byte;
+    }
+
+    let mut previous = bytes[0];
+    for byte in bytes.iter_mut().skip(1) {
+        let current = *byte;
+        *byte = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut start = 0;
+    while start < bytes.len() {
+        let mut end = start + 1;
+        while end < bytes.len() && bytes[end] == bytes[start] && end - start <= MAX_RUN_LENGTH {
+            end += 1;
+        }
+
+        if end - start >= MIN_RUN_LENGTH {
+            // Repeated byte: count - 1 followed by the value
+            output.push((end - start - 1) as u8);
+            output.push(bytes[start]);
+        } else {
+            // Literal bytes up to the next run of three: negated count followed by the values
+            end = start;
+            while end < bytes.len()
+                && end - start < MAX_RUN_LENGTH
+                && !(end + 2 < bytes.len() && bytes[end] == bytes[end + 1] && bytes[end] == bytes[end + 2])
+            {
+                end += 1;
+            }
+
+            output.push((-((end - start) as i32)) as u8);
+            output.extend_from_slice(&bytes[start..end]);
+        }
+
+        start = end;
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color3;
+    use byteorder::{ByteOrder, ReadBytesExt};
+
+    /// Inverse of `rle_compress`, following the OpenEXR reference decoder
+    fn rle_decompress(data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut input = data.iter();
+        while let Some(&count) = input.next() {
+            let count = count as i8;
+            if count < 0 {
+                bytes.extend(input.by_ref().take(-(count as i32) as usize));
+            } else {
+                let value = *input.next().unwrap();
+                bytes.extend(std::iter::repeat_n(value, count as usize + 1));
+            }
+        }
+
+        for i in 1..bytes.len() {
+            bytes[i] = bytes[i - 1].wrapping_add(bytes[i]).wrapping_sub(128);
+        }
+
+        let half = bytes.len().div_ceil(2);
+        (0..bytes.len())
+            .map(|i| if i % 2 == 0 { bytes[i / 2] } else { bytes[half + i / 2] })
+            .collect()
+    }
+
+    /// Attributes of a header as (name, type, value), stopping at the terminating null byte
+    fn attributes(mut header: &[u8]) -> Vec<(String, String, Vec<u8>)> {
+        let string = |header: &mut &[u8]| {
+            let end = header.iter().position(|&byte| byte == 0).unwrap();
+            let string = String::from_utf8(header[..end].to_vec()).unwrap();
+            *header = &header[end + 1..];
+            string
+        };
+
+        let mut attributes = Vec::new();
+        while header[0] != 0 {
+            let name = string(&mut header);
+            let kind = string(&mut header);
+            let size = header.read_i32::<LittleEndian>().unwrap() as usize;
+            attributes.push((name, kind, header[..size].to_vec()));
+            header = &header[size..];
+        }
+
+        attributes
+    }
+
+    fn image(width: usize, height: usize, color: impl Fn(usize, usize) -> Color3) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, color(x, y));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn half_normal_values() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        assert_eq!(f32_to_half(0.5), 0x3800);
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        // Smallest normal
+        assert_eq!(f32_to_half(2f32.powi(-14)), 0x0400);
+    }
+
+    #[test]
+    fn half_rounds_to_nearest_even() {
+        let ulp = 2f32.powi(-10);
+        assert_eq!(f32_to_half(1.0 + ulp * 0.5), 0x3c00);
+        assert_eq!(f32_to_half(1.0 + ulp * 0.51), 0x3c01);
+        assert_eq!(f32_to_half(1.0 + ulp * 1.5), 0x3c02);
+        assert_eq!(f32_to_half(1.0 + ulp * 2.5), 0x3c02);
+        assert_eq!(f32_to_half(-(1.0 + ulp * 1.5)), 0xbc02);
+
+        // Rounding up the mantissa carries into the exponent
+        assert_eq!(f32_to_half(2.0 - ulp * 0.25), 0x4000);
+    }
+
+    #[test]
+    fn half_subnormals() {
+        let smallest = 2f32.powi(-24);
+        assert_eq!(f32_to_half(smallest), 0x0001);
+        assert_eq!(f32_to_half(smallest * 1023.0), 0x03ff);
+        assert_eq!(f32_to_half(-smallest * 3.0), 0x8003);
+
+        // Ties go to the even neighbor, including zero and the smallest normal
+        assert_eq!(f32_to_half(smallest * 0.5), 0x0000);
+        assert_eq!(f32_to_half(smallest * 0.75), 0x0001);
+        assert_eq!(f32_to_half(smallest * 1.5), 0x0002);
+        assert_eq!(f32_to_half(smallest * 2.5), 0x0002);
+        assert_eq!(f32_to_half(smallest * 1023.5), 0x0400);
+
+        assert_eq!(f32_to_half(smallest * 0.25), 0x0000);
+        assert_eq!(f32_to_half(-1e-30), 0x8000);
+        assert_eq!(f32_to_half(f32::MIN_POSITIVE), 0x0000);
+    }
+
+    #[test]
+    fn half_overflow_and_nan() {
+        assert_eq!(f32_to_half(65519.0), 0x7bff);
+        assert_eq!(f32_to_half(65520.0), 0x7c00);
+        assert_eq!(f32_to_half(1e10), 0x7c00);
+        assert_eq!(f32_to_half(-1e10), 0xfc00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_half(f32::MAX), 0x7c00);
+
+        let nan = f32_to_half(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+        assert_ne!(f32_to_half(f32::from_bits(0x7f80_0001)) & 0x03ff, 0);
+    }
+
+    #[test]
+    fn rle_round_trip() {
+        let mut state = 12345u32;
+        let noise: Vec<u8> = (0..1001).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+
+        let mut mixed = vec![7; 300];
+        mixed.extend_from_slice(&noise[..130]);
+        mixed.extend_from_slice(&[1, 1, 2, 2, 2, 3]);
+        mixed.extend(std::iter::repeat_n(0, 129));
+
+        let inputs: [&[u8]; 7] = [&[42], &[1, 2], &[0; 256], &[9; 3], &noise, &mixed, &[0, 1, 0, 1, 0, 1, 0]];
+        for input in inputs {
+            let compressed = rle_compress(input);
+            assert_eq!(rle_decompress(&compressed), input);
+        }
+
+        // Constant data shrinks to a few runs
+        assert!(rle_compress(&[0; 256]).len() <= 8);
+    }
+
+    #[test]
+    fn header_and_offsets() {
+        let (width, height) = (2, 3);
+        let mut exr = Exr::new(width, height, ExrPixelType::Half, ExrCompression::None);
+        exr.add_layer("", &image(width, height, |x, y| Color3::new(x as Float, y as Float, 0.5))).unwrap();
+        exr.add_channel("A", vec![1.0; width * height]).unwrap();
+
+        let mut data = Vec::new();
+        exr.write(&mut data).unwrap();
+
+        assert_eq!(LittleEndian::read_u32(&data[0..4]), MAGIC);
+        assert_eq!(LittleEndian::read_u32(&data[4..8]), VERSION);
+
+        let attributes = attributes(&data[8..]);
+        let names: Vec<&str> = attributes.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(names, [
+            "channels", "compression", "dataWindow", "displayWindow",
+            "lineOrder", "pixelAspectRatio", "screenWindowCenter", "screenWindowWidth",
+        ]);
+
+        // Channels are sorted, each entry is the name, pixel type, pLinear and sampling
+        let (_, kind, list) = &attributes[0];
+        assert_eq!(kind, "chlist");
+        assert_eq!(list.len(), 4 * 18 + 1);
+        let channel_names: Vec<u8> = list.chunks(18).filter(|entry| entry.len() == 18).map(|entry| entry[0]).collect();
+        assert_eq!(channel_names, b"ABGR");
+        assert_eq!(LittleEndian::read_i32(&list[2..6]), 1);
+
+        assert_eq!(attributes[1].2, [0]);
+        let window: Vec<i32> = attributes[2].2.chunks(4).map(LittleEndian::read_i32).collect();
+        assert_eq!(window, [0, 0, 1, 2]);
+
+        // The offset table follows the header and points at consecutive scanline chunks
+        let header_length: usize = attributes.iter()
+            .map(|(name, kind, value)| name.len() + kind.len() + 6 + value.len())
+            .sum::<usize>() + 1;
+        let table = 8 + header_length;
+        let chunk_length = 4 * width * 2;
+
+        for y in 0..height {
+            let offset = LittleEndian::read_u64(&data[table + 8 * y..]) as usize;
+            assert_eq!(offset, table + 8 * height + y * (8 + chunk_length));
+            assert_eq!(LittleEndian::read_i32(&data[offset..]), y as i32);
+            assert_eq!(LittleEndian::read_i32(&data[offset + 4..]), chunk_length as i32);
+
+            // R follows A, B and G, one half per pixel
+            let r = offset + 8 + 3 * width * 2;
+            assert_eq!(LittleEndian::read_u16(&data[r + 2..]), f32_to_half(1.0));
+            assert_eq!(LittleEndian::read_u16(&data[r - width * 2 + 2..]), f32_to_half(y as f32));
+        }
+        assert_eq!(data.len(), table + 8 * height + height * (8 + chunk_length));
+    }
+
+    #[test]
+    fn compressed_scanlines() {
+        let (width, height) = (64, 2);
+        let mut exr = Exr::new(width, height, ExrPixelType::Float, ExrCompression::Rle);
+        exr.add_layer("", &image(width, height, |x, y| Color3::new(1.0, (x / 16) as Float, y as Float))).unwrap();
+
+        let mut data = Vec::new();
+        exr.write(&mut data).unwrap();
+
+        let table = 8 + attributes(&data[8..]).iter()
+            .map(|(name, kind, value)| name.len() + kind.len() + 6 + value.len())
+            .sum::<usize>() + 1;
+
+        for y in 0..height {
+            let offset = LittleEndian::read_u64(&data[table + 8 * y..]) as usize;
+            let length = LittleEndian::read_i32(&data[offset + 4..]) as usize;
+            assert!(length < 3 * width * 4);
+
+            let scanline = rle_decompress(&data[offset + 8..offset + 8 + length]);
+            let values: Vec<f32> = scanline.chunks(4).map(LittleEndian::read_f32).collect();
+            assert_eq!(values.len(), 3 * width);
+            // Channels B, G, R
+            assert_eq!(values[0], y as f32);
+            assert_eq!(values[width + 47], 2.0);
+            assert_eq!(values[2 * width + 5], 1.0);
+        }
+    }
+
+    #[test]
+    fn named_layers() {
+        let (width, height) = (3, 2);
+        let mut exr = Exr::new(width, height, ExrPixelType::Float, ExrCompression::None);
+        let gray = image(width, height, |_, _| Color3::new(0.5, 0.5, 0.5));
+        exr.add_layer("", &gray).unwrap();
+        exr.add_layer("albedo", &gray).unwrap();
+        exr.add_layer("normal", &gray).unwrap();
+
+        assert!(exr.add_layer("albedo", &gray).is_err());
+        assert!(exr.add_layer("small", &image(2, 2, |_, _| Color3::new(0.0, 0.0, 0.0))).is_err());
+        assert!(exr.add_channel("A", vec![1.0; 5]).is_err());
+
+        let mut data = Vec::new();
+        exr.write(&mut data).unwrap();
+        let (_, _, list) = &attributes(&data[8..])[0];
+
+        let mut names = Vec::new();
+        let mut list = &list[..];
+        while list[0] != 0 {
+            let end = list.iter().position(|&byte| byte == 0).unwrap();
+            names.push(String::from_utf8(list[..end].to_vec()).unwrap());
+            list = &list[end + 1 + 16..];
+        }
+        assert_eq!(names, ["B", "G", "R", "albedo.B", "albedo.G", "albedo.R", "normal.B", "normal.G", "normal.R"]);
+    }
+
+    #[test]
+    fn long_channel_names() {
+        let mut exr = Exr::new(1, 1, ExrPixelType::Half, ExrCompression::Rle);
+        exr.add_channel(&"x".repeat(32), vec![0.0]).unwrap();
+
+        let mut data = Vec::new();
+        exr.write(&mut data).unwrap();
+        assert_eq!(LittleEndian::read_u32(&data[4..8]), VERSION | LONG_NAMES);
+    }
+}
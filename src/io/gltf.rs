@@ -0,0 +1,746 @@
+use byteorder::{ByteOrder, LittleEndian};
+use crate::camera::Camera;
+use crate::color::Color3;
+use crate::error::*;
+use crate::image::Image;
+use crate::io::json::Json;
+use crate::material::*;
+use crate::math::*;
+use crate::scene::*;
+use crate::shape::{MeshData, TriangleMesh};
+use crate::texture::Texture;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4E4F_534A;
+const GLB_CHUNK_BIN: u32 = 0x004E_4942;
+
+const TRIANGLES: usize = 4;
+// Accessors are read into memory as floats, larger ones are rejected
+const MAX_ACCESSOR_VALUES: usize = 1 << 28;
+
+fn gltf_error(message: impl Into<String>) -> Error {
+    Error::GltfError(message.into())
+}
+
+/// Array member of an object, empty when missing
+fn array<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    json.get(key).and_then(Json::as_array).unwrap_or(&[])
+}
+
+fn index(json: &Json, key: &str) -> Option<usize> {
+    json.get(key).and_then(Json::as_usize)
+}
+
+fn number(json: &Json, key: &str, default: Float) -> Float {
+    json.get(key).and_then(Json::as_f64).unwrap_or(default)
+}
+
+fn numbers<const N: usize>(json: &Json, key: &str, default: [Float; N]) -> Result<[Float; N]> {
+    let values = match json.get(key) {
+        Some(values) => values.as_array().ok_or_else(|| gltf_error(format!("'{}' is not an array", key)))?,
+        None => return Ok(default),
+    };
+
+    if values.len() != N {
+        return Err(gltf_error(format!("'{}' must have {} elements", key, N)));
+    }
+
+    let mut result = default;
+    for (value, json) in result.iter_mut().zip(values) {
+        *value = json.as_f64().ok_or_else(|| gltf_error(format!("'{}' must contain numbers", key)))?;
+    }
+
+    Ok(result)
+}
+
+/// Element of a top-level array such as `meshes` or `accessors`
+fn element<'a>(root: &'a Json, key: &str, index: usize) -> Result<&'a Json> {
+    array(root, key)
+        .get(index)
+        .ok_or_else(|| gltf_error(format!("{} index {} out of range", key, index)))
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut accumulator = 0u32;
+    let mut bits = 0;
+
+    for c in text.bytes().take_while(|&c| c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(gltf_error("invalid base64 data")),
+        };
+
+        accumulator = (accumulator << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((accumulator >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Decodes `%XX` escapes in relative URIs
+fn decode_uri(uri: &str) -> String {
+    let mut bytes = Vec::with_capacity(uri.len());
+    let mut input = uri.bytes();
+
+    while let Some(c) = input.next() {
+        if c == b'%' {
+            let hex: Vec<u8> = input.clone().take(2).collect();
+            if hex.len() == 2 && hex.iter().all(u8::is_ascii_hexdigit) {
+                let digit = |c: u8| (c as char).to_digit(16).unwrap_or(0) as u8;
+                bytes.push(digit(hex[0]) * 16 + digit(hex[1]));
+                input.nth(1);
+                continue;
+            }
+        }
+        bytes.push(c);
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Splits a binary glTF file into its JSON and BIN chunks
+fn parse_glb(bytes: &[u8]) -> Result<(Json, Option<Vec<u8>>)> {
+    if bytes.len() < 12 || LittleEndian::read_u32(&bytes[4..8]) != 2 {
+        return Err(gltf_error("unsupported GLB header"));
+    }
+
+    let mut json = None;
+    let mut binary = None;
+    let mut offset = 12;
+
+    while offset + 8 <= bytes.len() {
+        let length = LittleEndian::read_u32(&bytes[offset..offset + 4]) as usize;
+        let kind = LittleEndian::read_u32(&bytes[offset + 4..offset + 8]);
+        let data = bytes.get(offset + 8..offset + 8 + length).ok_or_else(|| gltf_error("truncated GLB chunk"))?;
+
+        match kind {
+            GLB_CHUNK_JSON => {
+                let text = std::str::from_utf8(data).map_err(|_| gltf_error("JSON chunk is not UTF-8"))?;
+                json = Some(Json::parse(text)?);
+            }
+            GLB_CHUNK_BIN => binary = Some(data.to_vec()),
+            _ => (),
+        }
+
+        offset += 8 + length;
+    }
+
+    Ok((json.ok_or_else(|| gltf_error("missing JSON chunk"))?, binary))
+}
+
+/// Local transform of a node, either a matrix or translation, rotation and scale
+fn node_transform(node: &Json) -> Result<Matrix4> {
+    if node.get("matrix").is_some() {
+        let values = numbers(node, "matrix", [0.0; 16])?;
+        return Ok(Matrix4::from_column_major(&values));
+    }
+
+    let [tx, ty, tz] = numbers(node, "translation", [0.0, 0.0, 0.0])?;
+    let [x, y, z, w] = numbers(node, "rotation", [0.0, 0.0, 0.0, 1.0])?;
+    let [sx, sy, sz] = numbers(node, "scale", [1.0, 1.0, 1.0])?;
+
+    Ok(Matrix4::translation(Vector3::new(tx, ty, tz))
+        * Matrix4::from_quaternion(x, y, z, w)
+        * Matrix4::scaling(Vector3::new(sx, sy, sz)))
+}
+
+struct Importer<'a> {
+    root: &'a Json,
+    directory: &'a Path,
+    buffers: Vec<Vec<u8>>,
+    // Scene material for each glTF material
+    materials: Vec<MaterialId>,
+    default_material: Option<MaterialId>,
+    // Textures by glTF image index
+    textures: HashMap<usize, Option<Arc<Texture>>>,
+    // Aspect ratio for the scene camera, `None` once a camera is set
+    camera_aspect_ratio: Option<Float>,
+}
+
+impl Importer<'_> {
+    fn load_buffers(&mut self, mut binary: Option<Vec<u8>>) -> Result<()> {
+        for buffer in array(self.root, "buffers") {
+            let data = match buffer.get("uri").and_then(Json::as_str) {
+                Some(uri) if uri.starts_with("data:") => {
+                    let (_, data) = uri.split_once(";base64,").ok_or_else(|| gltf_error("unsupported data URI"))?;
+                    decode_base64(data)?
+                }
+                Some(uri) => std::fs::read(self.directory.join(decode_uri(uri)))?,
+                None => binary.take().ok_or_else(|| gltf_error("buffer without URI and GLB data"))?,
+            };
+
+            let length = index(buffer, "byteLength").unwrap_or(data.len());
+            if data.len() < length {
+                return Err(gltf_error("buffer is shorter than its byteLength"));
+            }
+
+            self.buffers.push(data);
+        }
+
+        Ok(())
+    }
+
+    fn buffer_view(&self, view: usize) -> Result<(&[u8], Option<usize>)> {
+        let view = element(self.root, "bufferViews", view)?;
+        let buffer = index(view, "buffer").ok_or_else(|| gltf_error("bufferView without buffer"))?;
+        let buffer = self.buffers.get(buffer).ok_or_else(|| gltf_error("buffer index out of range"))?;
+
+        let offset = index(view, "byteOffset").unwrap_or(0);
+        let length = index(view, "byteLength").ok_or_else(|| gltf_error("bufferView without byteLength"))?;
+        let data = offset.checked_add(length)
+            .and_then(|end| buffer.get(offset..end))
+            .ok_or_else(|| gltf_error("bufferView out of range"))?;
+
+        Ok((data, index(view, "byteStride")))
+    }
+
+    /// Reads an accessor as floats, returning the values and the number of components per element
+    fn accessor(&self, accessor: usize) -> Result<(Vec<Float>, usize)> {
+        let accessor = element(self.root, "accessors", accessor)?;
+
+        if accessor.get("sparse").is_some() {
+            return Err(gltf_error("sparse accessors are not supported"));
+        }
+
+        let count = index(accessor, "count").ok_or_else(|| gltf_error("accessor without count"))?;
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT4") => 16,
+            _ => return Err(gltf_error("unsupported accessor type")),
+        };
+        let component_type = index(accessor, "componentType").ok_or_else(|| gltf_error("accessor without componentType"))?;
+        let component_size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err(gltf_error(format!("unknown componentType {}", component_type))),
+        };
+        let normalized = accessor.get("normalized").and_then(Json::as_bool).unwrap_or(false);
+
+        let value_count = count.checked_mul(components)
+            .filter(|&value_count| value_count <= MAX_ACCESSOR_VALUES)
+            .ok_or_else(|| gltf_error(format!("accessor with {} elements is too large", count)))?;
+
+        let view = match index(accessor, "bufferView") {
+            Some(view) => view,
+            None => return Ok((vec![0.0; value_count], components)),
+        };
+
+        let (data, stride) = self.buffer_view(view)?;
+        let offset = index(accessor, "byteOffset").unwrap_or(0);
+        let element_size = components * component_size;
+        let stride = stride.unwrap_or(element_size);
+
+        if stride < element_size {
+            return Err(gltf_error(format!("byteStride {} is smaller than the accessor elements", stride)));
+        }
+
+        // Offset of the end of the last element
+        let end = match count.checked_sub(1) {
+            Some(last) => last.checked_mul(stride)
+                .and_then(|start| start.checked_add(offset))
+                .and_then(|start| start.checked_add(element_size)),
+            None => Some(0),
+        };
+        if end.is_none_or(|end| end > data.len()) {
+            return Err(gltf_error("accessor out of range"));
+        }
+
+        let mut values = Vec::with_capacity(value_count);
+        for i in 0..count {
+            for j in 0..components {
+                let bytes = &data[offset + i * stride + j * component_size..];
+                let value = match component_type {
+                    5120 => (bytes[0] as i8) as Float,
+                    5121 => bytes[0] as Float,
+                    5122 => LittleEndian::read_i16(bytes) as Float,
+                    5123 => LittleEndian::read_u16(bytes) as Float,
+                    5125 => LittleEndian::read_u32(bytes) as Float,
+                    _ => LittleEndian::read_f32(bytes) as Float,
+                };
+
+                let value = match (normalized, component_type) {
+                    (true, 5120) => max(value / 127.0, -1.0),
+                    (true, 5121) => value / 255.0,
+                    (true, 5122) => max(value / 32767.0, -1.0),
+                    (true, 5123) => value / 65535.0,
+                    _ => value,
+                };
+
+                values.push(value);
+            }
+        }
+
+        Ok((values, components))
+    }
+
+    /// Loads a PNG image referenced by a texture, other formats are skipped
+    fn texture(&mut self, texture: usize) -> Result<Option<Arc<Texture>>> {
+        let source = index(element(self.root, "textures", texture)?, "source")
+            .ok_or_else(|| gltf_error("texture without source"))?;
+
+        if let Some(texture) = self.textures.get(&source) {
+            return Ok(texture.clone());
+        }
+
+        let image = element(self.root, "images", source)?;
+        let uri = image.get("uri").and_then(Json::as_str);
+        let mime_type = image.get("mimeType").and_then(Json::as_str);
+
+        let texture = match (uri, mime_type) {
+            (Some(uri), _) if uri.starts_with("data:image/png;base64,") => {
+                let data = decode_base64(&uri["data:image/png;base64,".len()..])?;
+                Some(Texture::new(Image::read_png(&data[..])?))
+            }
+            (Some(uri), _) if uri.to_ascii_lowercase().ends_with(".png") => {
+                Some(Texture::load(self.directory.join(decode_uri(uri)))?)
+            }
+            (None, Some("image/png")) => {
+                let view = index(image, "bufferView").ok_or_else(|| gltf_error("image without uri or bufferView"))?;
+                let (data, _) = self.buffer_view(view)?;
+                Some(Texture::new(Image::read_png(data)?))
+            }
+            _ => {
+                eprintln!("gltf: skipping image {}, only PNG textures are supported", source);
+                None
+            }
+        };
+
+        let texture = texture.map(Arc::new);
+        self.textures.insert(source, texture.clone());
+        Ok(texture)
+    }
+
+    fn load_materials(&mut self, scene: &mut Scene) -> Result<()> {
+        for material in array(self.root, "materials") {
+            let default = Json::Object(Vec::new());
+            let pbr = material.get("pbrMetallicRoughness").unwrap_or(&default);
+
+            let [r, g, b, _] = numbers(pbr, "baseColorFactor", [1.0, 1.0, 1.0, 1.0])?;
+            let base_color_map = match pbr.get("baseColorTexture").and_then(|texture| index(texture, "index")) {
+                Some(texture) => self.texture(texture)?,
+                None => None,
+            };
+
+            let [er, eg, eb] = numbers(material, "emissiveFactor", [0.0, 0.0, 0.0])?;
+            let strength = material.get("extensions")
+                .and_then(|extensions| extensions.get("KHR_materials_emissive_strength"))
+                .map_or(1.0, |extension| number(extension, "emissiveStrength", 1.0));
+
+            let id = scene.add_material(MetallicRoughness {
+                base_color: Color3::new(r, g, b),
+                base_color_map,
+                metallic: number(pbr, "metallicFactor", 1.0),
+                roughness: number(pbr, "roughnessFactor", 1.0),
+                emission: Color3::new(er, eg, eb) * strength,
+            });
+            self.materials.push(id);
+        }
+
+        Ok(())
+    }
+
+    fn material(&mut self, primitive: &Json, scene: &mut Scene) -> Result<MaterialId> {
+        if let Some(material) = index(primitive, "material") {
+            return self.materials.get(material).copied().ok_or_else(|| gltf_error("material index out of range"));
+        }
+
+        // Default material from the specification
+        Ok(*self.default_material.get_or_insert_with(|| scene.add_material(MetallicRoughness {
+            base_color: Color3::new(1.0, 1.0, 1.0),
+            base_color_map: None,
+            metallic: 1.0,
+            roughness: 1.0,
+            emission: Color3::new(0.0, 0.0, 0.0),
+        })))
+    }
+
+    fn load_mesh(&mut self, mesh: usize, transform: &Matrix4, scene: &mut Scene) -> Result<()> {
+        let root = self.root;
+        let normal_transform = transform.inverse().unwrap_or(Matrix4::IDENTITY).transpose();
+
+        for primitive in array(element(root, "meshes", mesh)?, "primitives") {
+            if index(primitive, "mode").unwrap_or(TRIANGLES) != TRIANGLES {
+                eprintln!("gltf: skipping primitive of mesh {}, only triangles are supported", mesh);
+                continue;
+            }
+
+            let attributes = primitive.get("attributes").ok_or_else(|| gltf_error("primitive without attributes"))?;
+            let position = index(attributes, "POSITION").ok_or_else(|| gltf_error("primitive without positions"))?;
+
+            let (values, _) = self.accessor(position)?;
+            let positions: Vec<Vector3> = values.chunks_exact(3)
+                .map(|p| transform.transform_point(Vector3::new(p[0], p[1], p[2])))
+                .collect();
+
+            let normals = match index(attributes, "NORMAL") {
+                Some(normal) => self.accessor(normal)?.0
+                    .chunks_exact(3)
+                    .map(|n| normal_transform.transform_vector(Vector3::new(n[0], n[1], n[2])).normalize())
+                    .collect(),
+                None => Vec::new(),
+            };
+
+            // glTF puts the origin of texture space at the top left
+            let uvs = match index(attributes, "TEXCOORD_0") {
+                Some(uv) => {
+                    let (values, components) = self.accessor(uv)?;
+                    values.chunks_exact(components)
+                        .map(|uv| Vector2::new(uv[0], 1.0 - uv[1]))
+                        .collect()
+                }
+                None => Vec::new(),
+            };
+
+            let indices: Vec<u32> = match index(primitive, "indices") {
+                Some(indices) => self.accessor(indices)?.0.iter().map(|&index| index as u32).collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            if indices.iter().any(|&index| index as usize >= positions.len()) {
+                return Err(gltf_error(format!("vertex index out of range in mesh {}", mesh)));
+            }
+            if normals.len() != positions.len() && !normals.is_empty()
+                || uvs.len() != positions.len() && !uvs.is_empty()
+            {
+                return Err(gltf_error(format!("attribute count mismatch in mesh {}", mesh)));
+            }
+
+            let material = self.material(primitive, scene)?;
+            scene.add_mesh(TriangleMesh::new(MeshData {
+                positions,
+                normals,
+                uvs,
+                indices: indices[..indices.len() / 3 * 3].to_vec(),
+                material,
+            })?);
+        }
+
+        Ok(())
+    }
+
+    /// Uses the first perspective camera found as the scene camera
+    fn load_camera(&mut self, camera: usize, transform: &Matrix4, scene: &mut Scene) -> Result<()> {
+        let aspect_ratio = match self.camera_aspect_ratio {
+            Some(aspect_ratio) => aspect_ratio,
+            None => return Ok(()),
+        };
+
+        let camera = element(self.root, "cameras", camera)?;
+        let perspective = match camera.get("perspective") {
+            Some(perspective) => perspective,
+            None => {
+                eprintln!("gltf: skipping camera, only perspective cameras are supported");
+                return Ok(());
+            }
+        };
+
+        // The image resolution decides the aspect ratio, not `aspectRatio`
+        let fov = number(perspective, "yfov", FRAC_PI_2).to_degrees();
+
+        // Cameras look down -Z with +Y up
+        let origin = transform.transform_point(Vector3::ZERO);
+        let forward = transform.transform_vector(Vector3::new(0.0, 0.0, -1.0));
+        let up = transform.transform_vector(Vector3::new(0.0, 1.0, 0.0));
+
+        scene.camera = Camera::look_at(origin, origin + forward, up, fov, aspect_ratio);
+        self.camera_aspect_ratio = None;
+
+        Ok(())
+    }
+
+    fn load_light(&mut self, light: usize, transform: &Matrix4, scene: &mut Scene) -> Result<()> {
+        let light = self.root.get("extensions")
+            .and_then(|extensions| extensions.get("KHR_lights_punctual"))
+            .and_then(|extension| array(extension, "lights").get(light))
+            .ok_or_else(|| gltf_error(format!("light index {} out of range", light)))?;
+
+        let kind = light.get("type").and_then(Json::as_str).unwrap_or("");
+        match kind {
+            "point" => (),
+            "spot" => eprintln!("gltf: spot light cone is ignored, emitting in all directions"),
+            _ => {
+                eprintln!("gltf: skipping {} light, only point and spot lights are supported", kind);
+                return Ok(());
+            }
+        }
+
+        let [r, g, b] = numbers(light, "color", [1.0, 1.0, 1.0])?;
+        let intensity = number(light, "intensity", 1.0);
+        scene.add_point_light(transform.transform_point(Vector3::ZERO), Color3::new(r, g, b) * intensity);
+
+        Ok(())
+    }
+
+    fn load_node(&mut self, node: usize, parent: &Matrix4, scene: &mut Scene, depth: usize) -> Result<()> {
+        if depth > array(self.root, "nodes").len() {
+            return Err(gltf_error("node hierarchy contains a cycle"));
+        }
+
+        let root = self.root;
+        let node = element(root, "nodes", node)?;
+        let transform = *parent * node_transform(node)?;
+
+        if let Some(mesh) = index(node, "mesh") {
+            self.load_mesh(mesh, &transform, scene)?;
+        }
+
+        if let Some(camera) = index(node, "camera") {
+            self.load_camera(camera, &transform, scene)?;
+        }
+
+        let light = node.get("extensions")
+            .and_then(|extensions| extensions.get("KHR_lights_punctual"))
+            .and_then(|extension| index(extension, "light"));
+        if let Some(light) = light {
+            self.load_light(light, &transform, scene)?;
+        }
+
+        for child in array(node, "children") {
+            let child = child.as_usize().ok_or_else(|| gltf_error("invalid child index"))?;
+            self.load_node(child, &transform, scene, depth + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Gltf;
+
+impl Gltf {
+    /// Imports the default scene of a `.gltf` or `.glb` file
+    ///
+    /// Meshes use `MetallicRoughness` materials and point lights become small emissive
+    /// spheres. With `camera_aspect_ratio` set the first perspective camera replaces the
+    /// scene camera; pass `None` to keep a camera the scene already has. Returns whether
+    /// the camera was replaced.
+    pub fn load<P: AsRef<Path>>(path: P, scene: &mut Scene, camera_aspect_ratio: Option<Float>) -> Result<bool> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+
+        let (root, binary) = if bytes.starts_with(GLB_MAGIC) {
+            parse_glb(&bytes)?
+        } else {
+            let text = std::str::from_utf8(&bytes).map_err(|_| gltf_error("file is not UTF-8"))?;
+            (Json::parse(text)?, None)
+        };
+
+        let mut importer = Importer {
+            root: &root,
+            directory: path.parent().unwrap_or_else(|| Path::new("")),
+            buffers: Vec::new(),
+            materials: Vec::new(),
+            default_material: None,
+            textures: HashMap::new(),
+            camera_aspect_ratio,
+        };
+
+        importer.load_buffers(binary)?;
+        importer.load_materials(scene)?;
+
+        // Files without scenes contain nothing to display
+        let nodes = match array(&root, "scenes").get(index(&root, "scene").unwrap_or(0)) {
+            Some(default_scene) => array(default_scene, "nodes"),
+            None => &[],
+        };
+
+        for node in nodes {
+            let node = node.as_usize().ok_or_else(|| gltf_error("invalid node index"))?;
+            importer.load_node(node, &Matrix4::IDENTITY, scene, 0)?;
+        }
+
+        Ok(camera_aspect_ratio.is_some() && importer.camera_aspect_ratio.is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::RandomGenerator;
+
+    fn importer<'a>(root: &'a Json, buffer: Vec<u8>, camera_aspect_ratio: Option<Float>) -> Importer<'a> {
+        Importer {
+            root,
+            directory: Path::new(""),
+            buffers: vec![buffer],
+            materials: Vec::new(),
+            default_material: None,
+            textures: HashMap::new(),
+            camera_aspect_ratio,
+        }
+    }
+
+    fn glb(chunks: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut bytes = b"glTF".to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        for (kind, data) in chunks {
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&kind.to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        let length = bytes.len() as u32;
+        bytes[8..12].copy_from_slice(&length.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGVsbG8h").unwrap(), b"hello!");
+        assert_eq!(decode_base64("-_8").unwrap(), [0xFB, 0xFF]);
+        assert_eq!(decode_base64("+/8=").unwrap(), [0xFB, 0xFF]);
+        assert!(decode_base64("aGV*bG8=").is_err());
+    }
+
+    #[test]
+    fn uri_escapes() {
+        assert_eq!(decode_uri("my%20mesh%2Ebin"), "my mesh.bin");
+        assert_eq!(decode_uri("%C3%A9.png"), "\u{e9}.png");
+        assert_eq!(decode_uri("100%"), "100%");
+        assert_eq!(decode_uri("%zz%4"), "%zz%4");
+        assert_eq!(decode_uri("%+4"), "%+4");
+    }
+
+    #[test]
+    fn glb_chunks() {
+        let bytes = glb(&[(GLB_CHUNK_JSON, b"{\"asset\": {}}  "), (GLB_CHUNK_BIN, &[1, 2, 3, 4])]);
+        let (json, binary) = parse_glb(&bytes).unwrap();
+        assert!(json.get("asset").is_some());
+        assert_eq!(binary, Some(vec![1, 2, 3, 4]));
+
+        // Unknown chunks are skipped
+        let bytes = glb(&[(0x1234, b"ignored"), (GLB_CHUNK_JSON, b"{}")]);
+        assert_eq!(parse_glb(&bytes).unwrap(), (Json::Object(Vec::new()), None));
+    }
+
+    #[test]
+    fn invalid_glb() {
+        let mut bytes = glb(&[(GLB_CHUNK_JSON, b"{}")]);
+        bytes[4] = 1;
+        assert!(parse_glb(&bytes).is_err());
+
+        let mut bytes = glb(&[(GLB_CHUNK_JSON, b"{}")]);
+        bytes.truncate(bytes.len() - 1);
+        assert!(parse_glb(&bytes).is_err());
+
+        assert!(parse_glb(&glb(&[(GLB_CHUNK_BIN, &[0; 4])])).is_err());
+        assert!(parse_glb(b"glTF").is_err());
+    }
+
+    #[test]
+    fn interleaved_accessors() {
+        // Two vertices of a normalized u16 VEC2 followed by a float, 8 bytes apart
+        let mut buffer = vec![0xAA; 4];
+        for (u, v, w) in [(0u16, 65535u16, 1.5f32), (32768, 0, -2.0)] {
+            buffer.extend_from_slice(&u.to_le_bytes());
+            buffer.extend_from_slice(&v.to_le_bytes());
+            buffer.extend_from_slice(&w.to_le_bytes());
+        }
+
+        let root = Json::parse(r#"{
+            "bufferViews": [{"buffer": 0, "byteOffset": 4, "byteLength": 16, "byteStride": 8}],
+            "accessors": [
+                {"bufferView": 0, "count": 2, "type": "VEC2", "componentType": 5123, "normalized": true},
+                {"bufferView": 0, "byteOffset": 4, "count": 2, "type": "SCALAR", "componentType": 5126},
+                {"bufferView": 0, "byteOffset": 4, "count": 3, "type": "SCALAR", "componentType": 5126}
+            ]
+        }"#).unwrap();
+        let importer = importer(&root, buffer, None);
+
+        let (values, components) = importer.accessor(0).unwrap();
+        assert_eq!(components, 2);
+        assert_eq!(values, [0.0, 1.0, 32768.0 / 65535.0, 0.0]);
+
+        assert_eq!(importer.accessor(1).unwrap(), (vec![1.5, -2.0], 1));
+        assert!(importer.accessor(2).is_err());
+    }
+
+    #[test]
+    fn normalized_signed_accessors() {
+        let mut buffer = vec![0x80, 0x81, 0x7F, 0x00];
+        for value in [i16::MIN, i16::MAX] {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let root = Json::parse(r#"{
+            "bufferViews": [{"buffer": 0, "byteLength": 8}],
+            "accessors": [
+                {"bufferView": 0, "count": 4, "type": "SCALAR", "componentType": 5120, "normalized": true},
+                {"bufferView": 0, "count": 4, "type": "SCALAR", "componentType": 5120},
+                {"bufferView": 0, "byteOffset": 4, "count": 2, "type": "SCALAR", "componentType": 5122, "normalized": true}
+            ]
+        }"#).unwrap();
+        let importer = importer(&root, buffer, None);
+
+        assert_eq!(importer.accessor(0).unwrap().0, [-1.0, -1.0, 1.0, 0.0]);
+        assert_eq!(importer.accessor(1).unwrap().0, [-128.0, -127.0, 127.0, 0.0]);
+        assert_eq!(importer.accessor(2).unwrap().0, [-1.0, 1.0]);
+    }
+
+    #[test]
+    fn oversized_accessors() {
+        // Sizes that wrap around or would need huge allocations are rejected, not computed
+        let root = Json::parse(r#"{
+            "bufferViews": [
+                {"buffer": 0, "byteLength": 16},
+                {"buffer": 0, "byteLength": 16, "byteStride": 4611686018427387904},
+                {"buffer": 0, "byteOffset": 8, "byteLength": 18446744073709551615},
+                {"buffer": 0, "byteLength": 16, "byteStride": 2}
+            ],
+            "accessors": [
+                {"bufferView": 0, "count": 4611686018427387904, "type": "VEC4", "componentType": 5126},
+                {"bufferView": 1, "count": 5, "type": "SCALAR", "componentType": 5126},
+                {"bufferView": 0, "byteOffset": 18446744073709551615, "count": 1, "type": "SCALAR", "componentType": 5126},
+                {"count": 1000000000, "type": "VEC3", "componentType": 5126},
+                {"bufferView": 2, "count": 1, "type": "SCALAR", "componentType": 5126},
+                {"bufferView": 3, "count": 2, "type": "SCALAR", "componentType": 5126},
+                {"count": 3, "type": "VEC2", "componentType": 5126}
+            ]
+        }"#).unwrap();
+        let importer = importer(&root, vec![0; 16], None);
+
+        for accessor in 0..6 {
+            match importer.accessor(accessor) {
+                Err(Error::GltfError(_)) => (),
+                other => panic!("accessor {} gave {:?}", accessor, other.map(|(values, _)| values.len())),
+            }
+        }
+        assert_eq!(importer.accessor(6).unwrap(), (vec![0.0; 6], 2));
+    }
+
+    #[test]
+    fn camera_uses_render_aspect_ratio() {
+        let root = Json::parse(r#"{
+            "cameras": [{"type": "perspective", "perspective": {"yfov": 1.5707963267948966, "aspectRatio": 1.0}}],
+            "nodes": [{"camera": 0, "translation": [0, 0, 5]}]
+        }"#).unwrap();
+        let mut rng = RandomGenerator::new();
+
+        let mut scene = Scene::new();
+        importer(&root, Vec::new(), Some(2.0)).load_node(0, &Matrix4::IDENTITY, &mut scene, 0).unwrap();
+        let ray = scene.camera.get_ray(1.0, 0.5, &mut rng);
+        assert!((ray.origin.z - 5.0).abs() < 1e-12);
+        assert!((ray.direction.x / ray.direction.z + 2.0).abs() < 1e-12);
+
+        // A camera the scene already has is kept
+        let mut scene = Scene::new();
+        importer(&root, Vec::new(), None).load_node(0, &Matrix4::IDENTITY, &mut scene, 0).unwrap();
+        assert_eq!(scene.camera.get_ray(0.5, 0.5, &mut rng).origin.z, 0.0);
+    }
+}
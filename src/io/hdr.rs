@@ -1,8 +1,8 @@
-use byteorder::{ReadBytesExt, BigEndian};
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 use crate::error::*;
 use crate::image::Image;
-use crate::math::Float;
-use std::io::{BufReader, BufRead};
+use crate::math::*;
+use std::io::{BufReader, BufRead, BufWriter, Write};
 use std::fs::File;
 use std::path::Path;
 use crate::color::Color3;
@@ -49,7 +49,7 @@ impl Header {
 
         buf.clear();
         reader.read_line(&mut buf)?;
-        let mut tokens = buf.trim().split_whitespace();
+        let mut tokens = buf.split_whitespace();
 
         let y_axis = tokens.next().ok_or(Error::HdrDecodingError)?;
         let height = tokens.next().ok_or(Error::HdrDecodingError)?.parse()?;
@@ -78,19 +78,58 @@ fn decode_rgbe(r: u8, g: u8, b: u8, e: u8) -> Color3 {
     Color3 { r, g, b }
 }
 
+/// Shared exponent encoding, the inverse of `decode_rgbe`
+fn encode_rgbe(color: Color3) -> [u8; 4] {
+    let r = max(color.r, 0.0);
+    let g = max(color.g, 0.0);
+    let b = max(color.b, 0.0);
+
+    let v = max(r, max(g, b));
+    if v.is_nan() || v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    if v >= (2.0 as Float).powi(127) {
+        return [255, 255, 255, 255];
+    }
+
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    if v >= (2.0 as Float).powi(e) {
+        e += 1;
+    } else if v < (2.0 as Float).powi(e - 1) {
+        e -= 1;
+    }
+
+    let scale = 256.0 / (2.0 as Float).powi(e);
+    [(r * scale) as u8, (g * scale) as u8, (b * scale) as u8, (e + 128) as u8]
+}
+
+/// Scanlines of this width are run-length encoded
+fn is_rle_width(width: usize) -> bool {
+    (8..=0x7FFF).contains(&width)
+}
+
 fn unpack_rle_scanline<R: BufRead>(reader: &mut R, y: usize, image: &mut Image) -> Result<()> {
     let mut red = vec![0; image.width()];
     let mut green = vec![0; image.width()];
     let mut blue = vec![0; image.width()];
     let mut exp = vec![0; image.width()];
 
-    let new_rle_indicator: u16 = reader.read_u16::<BigEndian>()?;
-    if new_rle_indicator != 0x0202 {
-        println!("hdr::parse(): only New RLE HDRs are supported");
-        return Err(Error::HdrDecodingError);
+    let mut start = [0; 4];
+    reader.read_exact(&mut start)?;
+
+    // Scanlines that are not run-length encoded are stored as flat RGBE pixels
+    if start[0] != 2 || start[1] != 2 || start[2] & 0x80 != 0 || !is_rle_width(image.width()) {
+        image.set_pixel(0, y, decode_rgbe(start[0], start[1], start[2], start[3]));
+        for x in 1..image.width() {
+            let mut rgbe = [0; 4];
+            reader.read_exact(&mut rgbe)?;
+            image.set_pixel(x, y, decode_rgbe(rgbe[0], rgbe[1], rgbe[2], rgbe[3]));
+        }
+        return Ok(());
     }
 
-    let scanline_width: u16 = reader.read_u16::<BigEndian>()?;
+    let scanline_width = u16::from_be_bytes([start[2], start[3]]);
     if scanline_width as usize != image.width() {
         println!("hdr::parse(): bad scanline width");
         return Err(Error::HdrDecodingError);
@@ -104,12 +143,15 @@ fn unpack_rle_scanline<R: BufRead>(reader: &mut R, y: usize, image: &mut Image)
                 let count = count & 0x7F;
                 let value = reader.read_u8()?;
                 for _ in 0..count {
-                    component[x] = value;
+                    *component.get_mut(x).ok_or(Error::HdrDecodingError)? = value;
                     x += 1;
                 }
             } else {
+                if count == 0 {
+                    return Err(Error::HdrDecodingError);
+                }
                 for _ in 0..count {
-                    component[x] = reader.read_u8()?;
+                    *component.get_mut(x).ok_or(Error::HdrDecodingError)? = reader.read_u8()?;
                     x += 1;
                 }
             }
@@ -124,19 +166,178 @@ fn unpack_rle_scanline<R: BufRead>(reader: &mut R, y: usize, image: &mut Image)
     Ok(())
 }
 
+/// New-style RLE of one component: runs of at least 4 equal bytes, literals in between
+fn pack_rle_component<W: Write>(writer: &mut W, data: &[u8]) -> Result<()> {
+    const MIN_RUN: usize = 4;
+
+    let mut x = 0;
+    while x < data.len() {
+        // Find the next run long enough to be worth encoding
+        let mut run_start = x;
+        let mut run_length = 0;
+        while run_start < data.len() {
+            run_length = 1;
+            while run_length < 127 && run_start + run_length < data.len() && data[run_start + run_length] == data[run_start] {
+                run_length += 1;
+            }
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+
+        while x < run_start {
+            let count = (run_start - x).min(128);
+            writer.write_u8(count as u8)?;
+            writer.write_all(&data[x..x + count])?;
+            x += count;
+        }
+
+        if run_length >= MIN_RUN {
+            writer.write_u8(128 + run_length as u8)?;
+            writer.write_u8(data[run_start])?;
+            x += run_length;
+        }
+    }
+
+    Ok(())
+}
+
 pub struct Hdr;
 
 impl Hdr {
     pub fn load<P: AsRef<Path>>(p: P) -> Result<Image> {
-        let mut reader = BufReader::new(File::open(p)?);
+        Self::read(BufReader::new(File::open(p)?))
+    }
+
+    /// Decodes a Radiance RGBE image from any buffered reader
+    pub fn read<R: BufRead>(mut reader: R) -> Result<Image> {
         let header = Header::parse(&mut reader)?;
 
         let mut image = Image::new(header.width, header.height);
 
-        for y in (0..header.height).rev() {
+        // -Y means the first scanline is the top one
+        for y in 0..header.height {
             unpack_rle_scanline(&mut reader, y, &mut image)?;
         }
 
         Ok(image)
     }
+
+    /// Saves an image as Radiance RGBE with new-style run-length encoding
+    pub fn save<P: AsRef<Path>>(image: &Image, p: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(p)?);
+        Self::write(image, &mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn write<W: Write>(image: &Image, mut writer: W) -> Result<()> {
+        write!(writer, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", image.height(), image.width())?;
+
+        let width = image.width();
+        let mut components = vec![[0; 4]; width];
+
+        for y in 0..image.height() {
+            for (x, rgbe) in components.iter_mut().enumerate() {
+                *rgbe = encode_rgbe(image.get_pixel(x, y));
+            }
+
+            if !is_rle_width(width) {
+                for rgbe in &components {
+                    writer.write_all(rgbe)?;
+                }
+                continue;
+            }
+
+            writer.write_all(&[2, 2])?;
+            writer.write_u16::<BigEndian>(width as u16)?;
+
+            for component in 0..4 {
+                let data: Vec<u8> = components.iter().map(|rgbe| rgbe[component]).collect();
+                pack_rle_component(&mut writer, &data)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(image: &Image) -> Image {
+        let mut data = Vec::new();
+        Hdr::write(image, &mut data).unwrap();
+        Hdr::read(&data[..]).unwrap()
+    }
+
+    fn assert_close(decoded: &Image, image: &Image) {
+        assert_eq!((decoded.width(), decoded.height()), (image.width(), image.height()));
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let expected = image.get_pixel(x, y);
+                let actual = decoded.get_pixel(x, y);
+
+                // 8-bit mantissas relative to the brightest component
+                let tolerance = max(expected.r, max(expected.g, expected.b)) / 128.0;
+                assert!((expected.r - actual.r).abs() <= tolerance, "{:?} != {:?}", actual, expected);
+                assert!((expected.g - actual.g).abs() <= tolerance, "{:?} != {:?}", actual, expected);
+                assert!((expected.b - actual.b).abs() <= tolerance, "{:?} != {:?}", actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn run_length_encoded_round_trip() {
+        // Runs of constant color mixed with literal spans
+        let mut image = Image::new(300, 4);
+        for y in 0..4 {
+            for x in 0..300 {
+                let color = if x < 150 {
+                    Color3::new(1.0, 0.5, 0.25)
+                } else {
+                    Color3::new(x as Float * 0.01, y as Float * 10.0, 1e-3)
+                };
+                image.set_pixel(x, y, color);
+            }
+        }
+
+        assert_close(&round_trip(&image), &image);
+    }
+
+    #[test]
+    fn flat_round_trip() {
+        // Too narrow to be run-length encoded
+        let mut image = Image::new(3, 2);
+        image.set_pixel(0, 0, Color3::new(1000.0, 2.0, 0.0));
+        image.set_pixel(2, 1, Color3::new(0.125, 0.125, 0.125));
+
+        assert_close(&round_trip(&image), &image);
+    }
+
+    #[test]
+    fn first_scanline_is_top_row() {
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // Red on top, blue below, every component stored as a single run
+        for rgbe in [[128u8, 0, 0, 129], [0, 0, 128, 129]] {
+            data.extend_from_slice(&[2, 2, 0, 8]);
+            for component in rgbe {
+                data.extend_from_slice(&[128 + 8, component]);
+            }
+        }
+
+        let image = Hdr::read(&data[..]).unwrap();
+        assert_eq!(image.get_pixel(3, 0), Color3::new(1.0, 0.0, 0.0));
+        assert_eq!(image.get_pixel(3, 1), Color3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn rgbe_encoding() {
+        assert_eq!(encode_rgbe(Color3::new(0.0, 0.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(encode_rgbe(Color3::new(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(encode_rgbe(Color3::new(-1.0, 0.0, 0.0)), [0, 0, 0, 0]);
+    }
 }
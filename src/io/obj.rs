@@ -0,0 +1,482 @@
+use crate::color::Color3;
+use crate::error::*;
+use crate::material::*;
+use crate::math::*;
+use crate::scene::*;
+use crate::shape::{MeshData, TriangleMesh};
+use crate::texture::Texture;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+fn obj_error(line: usize, message: impl Into<String>) -> Error {
+    Error::ObjParseError { line, message: message.into() }
+}
+
+fn mtl_error(line: usize, message: impl Into<String>) -> Error {
+    Error::MtlParseError { line, message: message.into() }
+}
+
+/// Parses the next `N` whitespace-separated numbers, mapping failures with `error`
+fn parse_floats<const N: usize>(tokens: &mut SplitWhitespace, error: impl Fn(String) -> Error) -> Result<[Float; N]> {
+    let mut values = [0.0; N];
+
+    for value in &mut values {
+        let token = tokens.next().ok_or_else(|| error(format!("expected {} numbers", N)))?;
+        *value = token.parse().map_err(|_| error(format!("invalid number '{}'", token)))?;
+    }
+
+    Ok(values)
+}
+
+/// Lines of a text file with comments stripped, numbered from 1
+fn lines<R: BufRead>(reader: R) -> impl Iterator<Item = (usize, std::io::Result<String>)> {
+    reader.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let line = line.map(|line| match line.find('#') {
+                Some(comment) => line[..comment].to_string(),
+                None => line,
+            });
+            (index + 1, line)
+        })
+}
+
+/// Material description from an MTL file
+struct MtlMaterial {
+    diffuse: Color3,
+    specular: Color3,
+    emission: Color3,
+    exponent: Float,
+    diffuse_map: Option<PathBuf>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Color3::new(0.8, 0.8, 0.8),
+            specular: Color3::new(0.0, 0.0, 0.0),
+            emission: Color3::new(0.0, 0.0, 0.0),
+            exponent: 1.0,
+            diffuse_map: None,
+        }
+    }
+}
+
+pub struct Mtl;
+
+impl Mtl {
+    /// Registers all materials of an MTL file in the scene, returning their ids by name
+    ///
+    /// Emissive materials become `LightEmitter`s, materials with a specular color or
+    /// a diffuse map become `Phong` and the rest `Lambertian`.
+    pub fn load<P: AsRef<Path>>(path: P, scene: &mut Scene) -> Result<HashMap<String, MaterialId>> {
+        let path = path.as_ref();
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let reader = BufReader::new(File::open(path)?);
+
+        let mut materials = Vec::new();
+
+        for (line_number, line) in lines(reader) {
+            let line = line?;
+            let mut tokens = line.split_whitespace();
+            let error = |message| mtl_error(line_number, message);
+
+            let keyword = match tokens.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+
+            if keyword == "newmtl" {
+                let name = tokens.next().ok_or_else(|| mtl_error(line_number, "missing material name"))?;
+                materials.push((name.to_string(), MtlMaterial::default()));
+                continue;
+            }
+
+            let material = match materials.last_mut() {
+                Some((_, material)) => material,
+                None => return Err(mtl_error(line_number, format!("'{}' before newmtl", keyword))),
+            };
+
+            match keyword {
+                "Kd" => {
+                    let [r, g, b] = parse_floats(&mut tokens, error)?;
+                    material.diffuse = Color3::new(r, g, b);
+                }
+                "Ks" => {
+                    let [r, g, b] = parse_floats(&mut tokens, error)?;
+                    material.specular = Color3::new(r, g, b);
+                }
+                "Ke" => {
+                    let [r, g, b] = parse_floats(&mut tokens, error)?;
+                    material.emission = Color3::new(r, g, b);
+                }
+                "Ns" => {
+                    let [exponent] = parse_floats(&mut tokens, error)?;
+                    material.exponent = exponent;
+                }
+                "map_Kd" => {
+                    // Options like `-s u v w` come first, the file name is last
+                    let file_name = tokens.last().ok_or_else(|| mtl_error(line_number, "missing texture file name"))?;
+                    material.diffuse_map = Some(directory.join(file_name));
+                }
+                _ => (),
+            }
+        }
+
+        let mut textures: HashMap<PathBuf, Arc<Texture>> = HashMap::new();
+        let mut ids = HashMap::new();
+
+        for (name, material) in materials {
+            let id = if material.emission.luminance() > 0.0 {
+                scene.add_material(LightEmitter { color: material.emission })
+            } else if material.specular.luminance() > 0.0 || material.diffuse_map.is_some() {
+                let diffuse_map = match material.diffuse_map {
+                    Some(path) => match textures.get(&path) {
+                        Some(texture) => Some(texture.clone()),
+                        None => {
+                            let texture = Arc::new(Texture::load(&path)?);
+                            textures.insert(path, texture.clone());
+                            Some(texture)
+                        }
+                    },
+                    None => None,
+                };
+
+                scene.add_material(Phong {
+                    diffuse: material.diffuse,
+                    diffuse_map,
+                    specular: material.specular,
+                    exponent: material.exponent,
+                })
+            } else {
+                scene.add_material(Lambertian { color: material.diffuse })
+            };
+
+            ids.insert(name, id);
+        }
+
+        Ok(ids)
+    }
+}
+
+/// Indices of a face vertex into the position, UV and normal lists
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+/// Accumulates faces sharing a material into an indexed mesh
+struct MeshBuilder {
+    material: MaterialId,
+    vertices: HashMap<VertexKey, u32>,
+    positions: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    uvs: Vec<Vector2>,
+    indices: Vec<u32>,
+    // A mesh has normals or UVs only if every vertex has them
+    has_normals: bool,
+    has_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn new(material: MaterialId) -> Self {
+        Self {
+            material,
+            vertices: HashMap::new(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+            has_normals: true,
+            has_uvs: true,
+        }
+    }
+
+    fn vertex(&mut self, key: VertexKey, obj: &ObjData) -> u32 {
+        if let Some(&index) = self.vertices.get(&key) {
+            return index;
+        }
+
+        let (position, uv, normal) = key;
+        self.positions.push(obj.positions[position]);
+
+        match uv {
+            Some(uv) => self.uvs.push(obj.uvs[uv]),
+            None => self.has_uvs = false,
+        }
+
+        match normal {
+            Some(normal) => self.normals.push(obj.normals[normal]),
+            None => self.has_normals = false,
+        }
+
+        let index = self.positions.len() as u32 - 1;
+        self.vertices.insert(key, index);
+        index
+    }
+
+    /// Adds the mesh to the scene unless it has no faces
+    fn finish(self, scene: &mut Scene) -> Result<()> {
+        if self.indices.is_empty() {
+            return Ok(());
+        }
+
+        scene.add_mesh(TriangleMesh::new(MeshData {
+            positions: self.positions,
+            normals: if self.has_normals { self.normals } else { Vec::new() },
+            uvs: if self.has_uvs { self.uvs } else { Vec::new() },
+            indices: self.indices,
+            material: self.material,
+        })?);
+
+        Ok(())
+    }
+}
+
+/// Vertex attribute lists shared by all groups of an OBJ file
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Vector3>,
+    uvs: Vec<Vector2>,
+    normals: Vec<Vector3>,
+}
+
+impl ObjData {
+    /// Resolves a 1-based (or negative, relative to the end) index into a list of `count` elements
+    fn resolve(token: &str, count: usize, line: usize) -> Result<usize> {
+        let index: i64 = token.parse().map_err(|_| obj_error(line, format!("invalid index '{}'", token)))?;
+
+        let resolved = if index > 0 {
+            index - 1
+        } else {
+            count as i64 + index
+        };
+
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(obj_error(line, format!("index {} out of range", index)));
+        }
+
+        Ok(resolved as usize)
+    }
+
+    /// Parses a face vertex in `v`, `v/vt`, `v//vn` or `v/vt/vn` form
+    fn face_vertex(&self, token: &str, line: usize) -> Result<VertexKey> {
+        let mut parts = token.split('/');
+
+        let position = Self::resolve(parts.next().unwrap_or(""), self.positions.len(), line)?;
+        let uv = match parts.next() {
+            Some(uv) if !uv.is_empty() => Some(Self::resolve(uv, self.uvs.len(), line)?),
+            _ => None,
+        };
+        let normal = match parts.next() {
+            Some(normal) if !normal.is_empty() => Some(Self::resolve(normal, self.normals.len(), line)?),
+            _ => None,
+        };
+
+        if parts.next().is_some() {
+            return Err(obj_error(line, format!("invalid face vertex '{}'", token)));
+        }
+
+        Ok((position, uv, normal))
+    }
+}
+
+pub struct Obj;
+
+impl Obj {
+    /// Loads all faces of an OBJ file into the scene, along with materials from its MTL libraries
+    ///
+    /// Polygons are triangulated as fans. Every group and material change starts a new mesh.
+    pub fn load<P: AsRef<Path>>(path: P, scene: &mut Scene) -> Result<()> {
+        let path = path.as_ref();
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        Obj::read(BufReader::new(File::open(path)?), directory, scene)
+    }
+
+    /// Reads OBJ data, resolving MTL libraries relative to `directory`
+    pub fn read<R: BufRead>(reader: R, directory: &Path, scene: &mut Scene) -> Result<()> {
+        let mut obj = ObjData::default();
+        let mut materials = HashMap::new();
+        let mut default_material = None;
+        let mut builder: Option<MeshBuilder> = None;
+        let mut current_material: Option<MaterialId> = None;
+
+        for (line_number, line) in lines(reader) {
+            let line = line?;
+            let mut tokens = line.split_whitespace();
+            let error = |message| obj_error(line_number, message);
+
+            let keyword = match tokens.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+
+            match keyword {
+                "v" => {
+                    let [x, y, z] = parse_floats(&mut tokens, error)?;
+                    obj.positions.push(Vector3::new(x, y, z));
+                }
+                "vt" => {
+                    // v and w are optional, w is ignored
+                    let values = tokens
+                        .map(|token| token.parse().map_err(|_| error(format!("invalid number '{}'", token))))
+                        .collect::<Result<Vec<Float>>>()?;
+
+                    if values.is_empty() || values.len() > 3 {
+                        return Err(error("expected 1 to 3 numbers".to_string()));
+                    }
+
+                    obj.uvs.push(Vector2::new(values[0], values.get(1).copied().unwrap_or(0.0)));
+                }
+                "vn" => {
+                    let [x, y, z] = parse_floats(&mut tokens, error)?;
+                    obj.normals.push(Vector3::new(x, y, z));
+                }
+                "f" => {
+                    let vertices = tokens
+                        .map(|token| obj.face_vertex(token, line_number))
+                        .collect::<Result<Vec<_>>>()?;
+
+                    if vertices.len() < 3 {
+                        return Err(obj_error(line_number, "face with less than 3 vertices"));
+                    }
+
+                    let material = match current_material {
+                        Some(material) => material,
+                        None => *default_material.get_or_insert_with(|| {
+                            scene.add_material(Lambertian { color: Color3::new(0.8, 0.8, 0.8) })
+                        }),
+                    };
+
+                    let builder = builder.get_or_insert_with(|| MeshBuilder::new(material));
+                    let indices = vertices.into_iter()
+                        .map(|vertex| builder.vertex(vertex, &obj))
+                        .collect::<Vec<_>>();
+
+                    for i in 1..indices.len() - 1 {
+                        builder.indices.extend_from_slice(&[indices[0], indices[i], indices[i + 1]]);
+                    }
+                }
+                "g" | "o" => {
+                    if let Some(builder) = builder.take() {
+                        builder.finish(scene)?;
+                    }
+                }
+                "usemtl" => {
+                    let name = tokens.next().ok_or_else(|| obj_error(line_number, "missing material name"))?;
+                    let material = materials.get(name).copied();
+                    if material.is_none() {
+                        eprintln!("obj: unknown material '{}' at line {}, using the default material", name, line_number);
+                    }
+
+                    if current_material != material {
+                        if let Some(builder) = builder.take() {
+                            builder.finish(scene)?;
+                        }
+                        current_material = material;
+                    }
+                }
+                "mtllib" => {
+                    for file_name in tokens {
+                        materials.extend(Mtl::load(directory.join(file_name), scene)?);
+                    }
+                }
+                // Smoothing groups, lines, points and free-form geometry are ignored
+                _ => (),
+            }
+        }
+
+        if let Some(builder) = builder {
+            builder.finish(scene)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<Scene> {
+        let mut scene = Scene::new();
+        Obj::read(text.as_bytes(), Path::new(""), &mut scene)?;
+        Ok(scene)
+    }
+
+    fn error_line(text: &str) -> usize {
+        match read(text) {
+            Err(Error::ObjParseError { line, .. }) => line,
+            Err(other) => panic!("expected an OBJ error, got {:?}", other),
+            Ok(_) => panic!("expected an OBJ error"),
+        }
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn fan_triangulation() {
+        let scene = read(&format!("{}v 0.5 2 0\nf 1 2 3 5 4\n", SQUARE)).unwrap();
+        let mesh = scene.meshes()[0].data();
+        assert_eq!(mesh.positions.len(), 5);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn negative_indices() {
+        let scene = read(&format!("{}vt 0.5\nvt 0 1 0\nf -4/-2 -3/-1 -2/-1\nf 1/1 3/2 4/2\n", SQUARE)).unwrap();
+        let mesh = scene.meshes()[0].data();
+
+        // Shared vertices are deduplicated by their position and UV indices
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.positions[1].x, 1.0);
+        assert_eq!(mesh.positions[3].y, 1.0);
+        assert_eq!(mesh.uvs[0], Vector2::new(0.5, 0.0));
+        assert_eq!(mesh.uvs[1], Vector2::new(0.0, 1.0));
+        assert!(mesh.normals.is_empty());
+    }
+
+    #[test]
+    fn usemtl_splits_meshes() {
+        let directory = std::env::temp_dir().join(format!("disquiet-obj-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("test.mtl"), "newmtl red\nKd 1 0 0\nnewmtl green\nKd 0 1 0\n").unwrap();
+
+        let text = format!(
+            "mtllib test.mtl\n{}usemtl red\nf 1 2 3\nf 1 3 4\nusemtl red\nf 2 3 4\nusemtl green\nf 1 2 3\nusemtl missing\nf 1 2 4\n",
+            SQUARE,
+        );
+        let mut scene = Scene::new();
+        let result = Obj::read(text.as_bytes(), &directory, &mut scene);
+        std::fs::remove_dir_all(&directory).unwrap();
+        result.unwrap();
+
+        let meshes = scene.meshes();
+        let triangles: Vec<usize> = meshes.iter().map(TriangleMesh::triangle_count).collect();
+        assert_eq!(triangles, [3, 1, 1]);
+
+        // Unknown materials fall back to the default material
+        let materials: Vec<MaterialId> = meshes.iter().map(|mesh| mesh.data().material).collect();
+        assert_eq!(materials, [0, 1, 2]);
+    }
+
+    #[test]
+    fn unknown_material_without_library() {
+        let scene = read(&format!("{}usemtl missing\nf 1 2 3\nusemtl other\nf 1 3 4\n", SQUARE)).unwrap();
+        assert_eq!(scene.meshes().len(), 1);
+        assert_eq!(scene.meshes()[0].triangle_count(), 2);
+    }
+
+    #[test]
+    fn errors_report_line_numbers() {
+        assert_eq!(error_line(&format!("{}# comment\n\nf 1 2 5\n", SQUARE)), 7);
+        assert_eq!(error_line(&format!("{}f 1 2 0\n", SQUARE)), 5);
+        assert_eq!(error_line(&format!("{}f 1 -5 2\n", SQUARE)), 5);
+        assert_eq!(error_line("v 1 2\n"), 1);
+        assert_eq!(error_line("v 0 0 0\nvt\n"), 2);
+        assert_eq!(error_line("vt 1 2 3 4\n"), 1);
+        assert_eq!(error_line(&format!("{}f 1 2\n", SQUARE)), 5);
+        assert_eq!(error_line(&format!("{}f 1/1/1/1 2 3\n", SQUARE)), 5);
+    }
+}
@@ -0,0 +1,907 @@
+use crate::camera::Camera;
+use crate::color::Color3;
+use crate::error::*;
+use crate::io::{Hdr, Ply, RenderSettings};
+use crate::material::*;
+use crate::math::*;
+use crate::scene::*;
+use crate::shape::{MeshData, Sphere, TriangleMesh};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Nested `Include`s deeper than this are assumed to be cyclic
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+// RGB approximation of the spectral index of refraction of copper, pbrt's default metal
+const COPPER_ETA: Color3 = Color3::new(0.200438, 0.924033, 1.10221);
+const COPPER_K: Color3 = Color3::new(3.91295, 2.45285, 2.14219);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Identifier(String),
+    String(String),
+    Number(Float),
+    OpenBracket,
+    CloseBracket,
+}
+
+/// Splits a pbrt file into tokens, each paired with its line number
+fn tokenize(text: &str, file: &str) -> Result<Vec<(Token, usize)>> {
+    let error = |line, message: String| Error::PbrtParseError { file: file.to_string(), line, message };
+
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '[' => {
+                chars.next();
+                tokens.push((Token::OpenBracket, line));
+            }
+            ']' => {
+                chars.next();
+                tokens.push((Token::CloseBracket, line));
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => return Err(error(line, "unterminated string".to_string())),
+                        Some(c) => string.push(c),
+                    }
+                }
+                tokens.push((Token::String(string), line));
+            }
+            '-' | '+' | '.' | '0'..='9' => {
+                let mut text = String::new();
+                while let Some(c) = chars.next_if(|c| matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E')) {
+                    text.push(c);
+                }
+                let number = text.parse().map_err(|_| error(line, format!("invalid number '{}'", text)))?;
+                tokens.push((Token::Number(number), line));
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                // Booleans may be written without quotes
+                let token = match name.as_str() {
+                    "true" | "false" => Token::String(name),
+                    _ => Token::Identifier(name),
+                };
+                tokens.push((token, line));
+            }
+            _ => return Err(error(line, format!("unexpected character '{}'", c))),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Directive name followed by its arguments, up to the next directive
+struct Directive {
+    name: String,
+    args: Vec<Token>,
+    file: String,
+    line: usize,
+}
+
+impl Directive {
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::PbrtParseError {
+            file: self.file.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    /// All arguments as numbers, brackets are ignored
+    fn numbers(&self) -> Result<Vec<Float>> {
+        let mut numbers = Vec::new();
+        for arg in &self.args {
+            match arg {
+                Token::Number(number) => numbers.push(*number),
+                Token::OpenBracket | Token::CloseBracket => (),
+                _ => return Err(self.error(format!("{} expects only numbers", self.name))),
+            }
+        }
+
+        Ok(numbers)
+    }
+
+    fn fixed_numbers<const N: usize>(&self) -> Result<[Float; N]> {
+        let numbers = self.numbers()?;
+        if numbers.len() != N {
+            return Err(self.error(format!("{} expects {} numbers, found {}", self.name, N, numbers.len())));
+        }
+
+        let mut result = [0.0; N];
+        result.copy_from_slice(&numbers);
+        Ok(result)
+    }
+
+    /// Positional string argument
+    fn string(&self, index: usize) -> Result<&str> {
+        match self.args.get(index) {
+            Some(Token::String(string)) => Ok(string),
+            _ => Err(self.error(format!("{} expects a string as argument {}", self.name, index + 1))),
+        }
+    }
+
+    /// Parameter list following `positional` string arguments
+    fn params(&self, positional: usize) -> Result<Params<'_>> {
+        for index in 0..positional {
+            self.string(index)?;
+        }
+
+        let mut params = Vec::new();
+        let mut args = self.args[positional..].iter();
+
+        while let Some(arg) = args.next() {
+            let declaration = match arg {
+                Token::String(declaration) => declaration,
+                _ => return Err(self.error("expected a parameter declaration such as \"float fov\"")),
+            };
+
+            let mut words = declaration.split_whitespace();
+            let (kind, name) = match (words.next(), words.next(), words.next()) {
+                (Some(kind), Some(name), None) => (kind.to_string(), name.to_string()),
+                _ => return Err(self.error(format!("invalid parameter declaration \"{}\"", declaration))),
+            };
+
+            let mut values = Vec::new();
+            match args.next() {
+                Some(Token::OpenBracket) => loop {
+                    match args.next() {
+                        Some(Token::CloseBracket) => break,
+                        Some(Token::OpenBracket) | None => {
+                            return Err(self.error(format!("unterminated value list of '{}'", name)));
+                        }
+                        Some(value) => values.push(value.clone()),
+                    }
+                },
+                Some(value @ Token::Number(_)) | Some(value @ Token::String(_)) => values.push(value.clone()),
+                _ => return Err(self.error(format!("missing value of '{}'", name))),
+            }
+
+            let values = if values.iter().all(|value| matches!(value, Token::Number(_))) {
+                ParamValues::Numbers(values.iter().filter_map(|value| match value {
+                    Token::Number(number) => Some(*number),
+                    _ => None,
+                }).collect())
+            } else if values.iter().all(|value| matches!(value, Token::String(_))) {
+                ParamValues::Strings(values.into_iter().filter_map(|value| match value {
+                    Token::String(string) => Some(string),
+                    _ => None,
+                }).collect())
+            } else {
+                return Err(self.error(format!("'{}' mixes numbers and strings", name)));
+            };
+
+            params.push(Param { kind, name, values });
+        }
+
+        Ok(Params { params, directive: self })
+    }
+}
+
+enum ParamValues {
+    Numbers(Vec<Float>),
+    Strings(Vec<String>),
+}
+
+/// Typed parameter such as `"rgb Kd" [0.5 0.5 0.5]`
+struct Param {
+    kind: String,
+    name: String,
+    values: ParamValues,
+}
+
+struct Params<'a> {
+    params: Vec<Param>,
+    directive: &'a Directive,
+}
+
+impl Params<'_> {
+    fn get(&self, name: &str) -> Option<&Param> {
+        self.params.iter().find(|param| param.name == name)
+    }
+
+    fn numbers(&self, name: &str) -> Result<Option<&[Float]>> {
+        match self.get(name) {
+            Some(Param { values: ParamValues::Numbers(numbers), .. }) => Ok(Some(numbers)),
+            Some(_) => Err(self.directive.error(format!("'{}' must be numeric", name))),
+            None => Ok(None),
+        }
+    }
+
+    fn float(&self, name: &str, default: Float) -> Result<Float> {
+        match self.numbers(name)? {
+            Some([value]) => Ok(*value),
+            Some(_) => Err(self.directive.error(format!("'{}' must be a single number", name))),
+            None => Ok(default),
+        }
+    }
+
+    fn string(&self, name: &str) -> Result<Option<&str>> {
+        match self.get(name) {
+            Some(Param { values: ParamValues::Strings(strings), .. }) if strings.len() == 1 => Ok(Some(&strings[0])),
+            Some(_) => Err(self.directive.error(format!("'{}' must be a single string", name))),
+            None => Ok(None),
+        }
+    }
+
+    fn bool(&self, name: &str, default: bool) -> Result<bool> {
+        match self.string(name)? {
+            Some("true") => Ok(true),
+            Some("false") => Ok(false),
+            Some(other) => Err(self.directive.error(format!("'{}' must be true or false, found '{}'", name, other))),
+            None => Ok(default),
+        }
+    }
+
+    /// RGB color, other spectrum representations and textures fall back to `default`
+    fn color(&self, name: &str, default: Color3) -> Result<Color3> {
+        let param = match self.get(name) {
+            Some(param) => param,
+            None => return Ok(default),
+        };
+
+        match (param.kind.as_str(), &param.values) {
+            ("rgb" | "color", ParamValues::Numbers(values)) if values.len() == 3 => {
+                Ok(Color3::new(values[0], values[1], values[2]))
+            }
+            ("rgb" | "color", _) => Err(self.directive.error(format!("'{}' must be 3 numbers", name))),
+            (kind, _) => {
+                eprintln!("pbrt: {} '{}' is not supported, using the default", kind, name);
+                Ok(default)
+            }
+        }
+    }
+
+    /// Flat list of 3D points, normals or vectors
+    fn vectors(&self, name: &str) -> Result<Vec<Vector3>> {
+        let numbers = self.numbers(name)?.unwrap_or(&[]);
+        if !numbers.len().is_multiple_of(3) {
+            return Err(self.directive.error(format!("'{}' must have a multiple of 3 values", name)));
+        }
+
+        Ok(numbers.chunks_exact(3).map(|v| Vector3::new(v[0], v[1], v[2])).collect())
+    }
+}
+
+/// Material, area light and transform set by the enclosing attribute blocks
+#[derive(Clone)]
+struct GraphicsState {
+    transform: Matrix4,
+    // `None` until a material is set, shapes then use pbrt's default matte
+    material: Option<MaterialId>,
+    // Emitter material replacing `material` for shapes following `AreaLightSource`
+    area_light: Option<MaterialId>,
+}
+
+struct Loader {
+    directory: PathBuf,
+    scene: Scene,
+    settings: RenderSettings,
+    state: GraphicsState,
+    attribute_stack: Vec<GraphicsState>,
+    transform_stack: Vec<Matrix4>,
+    named_materials: HashMap<String, MaterialId>,
+    named_coordinate_systems: HashMap<String, Matrix4>,
+    default_material: Option<MaterialId>,
+    // Camera-to-world transform and field of view of the shorter image axis
+    camera: Option<(Matrix4, Float)>,
+    in_object: bool,
+}
+
+impl Loader {
+    fn new(directory: PathBuf) -> Self {
+        Loader {
+            directory,
+            scene: Scene::new(),
+            settings: RenderSettings {
+                width: 1280,
+                height: 720,
+                sample_count: 16,
+                bounces: 6,
+            },
+            state: GraphicsState {
+                transform: Matrix4::IDENTITY,
+                material: None,
+                area_light: None,
+            },
+            attribute_stack: Vec::new(),
+            transform_stack: Vec::new(),
+            named_materials: HashMap::new(),
+            named_coordinate_systems: HashMap::new(),
+            default_material: None,
+            camera: None,
+            in_object: false,
+        }
+    }
+
+    fn load_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        self.load_text(&text, &path.display().to_string(), depth)
+    }
+
+    /// Runs the directives of `text`, which was read from `file`
+    fn load_text(&mut self, text: &str, file: &str, depth: usize) -> Result<()> {
+        let file = file.to_string();
+        let mut tokens = tokenize(text, &file)?.into_iter().peekable();
+
+        while let Some((token, line)) = tokens.next() {
+            let name = match token {
+                Token::Identifier(name) => name,
+                _ => {
+                    return Err(Error::PbrtParseError { file, line, message: "expected a directive".to_string() });
+                }
+            };
+
+            let mut args = Vec::new();
+            while let Some((arg, _)) = tokens.next_if(|(token, _)| !matches!(token, Token::Identifier(_))) {
+                args.push(arg);
+            }
+
+            let directive = Directive { name, args, file: file.clone(), line };
+            self.directive(&directive, depth)?;
+        }
+
+        Ok(())
+    }
+
+    fn directive(&mut self, directive: &Directive, depth: usize) -> Result<()> {
+        match directive.name.as_str() {
+            "Identity" => self.state.transform = Matrix4::IDENTITY,
+            "Translate" => {
+                let [x, y, z] = directive.fixed_numbers()?;
+                self.apply(Matrix4::translation(Vector3::new(x, y, z)));
+            }
+            "Scale" => {
+                let [x, y, z] = directive.fixed_numbers()?;
+                self.apply(Matrix4::scaling(Vector3::new(x, y, z)));
+            }
+            "Rotate" => {
+                let [angle, x, y, z] = directive.fixed_numbers()?;
+                self.apply(Matrix4::rotation(Vector3::new(x, y, z), angle.to_radians()));
+            }
+            "LookAt" => {
+                let [ex, ey, ez, lx, ly, lz, ux, uy, uz] = directive.fixed_numbers()?;
+                let transform = look_at(Vector3::new(ex, ey, ez), Vector3::new(lx, ly, lz), Vector3::new(ux, uy, uz))
+                    .ok_or_else(|| directive.error("degenerate LookAt"))?;
+                self.apply(transform);
+            }
+            // pbrt lists matrices in column-major order
+            "Transform" => self.state.transform = Matrix4::from_column_major(&directive.fixed_numbers()?),
+            "ConcatTransform" => self.apply(Matrix4::from_column_major(&directive.fixed_numbers()?)),
+            "CoordinateSystem" => {
+                let name = directive.string(0)?.to_string();
+                self.named_coordinate_systems.insert(name, self.state.transform);
+            }
+            "CoordSysTransform" => {
+                let name = directive.string(0)?;
+                self.state.transform = *self.named_coordinate_systems.get(name)
+                    .ok_or_else(|| directive.error(format!("unknown coordinate system '{}'", name)))?;
+            }
+            "Camera" => self.load_camera(directive)?,
+            "Film" => {
+                let params = directive.params(1)?;
+                self.settings.width = positive(&params, "xresolution", 1280)?;
+                self.settings.height = positive(&params, "yresolution", 720)?;
+            }
+            "Sampler" => {
+                let params = directive.params(1)?;
+                self.settings.sample_count = positive(&params, "pixelsamples", 16)?;
+            }
+            "Integrator" => {
+                // pbrt counts bounces after the camera ray, the path tracer counts all rays
+                let params = directive.params(1)?;
+                self.settings.bounces = positive(&params, "maxdepth", 5)? + 1;
+            }
+            "WorldBegin" => {
+                self.state.transform = Matrix4::IDENTITY;
+                self.named_coordinate_systems.insert("world".to_string(), Matrix4::IDENTITY);
+            }
+            "WorldEnd" => (),
+            "AttributeBegin" => self.attribute_stack.push(self.state.clone()),
+            "AttributeEnd" => {
+                self.state = self.attribute_stack.pop().ok_or_else(|| directive.error("unmatched AttributeEnd"))?;
+            }
+            "TransformBegin" => self.transform_stack.push(self.state.transform),
+            "TransformEnd" => {
+                self.state.transform = self.transform_stack.pop().ok_or_else(|| directive.error("unmatched TransformEnd"))?;
+            }
+            "Material" => {
+                let params = directive.params(1)?;
+                self.state.material = Some(self.load_material(directive.string(0)?, &params)?);
+            }
+            "MakeNamedMaterial" => {
+                let params = directive.params(1)?;
+                let kind = params.string("type")?.ok_or_else(|| directive.error("named material without a type"))?;
+                let material = self.load_material(kind, &params)?;
+                self.named_materials.insert(directive.string(0)?.to_string(), material);
+            }
+            "NamedMaterial" => {
+                let name = directive.string(0)?;
+                let material = self.named_materials.get(name)
+                    .ok_or_else(|| directive.error(format!("unknown material '{}'", name)))?;
+                self.state.material = Some(*material);
+            }
+            "AreaLightSource" => {
+                let params = directive.params(1)?;
+                let kind = directive.string(0)?;
+                if kind != "diffuse" {
+                    eprintln!("pbrt: treating '{}' area light as diffuse", kind);
+                }
+
+                let color = params.color("L", Color3::new(1.0, 1.0, 1.0))? * params.color("scale", Color3::new(1.0, 1.0, 1.0))?;
+                self.state.area_light = Some(self.scene.add_material(LightEmitter { color }));
+            }
+            "LightSource" => self.load_light(directive)?,
+            "Shape" => {
+                if self.in_object {
+                    eprintln!("pbrt: skipping shape inside an object definition");
+                } else {
+                    self.load_shape(directive)?;
+                }
+            }
+            "ObjectBegin" => {
+                eprintln!("pbrt: object instancing is not supported, skipping '{}'", directive.string(0)?);
+                self.attribute_stack.push(self.state.clone());
+                self.in_object = true;
+            }
+            "ObjectEnd" => {
+                self.state = self.attribute_stack.pop().ok_or_else(|| directive.error("unmatched ObjectEnd"))?;
+                self.in_object = false;
+            }
+            "Include" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(directive.error("Include nested too deeply"));
+                }
+                let path = self.directory.join(directive.string(0)?);
+                self.load_file(&path, depth + 1)?;
+            }
+            "ReverseOrientation" | "PixelFilter" | "Accelerator" | "ObjectInstance" | "Texture"
+            | "MakeNamedMedium" | "MediumInterface" | "ActiveTransform" | "TransformTimes" => {
+                eprintln!("pbrt: ignoring unsupported directive {} at line {}", directive.name, directive.line);
+            }
+            other => return Err(directive.error(format!("unknown directive '{}'", other))),
+        }
+
+        Ok(())
+    }
+
+    /// Post-multiplies the current transform
+    fn apply(&mut self, transform: Matrix4) {
+        self.state.transform = self.state.transform * transform;
+    }
+
+    fn load_camera(&mut self, directive: &Directive) -> Result<()> {
+        let params = directive.params(1)?;
+        let kind = directive.string(0)?;
+        if kind != "perspective" {
+            eprintln!("pbrt: treating '{}' camera as perspective", kind);
+        }
+
+        // The current transform maps world space to camera space
+        let camera_to_world = self.state.transform.inverse()
+            .ok_or_else(|| directive.error("camera transform is not invertible"))?;
+        self.named_coordinate_systems.insert("camera".to_string(), camera_to_world);
+        self.camera = Some((camera_to_world, params.float("fov", 90.0)?));
+
+        Ok(())
+    }
+
+    fn default_material(&mut self) -> MaterialId {
+        let scene = &mut self.scene;
+        *self.default_material.get_or_insert_with(|| scene.add_material(Lambertian {
+            color: Color3::new(0.5, 0.5, 0.5),
+        }))
+    }
+
+    /// Material of the next shape, the area light takes precedence
+    fn current_material(&mut self) -> MaterialId {
+        match (self.state.area_light, self.state.material) {
+            (Some(material), _) | (None, Some(material)) => material,
+            (None, None) => self.default_material(),
+        }
+    }
+
+    fn load_material(&mut self, kind: &str, params: &Params) -> Result<MaterialId> {
+        let white = Color3::new(1.0, 1.0, 1.0);
+
+        let id = match kind {
+            "glass" => {
+                if params.float("uroughness", 0.0)? != 0.0 || params.float("vroughness", 0.0)? != 0.0 {
+                    eprintln!("pbrt: rough glass is rendered as smooth glass");
+                }
+
+                let ior = match params.get("eta") {
+                    Some(_) => params.float("eta", 1.5)?,
+                    None => params.float("index", 1.5)?,
+                };
+
+                self.scene.add_material(Dielectric {
+                    reflectance: params.color("Kr", white)?,
+                    transmittance: params.color("Kt", white)?,
+                    ior,
+                })
+            }
+            "metal" => {
+                let eta = params.color("eta", COPPER_ETA)?;
+                let k = params.color("k", COPPER_K)?;
+
+                // Reflectance at normal incidence of a conductor
+                let f0 = |eta: Float, k: Float| ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k);
+                let base_color = Color3::new(f0(eta.r, k.r), f0(eta.g, k.g), f0(eta.b, k.b));
+
+                let roughness = params.float("roughness", 0.01)?;
+                let roughness = (params.float("uroughness", roughness)? + params.float("vroughness", roughness)?) / 2.0;
+                let alpha = if params.bool("remaproughness", true)? {
+                    roughness_to_alpha(roughness)
+                } else {
+                    roughness
+                };
+
+                self.scene.add_material(MetallicRoughness {
+                    base_color,
+                    base_color_map: None,
+                    metallic: 1.0,
+                    roughness: alpha.sqrt(),
+                    emission: Color3::new(0.0, 0.0, 0.0),
+                })
+            }
+            "mirror" => self.scene.add_material(MetallicRoughness {
+                base_color: params.color("Kr", Color3::new(0.9, 0.9, 0.9))?,
+                base_color_map: None,
+                metallic: 1.0,
+                roughness: 0.0,
+                emission: Color3::new(0.0, 0.0, 0.0),
+            }),
+            _ => {
+                if kind != "matte" {
+                    eprintln!("pbrt: '{}' material is not supported, using matte", kind);
+                }
+
+                self.scene.add_material(Lambertian {
+                    color: params.color("Kd", Color3::new(0.5, 0.5, 0.5))?,
+                })
+            }
+        };
+
+        Ok(id)
+    }
+
+    fn load_light(&mut self, directive: &Directive) -> Result<()> {
+        let params = directive.params(1)?;
+        let scale = params.color("scale", Color3::new(1.0, 1.0, 1.0))?;
+
+        match directive.string(0)? {
+            kind @ ("point" | "spot") => {
+                if kind == "spot" {
+                    eprintln!("pbrt: spot light cone is ignored, emitting in all directions");
+                }
+
+                let from = params.vectors("from")?.first().copied().unwrap_or(Vector3::ZERO);
+                let intensity = params.color("I", Color3::new(1.0, 1.0, 1.0))? * scale;
+                self.scene.add_point_light(self.state.transform.transform_point(from), intensity);
+            }
+            "infinite" => match params.string("mapname")? {
+                Some(map) if map.to_ascii_lowercase().ends_with(".hdr") => {
+                    self.scene.sky = Some(Hdr::load(self.directory.join(map))?);
+                }
+                Some(map) => eprintln!("pbrt: environment map '{}' is not an HDR image, skipping", map),
+                None => self.scene.world_color = params.color("L", Color3::new(1.0, 1.0, 1.0))? * scale,
+            },
+            other => eprintln!("pbrt: skipping unsupported '{}' light", other),
+        }
+
+        Ok(())
+    }
+
+    fn load_shape(&mut self, directive: &Directive) -> Result<()> {
+        let params = directive.params(1)?;
+        let transform = self.state.transform;
+
+        match directive.string(0)? {
+            "sphere" => {
+                // Spheres stay spheres, so only uniform scaling is honored
+                let radius = params.float("radius", 1.0)? * transform.transform_vector(Vector3::X).len();
+                if !(radius > 0.0 && radius.is_finite()) {
+                    return Err(directive.error("sphere radius must be positive"));
+                }
+                let material = self.current_material();
+                self.scene.add_shape(Sphere {
+                    center: transform.transform_point(Vector3::ZERO),
+                    radius,
+                    material,
+                });
+            }
+            "trianglemesh" => {
+                let positions = params.vectors("P")?;
+                let normals = params.vectors("N")?;
+
+                let uvs: Vec<Vector2> = match params.numbers("uv")?.or(params.numbers("st")?) {
+                    Some(uvs) => uvs.chunks_exact(2).map(|uv| Vector2::new(uv[0], uv[1])).collect(),
+                    None => Vec::new(),
+                };
+
+                let indices: Vec<u32> = match params.numbers("indices")? {
+                    Some(indices) => {
+                        if let Some(index) = indices.iter().find(|index| **index < 0.0 || index.fract() != 0.0) {
+                            return Err(directive.error(format!("invalid trianglemesh index {}", index)));
+                        }
+                        indices.iter().map(|&index| index as u32).collect()
+                    }
+                    None if positions.len() == 3 => vec![0, 1, 2],
+                    None => return Err(directive.error("trianglemesh without indices")),
+                };
+
+                if !indices.len().is_multiple_of(3) {
+                    return Err(directive.error("trianglemesh index count must be a multiple of 3"));
+                }
+                if indices.iter().any(|&index| index as usize >= positions.len()) {
+                    return Err(directive.error("trianglemesh vertex index out of range"));
+                }
+                if !normals.is_empty() && normals.len() != positions.len()
+                    || !uvs.is_empty() && uvs.len() != positions.len()
+                {
+                    return Err(directive.error("trianglemesh attribute count mismatch"));
+                }
+
+                let mut data = MeshData {
+                    positions,
+                    normals,
+                    uvs,
+                    indices,
+                    material: self.current_material(),
+                };
+                data.transform(&transform);
+                self.scene.add_mesh(TriangleMesh::new(data)?);
+            }
+            "plymesh" => {
+                let file = params.string("filename")?.ok_or_else(|| directive.error("plymesh without a filename"))?;
+                let mut data = Ply::load(self.directory.join(file), self.current_material())?;
+                data.transform(&transform);
+                self.scene.add_mesh(TriangleMesh::new(data)?);
+            }
+            other => eprintln!("pbrt: skipping unsupported '{}' shape", other),
+        }
+
+        Ok(())
+    }
+
+    /// Places the camera once the film size, and thus the aspect ratio, is known
+    fn finish_camera(&mut self) {
+        let (camera_to_world, fov) = match self.camera {
+            Some(camera) => camera,
+            None => return,
+        };
+
+        // pbrt's field of view spans the shorter image axis
+        let aspect_ratio = self.settings.width as Float / self.settings.height as Float;
+        let fov = if aspect_ratio < 1.0 {
+            2.0 * ((fov.to_radians() / 2.0).tan() / aspect_ratio).atan().to_degrees()
+        } else {
+            fov
+        };
+
+        // Camera space looks down +Z with +Y up and +X to the right of the image
+        self.scene.camera = Camera::from_basis(
+            camera_to_world.transform_point(Vector3::ZERO),
+            camera_to_world.transform_vector(Vector3::new(0.0, 0.0, 1.0)).normalize(),
+            camera_to_world.transform_vector(Vector3::X).normalize(),
+            camera_to_world.transform_vector(Vector3::Y).normalize(),
+            fov,
+            aspect_ratio,
+        );
+    }
+}
+
+/// World-to-camera transform of pbrt's `LookAt` directive
+fn look_at(eye: Vector3, at: Vector3, up: Vector3) -> Option<Matrix4> {
+    // pbrt's camera space is left-handed
+    let forward = (at - eye).normalize();
+    let right = up.normalize().cross(forward);
+    if right.len() < 1e-9 {
+        return None;
+    }
+    let right = right.normalize();
+    let up = forward.cross(right);
+
+    let camera_to_world = Matrix4::new([
+        [right.x, up.x, forward.x, eye.x],
+        [right.y, up.y, forward.y, eye.y],
+        [right.z, up.z, forward.z, eye.z],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    camera_to_world.inverse()
+}
+
+/// Microfacet width for a perceptually linear roughness, as remapped by pbrt-v3
+fn roughness_to_alpha(roughness: Float) -> Float {
+    let x = max(roughness, 1e-3).ln();
+    1.62142 + 0.819955 * x + 0.1734 * x * x + 0.0171201 * x * x * x + 0.000640711 * x * x * x * x
+}
+
+fn positive(params: &Params, name: &str, default: usize) -> Result<usize> {
+    let value = params.float(name, default as Float)?;
+    if value < 1.0 || value.fract() != 0.0 {
+        return Err(params.directive.error(format!("'{}' must be a positive integer", name)));
+    }
+
+    Ok(value as usize)
+}
+
+pub struct Pbrt;
+
+impl Pbrt {
+    /// Loads a subset of the pbrt-v3 scene format
+    ///
+    /// Supports transforms, attribute blocks, perspective cameras, `trianglemesh`,
+    /// `sphere` and `plymesh` shapes, `matte`, `glass`, `metal` and `mirror` materials,
+    /// diffuse area lights and point, spot and infinite lights. Other materials become
+    /// matte and other unsupported features are skipped with a warning. Included files
+    /// and meshes are relative to the main file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(Scene, RenderSettings)> {
+        let path = path.as_ref();
+        let mut loader = Loader::new(path.parent().unwrap_or_else(|| Path::new("")).to_path_buf());
+
+        loader.load_file(path, 0)?;
+        loader.finish_camera();
+
+        Ok((loader.scene, loader.settings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::RandomGenerator;
+
+    fn load(text: &str) -> Result<Loader> {
+        let mut loader = Loader::new(PathBuf::new());
+        loader.load_text(text, "test.pbrt", 0)?;
+        loader.finish_camera();
+        Ok(loader)
+    }
+
+    /// Direction of the camera ray through image position `(u, v)`, scaled to unit depth
+    fn camera_ray(loader: &Loader, u: Float, v: Float) -> Vector3 {
+        let direction = loader.scene.camera.get_ray(u, v, &mut RandomGenerator::new()).direction;
+        direction / direction.z
+    }
+
+    fn assert_close(a: Vector3, b: Vector3) {
+        assert!((a - b).len() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn tokens_and_lines() {
+        let text = "Shape \"sphere\" # comment \"ignored\"\n  \"float radius\" [ -1.5e1 +2 .5 ]\nbool_flag true";
+        let tokens = tokenize(text, "test.pbrt").unwrap();
+
+        assert_eq!(tokens, [
+            (Token::Identifier("Shape".to_string()), 1),
+            (Token::String("sphere".to_string()), 1),
+            (Token::String("float radius".to_string()), 2),
+            (Token::OpenBracket, 2),
+            (Token::Number(-15.0), 2),
+            (Token::Number(2.0), 2),
+            (Token::Number(0.5), 2),
+            (Token::CloseBracket, 2),
+            (Token::Identifier("bool_flag".to_string()), 3),
+            (Token::String("true".to_string()), 3),
+        ]);
+    }
+
+    #[test]
+    fn tokenizer_errors() {
+        for (text, expected_line) in [("Shape\n\"sphere", 2), ("\n\nTranslate 1 2 3 @", 3), ("Scale 1.2.3 1 1", 1)] {
+            match tokenize(text, "test.pbrt") {
+                Err(Error::PbrtParseError { line, .. }) => assert_eq!(line, expected_line, "{}", text),
+                other => panic!("expected an error for {:?}, got {:?}", text, other),
+            }
+        }
+    }
+
+    #[test]
+    fn cyclic_include() {
+        let directory = std::env::temp_dir().join(format!("disquiet-pbrt-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("cycle.pbrt"), "Include \"cycle.pbrt\"\n").unwrap();
+
+        let result = Pbrt::load(directory.join("cycle.pbrt"));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        match result {
+            Err(Error::PbrtParseError { message, .. }) => assert_eq!(message, "Include nested too deeply"),
+            Err(other) => panic!("expected a nesting error, got {:?}", other),
+            Ok(_) => panic!("expected a nesting error"),
+        }
+    }
+
+    #[test]
+    fn look_at_is_left_handed() {
+        // Looking down +Z with +Y up puts +X on the right of the image
+        let loader = load("LookAt 0 0 0  0 0 1  0 1 0\nCamera \"perspective\" \"float fov\" 90\n\
+            Film \"image\" \"integer xresolution\" 200 \"integer yresolution\" 200\nWorldBegin\nWorldEnd").unwrap();
+
+        assert_close(camera_ray(&loader, 0.5, 0.5), Vector3::new(0.0, 0.0, 1.0));
+        assert_close(camera_ray(&loader, 1.0, 0.5), Vector3::new(1.0, 0.0, 1.0));
+        assert_close(camera_ray(&loader, 0.5, 0.0), Vector3::new(0.0, 1.0, 1.0));
+
+        let origin = loader.scene.camera.get_ray(0.5, 0.5, &mut RandomGenerator::new()).origin;
+        assert_close(origin, Vector3::ZERO);
+    }
+
+    #[test]
+    fn fov_spans_shorter_axis() {
+        let scene = |width, height| format!(
+            "LookAt 0 0 0  0 0 1  0 1 0\nCamera \"perspective\" \"float fov\" 90\n\
+             Film \"image\" \"integer xresolution\" {} \"integer yresolution\" {}\nWorldBegin\nWorldEnd",
+            width, height,
+        );
+
+        let landscape = load(&scene(200, 100)).unwrap();
+        assert_close(camera_ray(&landscape, 1.0, 0.5), Vector3::new(2.0, 0.0, 1.0));
+        assert_close(camera_ray(&landscape, 0.5, 0.0), Vector3::new(0.0, 1.0, 1.0));
+
+        let portrait = load(&scene(100, 200)).unwrap();
+        assert_close(camera_ray(&portrait, 1.0, 0.5), Vector3::new(1.0, 0.0, 1.0));
+        assert_close(camera_ray(&portrait, 0.5, 0.0), Vector3::new(0.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn trianglemesh_indices() {
+        let mesh = |indices: &str| format!(
+            "WorldBegin\nShape \"trianglemesh\" \"point P\" [0 0 0 1 0 0 0 1 0] \"integer indices\" [{}]\nWorldEnd",
+            indices,
+        );
+
+        let loader = load(&mesh("0 1 2")).unwrap();
+        assert_eq!(loader.scene.meshes()[0].data().indices, [0, 1, 2]);
+
+        assert!(load(&mesh("0 1 3")).is_err());
+        assert!(load(&mesh("0 -1 2")).is_err());
+        assert!(load(&mesh("0 0.5 2")).is_err());
+        assert!(load(&mesh("0 1")).is_err());
+    }
+
+    #[test]
+    fn sphere_radius() {
+        let sphere = |transform: &str, radius: &str| format!(
+            "WorldBegin\n{}\nShape \"sphere\" \"float radius\" [{}]\nWorldEnd",
+            transform, radius,
+        );
+
+        let loader = load(&sphere("Scale 2 2 2", "1.5")).unwrap();
+        assert_eq!(loader.scene.shapes().next().unwrap().bounding_box().max().x, 3.0);
+
+        for (transform, radius) in [("", "0"), ("", "-1"), ("", "1e999"), ("Scale 0 0 0", "1")] {
+            match load(&sphere(transform, radius)) {
+                Err(Error::PbrtParseError { line, message, .. }) => {
+                    assert_eq!((line, message.as_str()), (3, "sphere radius must be positive"));
+                }
+                Err(other) => panic!("expected a radius error, got {:?}", other),
+                Ok(_) => panic!("sphere with radius {} was accepted", radius),
+            }
+        }
+    }
+}
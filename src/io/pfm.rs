@@ -0,0 +1,183 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::color::Color3;
+use crate::error::*;
+use crate::image::Image;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// Larger headers are assumed to be corrupt rather than allocated
+const MAX_PIXELS: usize = 1 << 28;
+
+fn pfm_error(message: impl Into<String>) -> Error {
+    Error::PfmDecodingError(message.into())
+}
+
+/// Next whitespace-delimited header token, consuming exactly one whitespace byte after it
+fn token<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut token = Vec::new();
+
+    loop {
+        let byte = reader.read_u8()?;
+        if byte.is_ascii_whitespace() {
+            if token.is_empty() {
+                continue;
+            }
+            break;
+        }
+        token.push(byte);
+    }
+
+    String::from_utf8(token).map_err(|_| pfm_error("invalid header"))
+}
+
+fn read_pixels<R: BufRead, B: ByteOrder>(reader: &mut R, image: &mut Image, channels: usize) -> Result<()> {
+    let mut values = [0.0; 3];
+
+    // Scanlines are stored from bottom to top
+    for y in (0..image.height()).rev() {
+        for x in 0..image.width() {
+            for value in values.iter_mut().take(channels) {
+                *value = reader.read_f32::<B>()?.into();
+            }
+
+            let color = if channels == 1 {
+                Color3::new(values[0], values[0], values[0])
+            } else {
+                Color3::new(values[0], values[1], values[2])
+            };
+            image.set_pixel(x, y, color);
+        }
+    }
+
+    Ok(())
+}
+
+/// Portable float map, uncompressed 32-bit floating-point RGB or grayscale
+pub struct Pfm;
+
+impl Pfm {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Image> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Decodes color (`PF`) and grayscale (`Pf`) maps in either byte order
+    pub fn read<R: BufRead>(mut reader: R) -> Result<Image> {
+        let channels = match token(&mut reader)?.as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            other => return Err(pfm_error(format!("unknown format '{}'", other))),
+        };
+
+        let width: usize = token(&mut reader)?.parse().map_err(|_| pfm_error("invalid width"))?;
+        let height: usize = token(&mut reader)?.parse().map_err(|_| pfm_error("invalid height"))?;
+        let scale: f32 = token(&mut reader)?.parse().map_err(|_| pfm_error("invalid scale"))?;
+
+        if width == 0 || height == 0 {
+            return Err(pfm_error("image is empty"));
+        }
+        if width.checked_mul(height).is_none_or(|pixels| pixels > MAX_PIXELS) {
+            return Err(pfm_error(format!("image size {}x{} is too large", width, height)));
+        }
+        if scale == 0.0 || !scale.is_finite() {
+            return Err(pfm_error("invalid scale"));
+        }
+
+        // The sign of the scale gives the byte order, its magnitude is unused
+        let mut image = Image::new(width, height);
+        if scale < 0.0 {
+            read_pixels::<R, LittleEndian>(&mut reader, &mut image, channels)?;
+        } else {
+            read_pixels::<R, BigEndian>(&mut reader, &mut image, channels)?;
+        }
+
+        Ok(image)
+    }
+
+    pub fn save<P: AsRef<Path>>(image: &Image, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        Self::write(image, &mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Encodes a little-endian color map
+    pub fn write<W: Write>(image: &Image, mut writer: W) -> Result<()> {
+        write!(writer, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+
+        for y in (0..image.height()).rev() {
+            for x in 0..image.width() {
+                let color = image.get_pixel(x, y);
+                writer.write_f32::<LittleEndian>(color.r as f32)?;
+                writer.write_f32::<LittleEndian>(color.g as f32)?;
+                writer.write_f32::<LittleEndian>(color.b as f32)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Float;
+
+    fn gradient(width: usize, height: usize) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, Color3::new(x as Float * 0.25, y as Float * 100.0, -1.5));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = gradient(5, 3);
+        let mut data = Vec::new();
+        Pfm::write(&image, &mut data).unwrap();
+
+        let decoded = Pfm::read(&data[..]).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (5, 3));
+        for y in 0..3 {
+            for x in 0..5 {
+                assert_eq!(decoded.get_pixel(x, y), image.get_pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn big_endian_grayscale() {
+        let mut data = b"Pf\n2 2\n1.0\n".to_vec();
+        for value in [1.0f32, 2.0, 3.0, 4.0] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let image = Pfm::read(&data[..]).unwrap();
+        assert_eq!(image.get_pixel(0, 1), Color3::new(1.0, 1.0, 1.0));
+        assert_eq!(image.get_pixel(1, 0), Color3::new(4.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn truncated_data() {
+        let mut data = Vec::new();
+        Pfm::write(&gradient(4, 4), &mut data).unwrap();
+        data.truncate(data.len() - 1);
+
+        assert!(Pfm::read(&data[..]).is_err());
+    }
+
+    #[test]
+    fn oversized_header() {
+        for header in ["PF 4000000000 4000000000 -1.0 ", "PF\n18446744073709551615 2\n-1.0\n", "Pf 65536 65536 1.0 "] {
+            match Pfm::read(header.as_bytes()) {
+                Err(Error::PfmDecodingError(message)) => assert!(message.contains("too large"), "{}", message),
+                Err(other) => panic!("expected a size error for {:?}, got {:?}", header, other),
+                Ok(_) => panic!("expected a size error for {:?}", header),
+            }
+        }
+    }
+}
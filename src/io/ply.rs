@@ -0,0 +1,396 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use crate::error::*;
+use crate::math::*;
+use crate::scene::MaterialId;
+use crate::shape::MeshData;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::str::SplitAsciiWhitespace;
+
+fn ply_error(message: impl Into<String>) -> Error {
+    Error::PlyParseError(message.into())
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::Int8,
+            "uchar" | "uint8" => ScalarType::UInt8,
+            "short" | "int16" => ScalarType::Int16,
+            "ushort" | "uint16" => ScalarType::UInt16,
+            "int" | "int32" => ScalarType::Int32,
+            "uint" | "uint32" => ScalarType::UInt32,
+            "float" | "float32" => ScalarType::Float32,
+            "double" | "float64" => ScalarType::Float64,
+            _ => return Err(ply_error(format!("unknown property type '{}'", name))),
+        })
+    }
+}
+
+enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property_index(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|property| names.contains(&property.name.as_str()))
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+impl Header {
+    fn parse<R: BufRead>(reader: &mut R) -> Result<Header> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim() != "ply" {
+            return Err(ply_error("missing 'ply' magic"));
+        }
+
+        let mut format = None;
+        let mut elements: Vec<Element> = Vec::new();
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(ply_error("unexpected end of header"));
+            }
+
+            let mut tokens = line.split_ascii_whitespace();
+            let missing = || ply_error(format!("incomplete header line '{}'", line.trim()));
+
+            match tokens.next() {
+                Some("format") => {
+                    format = Some(match tokens.next().ok_or_else(missing)? {
+                        "ascii" => Format::Ascii,
+                        "binary_little_endian" => Format::BinaryLittleEndian,
+                        "binary_big_endian" => Format::BinaryBigEndian,
+                        other => return Err(ply_error(format!("unknown format '{}'", other))),
+                    });
+                }
+                Some("element") => {
+                    let name = tokens.next().ok_or_else(missing)?.to_string();
+                    let count = tokens.next().ok_or_else(missing)?.parse()?;
+                    elements.push(Element { name, count, properties: Vec::new() });
+                }
+                Some("property") => {
+                    let element = elements.last_mut().ok_or_else(|| ply_error("property before element"))?;
+                    let kind = match tokens.next().ok_or_else(missing)? {
+                        "list" => PropertyKind::List {
+                            count: ScalarType::parse(tokens.next().ok_or_else(missing)?)?,
+                            item: ScalarType::parse(tokens.next().ok_or_else(missing)?)?,
+                        },
+                        scalar => PropertyKind::Scalar(ScalarType::parse(scalar)?),
+                    };
+                    let name = tokens.next().ok_or_else(missing)?.to_string();
+                    element.properties.push(Property { name, kind });
+                }
+                Some("end_header") => break,
+                Some("comment") | Some("obj_info") | None => (),
+                Some(other) => return Err(ply_error(format!("unknown header keyword '{}'", other))),
+            }
+        }
+
+        let format = format.ok_or_else(|| ply_error("missing format"))?;
+        Ok(Header { format, elements })
+    }
+}
+
+/// Source of property values in the body of a PLY file
+trait ValueReader {
+    fn read(&mut self, scalar: ScalarType) -> Result<Float>;
+}
+
+struct AsciiReader<'a> {
+    tokens: SplitAsciiWhitespace<'a>,
+}
+
+impl ValueReader for AsciiReader<'_> {
+    fn read(&mut self, _scalar: ScalarType) -> Result<Float> {
+        let token = self.tokens.next().ok_or_else(|| ply_error("unexpected end of data"))?;
+        token.parse().map_err(|_| ply_error(format!("invalid number '{}'", token)))
+    }
+}
+
+struct BinaryReader<R, B> {
+    reader: R,
+    byte_order: PhantomData<B>,
+}
+
+impl<R: Read, B: ByteOrder> ValueReader for BinaryReader<R, B> {
+    fn read(&mut self, scalar: ScalarType) -> Result<Float> {
+        let reader = &mut self.reader;
+
+        Ok(match scalar {
+            ScalarType::Int8 => reader.read_i8()? as Float,
+            ScalarType::UInt8 => reader.read_u8()? as Float,
+            ScalarType::Int16 => reader.read_i16::<B>()? as Float,
+            ScalarType::UInt16 => reader.read_u16::<B>()? as Float,
+            ScalarType::Int32 => reader.read_i32::<B>()? as Float,
+            ScalarType::UInt32 => reader.read_u32::<B>()? as Float,
+            ScalarType::Float32 => reader.read_f32::<B>()? as Float,
+            ScalarType::Float64 => reader.read_f64::<B>()?,
+        })
+    }
+}
+
+/// Reads all elements, keeping vertex attributes and faces
+fn read_body<V: ValueReader>(header: &Header, values: &mut V, material: MaterialId) -> Result<MeshData> {
+    let mut mesh = MeshData {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        indices: Vec::new(),
+        material,
+    };
+
+    for element in &header.elements {
+        let mut row = vec![0.0; element.properties.len()];
+        let mut list = Vec::new();
+
+        let position = [
+            element.property_index(&["x"]),
+            element.property_index(&["y"]),
+            element.property_index(&["z"]),
+        ];
+        let normal = [
+            element.property_index(&["nx"]),
+            element.property_index(&["ny"]),
+            element.property_index(&["nz"]),
+        ];
+        let uv = [
+            element.property_index(&["u", "s", "texture_u", "texture_s"]),
+            element.property_index(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let indices = element.property_index(&["vertex_indices", "vertex_index"]);
+
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.kind {
+                    PropertyKind::Scalar(scalar) => row[i] = values.read(scalar)?,
+                    PropertyKind::List { count, item } => {
+                        let count = values.read(count)? as usize;
+                        // Only the face index list is kept
+                        let keep = Some(i) == indices;
+                        if keep {
+                            list.clear();
+                        }
+                        for _ in 0..count {
+                            let value = values.read(item)?;
+                            if keep {
+                                list.push(value);
+                            }
+                        }
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    if let [Some(x), Some(y), Some(z)] = position {
+                        mesh.positions.push(Vector3::new(row[x], row[y], row[z]));
+                    } else {
+                        return Err(ply_error("vertex element without x, y and z"));
+                    }
+
+                    if let [Some(x), Some(y), Some(z)] = normal {
+                        mesh.normals.push(Vector3::new(row[x], row[y], row[z]));
+                    }
+
+                    if let [Some(u), Some(v)] = uv {
+                        mesh.uvs.push(Vector2::new(row[u], row[v]));
+                    }
+                }
+                "face" if indices.is_some() => {
+                    if list.len() < 3 {
+                        return Err(ply_error("face with less than 3 vertices"));
+                    }
+                    if let Some(index) = list.iter().find(|index| **index < 0.0 || index.fract() != 0.0) {
+                        return Err(ply_error(format!("invalid face index {}", index)));
+                    }
+
+                    // Polygons are triangulated as fans
+                    for i in 1..list.len() - 1 {
+                        for &index in &[list[0], list[i], list[i + 1]] {
+                            mesh.indices.push(index as u32);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let vertex_count = mesh.positions.len();
+    if let Some(&index) = mesh.indices.iter().find(|&&index| index as usize >= vertex_count) {
+        return Err(ply_error(format!("face index {} out of range", index)));
+    }
+
+    Ok(mesh)
+}
+
+pub struct Ply;
+
+impl Ply {
+    /// Loads the vertices and faces of an ASCII or binary PLY file as triangle mesh data
+    ///
+    /// Normals and texture coordinates are kept when vertices have `nx/ny/nz` and `u/v`
+    /// (or `s/t`) properties, other elements and properties are skipped.
+    pub fn load<P: AsRef<Path>>(path: P, material: MaterialId) -> Result<MeshData> {
+        Ply::read(BufReader::new(File::open(path)?), material)
+    }
+
+    pub fn read<R: BufRead>(mut reader: R, material: MaterialId) -> Result<MeshData> {
+        let header = Header::parse(&mut reader)?;
+
+        match header.format {
+            Format::Ascii => {
+                let mut text = String::new();
+                reader.read_to_string(&mut text)?;
+                let mut values = AsciiReader { tokens: text.split_ascii_whitespace() };
+                read_body(&header, &mut values, material)
+            }
+            Format::BinaryLittleEndian => {
+                let mut values = BinaryReader { reader, byte_order: PhantomData::<LittleEndian> };
+                read_body(&header, &mut values, material)
+            }
+            Format::BinaryBigEndian => {
+                let mut values = BinaryReader { reader, byte_order: PhantomData::<BigEndian> };
+                read_body(&header, &mut values, material)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [[f32; 3]; 5] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.5, 2.0, -1.5]];
+
+    /// Encodes positions and faces, the face list uses uchar counts and int indices
+    fn encode(format: &str, positions: &[[f32; 3]], faces: &[&[i32]]) -> Vec<u8> {
+        let mut data = format!(
+            "ply\nformat {} 1.0\ncomment test\nelement vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+             element face {}\nproperty list uchar int vertex_indices\nend_header\n",
+            format, positions.len(), faces.len(),
+        ).into_bytes();
+
+        let big_endian = format == "binary_big_endian";
+        let push_f32 = |data: &mut Vec<u8>, value: f32| match format {
+            "ascii" => data.extend_from_slice(format!("{} ", value).as_bytes()),
+            _ if big_endian => data.extend_from_slice(&value.to_be_bytes()),
+            _ => data.extend_from_slice(&value.to_le_bytes()),
+        };
+
+        for position in positions {
+            for &value in position {
+                push_f32(&mut data, value);
+            }
+        }
+
+        for face in faces {
+            if format == "ascii" {
+                let indices: Vec<String> = face.iter().map(i32::to_string).collect();
+                data.extend_from_slice(format!("\n{} {}", face.len(), indices.join(" ")).as_bytes());
+                continue;
+            }
+
+            data.push(face.len() as u8);
+            for &index in face.iter() {
+                let bytes = if big_endian { index.to_be_bytes() } else { index.to_le_bytes() };
+                data.extend_from_slice(&bytes);
+            }
+        }
+
+        data
+    }
+
+    fn read(data: &[u8]) -> Result<MeshData> {
+        Ply::read(data, 7)
+    }
+
+    #[test]
+    fn formats_agree() {
+        let faces: [&[i32]; 2] = [&[0, 1, 2, 3], &[3, 2, 4]];
+
+        for format in ["ascii", "binary_little_endian", "binary_big_endian"] {
+            let mesh = read(&encode(format, &POSITIONS, &faces)).unwrap();
+            assert_eq!(mesh.material, 7);
+            assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3, 3, 2, 4], "{}", format);
+
+            let positions: Vec<[Float; 3]> = mesh.positions.iter().map(|p| [p.x, p.y, p.z]).collect();
+            assert_eq!(positions, [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.5, 2.0, -1.5]], "{}", format);
+            assert!(mesh.normals.is_empty() && mesh.uvs.is_empty());
+        }
+    }
+
+    #[test]
+    fn vertex_attributes() {
+        let data = b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            property float nx\nproperty float ny\nproperty float nz\nproperty float s\nproperty float t\n\
+            element face 1\nproperty uchar flags\nproperty list uchar uint vertex_index\nproperty list uchar float extra\n\
+            end_header\n0 0 0 0 0 1 0 0\n1 0 0 0 0 1 1 0\n0 1 0 0 0 1 0 1\n5 3 0 1 2 2 9.5 9.5\n";
+
+        let mesh = read(data).unwrap();
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.normals.len(), 3);
+        assert_eq!(mesh.normals[2].z, 1.0);
+        assert_eq!(mesh.uvs, [Vector2::new(0.0, 0.0), Vector2::new(1.0, 0.0), Vector2::new(0.0, 1.0)]);
+    }
+
+    #[test]
+    fn invalid_faces() {
+        for format in ["ascii", "binary_little_endian"] {
+            assert!(read(&encode(format, &POSITIONS, &[&[0, 1, 5]])).is_err(), "{}", format);
+            assert!(read(&encode(format, &POSITIONS, &[&[0, -1, 2]])).is_err(), "{}", format);
+            assert!(read(&encode(format, &POSITIONS, &[&[0, 1, 2], &[3, 4]])).is_err(), "{}", format);
+        }
+    }
+
+    #[test]
+    fn truncated_data() {
+        let mut data = encode("binary_big_endian", &POSITIONS, &[&[0, 1, 2]]);
+        data.pop();
+        assert!(read(&data).is_err());
+
+        assert!(read(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n").is_err());
+        assert!(read(b"obj\n").is_err());
+    }
+}
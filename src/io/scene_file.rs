@@ -0,0 +1,474 @@
+use crate::camera::Camera;
+use crate::color::Color3;
+use crate::error::*;
+use crate::io::{Gltf, Hdr, Json, Obj, Ply};
+use crate::material::*;
+use crate::math::*;
+use crate::scene::*;
+use crate::shape::plane::Plane;
+use crate::shape::{Sphere, Triangle, TriangleMesh};
+use crate::texture::Texture;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Render parameters stored alongside the scene
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub sample_count: usize,
+    pub bounces: usize,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            sample_count: 16,
+            bounces: 4,
+        }
+    }
+}
+
+fn scene_error(context: &str, message: impl AsRef<str>) -> Error {
+    Error::SceneFileError(format!("{}: {}", context, message.as_ref()))
+}
+
+fn field<'a>(json: &'a Json, key: &str, context: &str) -> Result<&'a Json> {
+    json.get(key).ok_or_else(|| scene_error(context, format!("missing '{}'", key)))
+}
+
+fn float(json: &Json, key: &str, context: &str) -> Result<Float> {
+    field(json, key, context)?
+        .as_f64()
+        .ok_or_else(|| scene_error(context, format!("'{}' must be a number", key)))
+}
+
+fn float_or(json: &Json, key: &str, default: Float, context: &str) -> Result<Float> {
+    match json.get(key) {
+        Some(_) => float(json, key, context),
+        None => Ok(default),
+    }
+}
+
+fn count_or(json: &Json, key: &str, default: usize, context: &str) -> Result<usize> {
+    match json.get(key) {
+        Some(value) => value.as_usize()
+            .filter(|&count| count > 0)
+            .ok_or_else(|| scene_error(context, format!("'{}' must be a positive integer", key))),
+        None => Ok(default),
+    }
+}
+
+fn radius(json: &Json, context: &str) -> Result<Float> {
+    match float(json, "radius", context)? {
+        radius if radius > 0.0 && radius.is_finite() => Ok(radius),
+        _ => Err(scene_error(context, "radius must be positive")),
+    }
+}
+
+fn string<'a>(json: &'a Json, key: &str, context: &str) -> Result<&'a str> {
+    field(json, key, context)?
+        .as_str()
+        .ok_or_else(|| scene_error(context, format!("'{}' must be a string", key)))
+}
+
+fn triple(json: &Json, key: &str, context: &str) -> Result<[Float; 3]> {
+    let values = json.as_array().filter(|values| values.len() == 3);
+    let values = values.ok_or_else(|| scene_error(context, format!("'{}' must be an array of 3 numbers", key)))?;
+
+    let mut result = [0.0; 3];
+    for (value, json) in result.iter_mut().zip(values) {
+        *value = json.as_f64().ok_or_else(|| scene_error(context, format!("'{}' must be an array of 3 numbers", key)))?;
+    }
+
+    Ok(result)
+}
+
+fn vector(json: &Json, key: &str, context: &str) -> Result<Vector3> {
+    let [x, y, z] = triple(field(json, key, context)?, key, context)?;
+    Ok(Vector3::new(x, y, z))
+}
+
+fn color(json: &Json, key: &str, context: &str) -> Result<Color3> {
+    let [r, g, b] = triple(field(json, key, context)?, key, context)?;
+    Ok(Color3::new(r, g, b))
+}
+
+fn color_or(json: &Json, key: &str, default: Color3, context: &str) -> Result<Color3> {
+    match json.get(key) {
+        Some(_) => color(json, key, context),
+        None => Ok(default),
+    }
+}
+
+struct Loader<'a> {
+    directory: &'a Path,
+    scene: Scene,
+    materials: HashMap<String, MaterialId>,
+    // Aspect ratio for a glTF camera, `None` once the scene has a camera
+    camera_aspect_ratio: Option<Float>,
+}
+
+impl Loader<'_> {
+    fn load_settings(&self, json: Option<&Json>) -> Result<RenderSettings> {
+        let mut settings = RenderSettings::default();
+        let json = match json {
+            Some(json) => json,
+            None => return Ok(settings),
+        };
+
+        let context = "settings";
+        settings.width = count_or(json, "width", settings.width, context)?;
+        settings.height = count_or(json, "height", settings.height, context)?;
+        settings.sample_count = count_or(json, "samples", settings.sample_count, context)?;
+        settings.bounces = count_or(json, "bounces", settings.bounces, context)?;
+
+        Ok(settings)
+    }
+
+    fn load_camera(&mut self, json: &Json, settings: &RenderSettings) -> Result<()> {
+        let context = "camera";
+        let position = vector(json, "position", context)?;
+        let look_at = vector(json, "look_at", context)?;
+        let up = match json.get("up") {
+            Some(_) => vector(json, "up", context)?,
+            None => Vector3::Y,
+        };
+        let fov = float_or(json, "fov", 70.0, context)?;
+
+        let aspect_ratio = settings.width as Float / settings.height as Float;
+        self.scene.camera = Camera::look_at(position, look_at, up, fov, aspect_ratio);
+        self.camera_aspect_ratio = None;
+
+        Ok(())
+    }
+
+    fn load_material(&mut self, name: &str, json: &Json) -> Result<MaterialId> {
+        let context = &format!("material '{}'", name);
+
+        let texture = match json.get("texture") {
+            Some(_) => Some(Arc::new(Texture::load(self.directory.join(string(json, "texture", context)?))?)),
+            None => None,
+        };
+
+        let white = Color3::new(1.0, 1.0, 1.0);
+        let black = Color3::new(0.0, 0.0, 0.0);
+
+        let id = match string(json, "type", context)? {
+            "lambertian" => self.scene.add_material(Lambertian {
+                color: color(json, "color", context)?,
+            }),
+            "phong" => self.scene.add_material(Phong {
+                diffuse: color_or(json, "diffuse", white, context)?,
+                diffuse_map: texture,
+                specular: color_or(json, "specular", black, context)?,
+                exponent: float_or(json, "exponent", 1.0, context)?,
+            }),
+            "metallic_roughness" => self.scene.add_material(MetallicRoughness {
+                base_color: color_or(json, "base_color", white, context)?,
+                base_color_map: texture,
+                metallic: float_or(json, "metallic", 0.0, context)?,
+                roughness: float_or(json, "roughness", 0.5, context)?,
+                emission: color_or(json, "emission", black, context)?,
+            }),
+            "emitter" => self.scene.add_material(LightEmitter {
+                color: color(json, "color", context)?,
+            }),
+            other => return Err(scene_error(context, format!("unknown material type '{}'", other))),
+        };
+
+        Ok(id)
+    }
+
+    fn material(&self, json: &Json, context: &str) -> Result<MaterialId> {
+        let name = string(json, "material", context)?;
+        self.materials.get(name)
+            .copied()
+            .ok_or_else(|| scene_error(context, format!("unknown material '{}'", name)))
+    }
+
+    fn load_shape(&mut self, json: &Json, context: &str) -> Result<()> {
+        match string(json, "type", context)? {
+            "sphere" => {
+                let sphere = Sphere {
+                    center: vector(json, "center", context)?,
+                    radius: radius(json, context)?,
+                    material: self.material(json, context)?,
+                };
+                self.scene.add_shape(sphere);
+            }
+            "plane" => {
+                let normal = vector(json, "normal", context)?;
+                if normal.len_squared() == 0.0 {
+                    return Err(scene_error(context, "normal must not be zero"));
+                }
+
+                let plane = Plane {
+                    point: vector(json, "point", context)?,
+                    normal: normal.normalize(),
+                    material: self.material(json, context)?,
+                };
+                self.scene.add_shape(plane);
+            }
+            "triangle" => {
+                let a = vector(json, "a", context)?;
+                let b = vector(json, "b", context)?;
+                let c = vector(json, "c", context)?;
+                let normal = (b - a).cross(c - a);
+                if normal.len_squared() == 0.0 {
+                    return Err(scene_error(context, "triangle has zero area"));
+                }
+                let normal = normal.normalize();
+
+                let triangle = Triangle {
+                    a,
+                    b,
+                    c,
+                    na: normal,
+                    nb: normal,
+                    nc: normal,
+                    material: self.material(json, context)?,
+                };
+                self.scene.add_shape(triangle);
+            }
+            "mesh" => {
+                let file = self.directory.join(string(json, "file", context)?);
+                let extension = file.extension()
+                    .and_then(|extension| extension.to_str())
+                    .map(str::to_ascii_lowercase);
+
+                // OBJ and glTF files bring their own materials
+                match extension.as_deref() {
+                    Some("obj") => Obj::load(&file, &mut self.scene)?,
+                    Some("gltf") | Some("glb") => {
+                        if Gltf::load(&file, &mut self.scene, self.camera_aspect_ratio)? {
+                            self.camera_aspect_ratio = None;
+                        }
+                    }
+                    Some("ply") => {
+                        let data = Ply::load(&file, self.material(json, context)?)?;
+                        self.scene.add_mesh(TriangleMesh::new(data)?);
+                    }
+                    _ => return Err(scene_error(context, format!("unsupported mesh file '{}'", file.display()))),
+                }
+            }
+            other => return Err(scene_error(context, format!("unknown shape type '{}'", other))),
+        }
+
+        Ok(())
+    }
+
+    /// Lights are emissive spheres with their own material
+    fn load_light(&mut self, json: &Json, context: &str) -> Result<()> {
+        match string(json, "type", context)? {
+            "sphere" => {
+                let material = self.scene.add_material(LightEmitter {
+                    color: color(json, "color", context)?,
+                });
+
+                self.scene.add_shape(Sphere {
+                    center: vector(json, "center", context)?,
+                    radius: radius(json, context)?,
+                    material,
+                });
+            }
+            other => return Err(scene_error(context, format!("unknown light type '{}'", other))),
+        }
+
+        Ok(())
+    }
+}
+
+pub struct SceneFile;
+
+impl SceneFile {
+    /// Loads a JSON scene description
+    ///
+    /// The top-level object may contain `settings`, `camera`, `sky` (path to an HDR),
+    /// `background` (a color), `materials` (an object mapping names to materials),
+    /// `shapes` and `lights`. Paths are relative to the scene file.
+    ///
+    /// `settings` holds `width`, `height`, `samples` and `bounces`, all positive integers.
+    /// `bounces` limits the number of rays in a path including the camera ray, so the
+    /// smallest value of 1 only shows emitters seen directly.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(Scene, RenderSettings)> {
+        let path = path.as_ref();
+        Self::parse(&std::fs::read_to_string(path)?, path.parent().unwrap_or_else(|| Path::new("")))
+    }
+
+    /// Loads a scene description from text, resolving paths relative to `directory`
+    pub fn parse(text: &str, directory: &Path) -> Result<(Scene, RenderSettings)> {
+        let root = Json::parse(text)?;
+        if root.as_object().is_none() {
+            return Err(scene_error("scene", "top level must be an object"));
+        }
+
+        let mut loader = Loader {
+            directory,
+            scene: Scene::new(),
+            materials: HashMap::new(),
+            camera_aspect_ratio: None,
+        };
+
+        let settings = loader.load_settings(root.get("settings"))?;
+        loader.camera_aspect_ratio = Some(settings.width as Float / settings.height as Float);
+
+        if let Some(camera) = root.get("camera") {
+            loader.load_camera(camera, &settings)?;
+        }
+
+        if let Some(background) = root.get("background") {
+            let [r, g, b] = triple(background, "background", "scene")?;
+            loader.scene.world_color = Color3::new(r, g, b);
+        }
+
+        if root.get("sky").is_some() {
+            let sky = string(&root, "sky", "scene")?;
+            loader.scene.sky = Some(Hdr::load(loader.directory.join(sky))?);
+        }
+
+        if let Some(materials) = root.get("materials") {
+            let materials = materials.as_object().ok_or_else(|| scene_error("materials", "must be an object"))?;
+            for (name, material) in materials {
+                let id = loader.load_material(name, material)?;
+                loader.materials.insert(name.clone(), id);
+            }
+        }
+
+        if let Some(shapes) = root.get("shapes") {
+            let shapes = shapes.as_array().ok_or_else(|| scene_error("shapes", "must be an array"))?;
+            for (i, shape) in shapes.iter().enumerate() {
+                loader.load_shape(shape, &format!("shapes[{}]", i))?;
+            }
+        }
+
+        if let Some(lights) = root.get("lights") {
+            let lights = lights.as_array().ok_or_else(|| scene_error("lights", "must be an array"))?;
+            for (i, light) in lights.iter().enumerate() {
+                loader.load_light(light, &format!("lights[{}]", i))?;
+            }
+        }
+
+        Ok((loader.scene, settings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::RandomGenerator;
+
+    fn parse(text: &str) -> Result<(Scene, RenderSettings)> {
+        SceneFile::parse(text, Path::new(""))
+    }
+
+    fn error(text: &str) -> String {
+        match parse(text) {
+            Err(Error::SceneFileError(message)) => message,
+            Err(error) => panic!("unexpected error {}", error),
+            Ok(_) => panic!("invalid scene was accepted: {}", text),
+        }
+    }
+
+    /// Scene with a single shape or light, using a material named "white"
+    fn with(key: &str, object: &str) -> String {
+        format!(r#"{{"materials": {{"white": {{"type": "lambertian", "color": [1, 1, 1]}}}}, "{}": [{}]}}"#, key, object)
+    }
+
+    #[test]
+    fn valid_scene() {
+        let (scene, settings) = parse(r#"{
+            "settings": {"width": 320, "height": 240, "samples": 8, "bounces": 6},
+            "camera": {"position": [0, 1, -5], "look_at": [0, 1, 0], "fov": 60},
+            "background": [0.1, 0.2, 0.3],
+            "materials": {
+                "white": {"type": "lambertian", "color": [0.8, 0.8, 0.8]},
+                "shiny": {"type": "phong", "specular": [0.5, 0.5, 0.5], "exponent": 20}
+            },
+            "shapes": [
+                {"type": "sphere", "center": [0, 1, 0], "radius": 1, "material": "shiny"},
+                {"type": "plane", "point": [0, 0, 0], "normal": [0, 2, 0], "material": "white"},
+                {"type": "triangle", "a": [-1, 0, 2], "b": [1, 0, 2], "c": [0, 2, 2], "material": "white"}
+            ],
+            "lights": [{"type": "sphere", "center": [0, 5, 0], "radius": 0.5, "color": [10, 10, 10]}]
+        }"#).unwrap();
+
+        assert_eq!((settings.width, settings.height, settings.sample_count, settings.bounces), (320, 240, 8, 6));
+        assert_eq!(scene.world_color, Color3::new(0.1, 0.2, 0.3));
+        assert_eq!(scene.shapes().count(), 4);
+
+        let ray = scene.camera.get_ray(0.5, 0.5, &mut RandomGenerator::new());
+        assert_eq!(ray.origin.z, -5.0);
+        assert!(ray.direction.z > 0.99);
+
+        // Only the light sphere emits
+        let (light, probability) = scene.sample_light(&mut RandomGenerator::new()).unwrap();
+        assert_eq!(light.bounding_box().center().y, 5.0);
+        assert_eq!(probability, 1.0);
+
+        // Everything is optional
+        let (scene, settings) = parse("{}").unwrap();
+        assert_eq!(scene.shapes().count(), 0);
+        assert_eq!(settings.width, RenderSettings::default().width);
+    }
+
+    #[test]
+    fn unknown_names() {
+        let sphere = r#"{"type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "black"}"#;
+        assert_eq!(error(&with("shapes", sphere)), "shapes[0]: unknown material 'black'");
+
+        assert_eq!(error(&with("shapes", r#"{"type": "cube", "material": "white"}"#)), "shapes[0]: unknown shape type 'cube'");
+        assert_eq!(error(&with("lights", r#"{"type": "spot", "color": [1, 1, 1]}"#)), "lights[0]: unknown light type 'spot'");
+        assert_eq!(
+            error(r#"{"materials": {"glass": {"type": "dielectric"}}}"#),
+            "material 'glass': unknown material type 'dielectric'",
+        );
+        assert!(error(&with("shapes", r#"{"type": "mesh", "file": "mesh.stl", "material": "white"}"#)).contains("unsupported mesh file"));
+    }
+
+    #[test]
+    fn wrong_types() {
+        let sphere = |fields: &str| with("shapes", &format!(r#"{{"type": "sphere", "material": "white", {}}}"#, fields));
+
+        assert_eq!(error(&sphere(r#""center": [0, 0, 0], "radius": "1""#)), "shapes[0]: 'radius' must be a number");
+        assert_eq!(error(&sphere(r#""center": [0, 0], "radius": 1"#)), "shapes[0]: 'center' must be an array of 3 numbers");
+        assert_eq!(error(&sphere(r#""center": [0, "0", 0], "radius": 1"#)), "shapes[0]: 'center' must be an array of 3 numbers");
+        assert_eq!(error(&sphere(r#""radius": 1"#)), "shapes[0]: missing 'center'");
+        assert_eq!(error(&with("shapes", r#"{"type": 1}"#)), "shapes[0]: 'type' must be a string");
+
+        assert_eq!(error(r#"{"shapes": {}}"#), "shapes: must be an array");
+        assert_eq!(error(r#"{"materials": []}"#), "materials: must be an object");
+        assert_eq!(error(r#"{"background": 1}"#), "scene: 'background' must be an array of 3 numbers");
+        assert_eq!(error("[]"), "scene: top level must be an object");
+    }
+
+    #[test]
+    fn invalid_geometry() {
+        for radius in ["0", "-0.5", "1e999"] {
+            let sphere = format!(r#"{{"type": "sphere", "center": [0, 0, 0], "radius": {}, "material": "white"}}"#, radius);
+            assert_eq!(error(&with("shapes", &sphere)), "shapes[0]: radius must be positive");
+
+            let light = format!(r#"{{"type": "sphere", "center": [0, 0, 0], "radius": {}, "color": [1, 1, 1]}}"#, radius);
+            assert_eq!(error(&with("lights", &light)), "lights[0]: radius must be positive");
+        }
+
+        let plane = r#"{"type": "plane", "point": [0, 0, 0], "normal": [0, 0, 0], "material": "white"}"#;
+        assert_eq!(error(&with("shapes", plane)), "shapes[0]: normal must not be zero");
+
+        let triangle = r#"{"type": "triangle", "a": [0, 0, 0], "b": [1, 1, 1], "c": [2, 2, 2], "material": "white"}"#;
+        assert_eq!(error(&with("shapes", triangle)), "shapes[0]: triangle has zero area");
+    }
+
+    #[test]
+    fn settings_counts() {
+        for (field, value) in [("width", "0"), ("height", "-480"), ("samples", "2.5"), ("bounces", "0"), ("bounces", "\"4\"")] {
+            let text = format!(r#"{{"settings": {{"{}": {}}}}}"#, field, value);
+            assert_eq!(error(&text), format!("settings: '{}' must be a positive integer", field));
+        }
+
+        assert_eq!(parse(r#"{"settings": {"bounces": 1}}"#).unwrap().1.bounces, 1);
+    }
+}
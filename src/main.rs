@@ -5,107 +5,38 @@ mod accelerator;
 mod camera;
 mod color;
 mod error;
+mod film;
+mod filter;
 mod image;
 mod integrator;
 mod io;
 mod material;
 mod math;
+mod options;
 mod random;
 mod scene;
 mod shape;
+mod texture;
 
 use std::fs::File;
 
 use crate::accelerator::*;
 use crate::camera::*;
 use crate::color::*;
+use crate::film::*;
+use crate::filter::*;
 use crate::image::*;
 use crate::integrator::*;
 use crate::io::*;
 use crate::material::*;
 use crate::math::*;
+use crate::options::*;
 use crate::random::*;
 use crate::scene::*;
 use crate::shape::*;
 use crossbeam_deque::{Injector, Steal};
 use std::time::Instant;
 
-fn build_scene() -> Scene {
-    let origin = Vector3::new(-1.5, 9.0, 0.1);
-    let at = Vector3::new(-1.5, 0.0, 0.0);
-    let up = Vector3::new(0.0, 1.0, 0.0);
-    let fov = 90.0;
-    let aspect_ratio = 16.0 / 9.0;
-
-    let mut scene = Scene::new();
-    scene.camera = Camera::look_at(origin, at, up, fov, aspect_ratio);
-
-    // scene.sky = Some(Hdr::load("spiaggia_di_mondello_4k.hdr").unwrap());
-    // scene.sky = Some(load("blaubeuren_night_4k.hdr").unwrap());
-    // scene.sky = Some(load("blaubeuren_night_4k.hdr").unwrap());
-
-    let red = scene.add_material(Lambertian {
-        color: Color3::new(1.0, 0.1, 0.1),
-    });
-
-    let green = scene.add_material(Lambertian {
-        color: Color3::new(0.1, 0.9, 0.1),
-    });
-
-    let white = scene.add_material(Lambertian {
-        color: Color3::new(1.0, 1.0, 1.0),
-    });
-
-    let light = scene.add_material(LightEmitter {
-        color: Color3::new(10.0, 10.0, 10.0),
-    });
-
-    scene.add_shape(Sphere {
-        center: Vector3::new(0.5, -0.2, -0.5),
-        radius: 0.3,
-        material: green,
-    });
-    scene.add_shape(Sphere {
-        center: Vector3::new(0.0, 0.0, 3.0),
-        radius: 0.5,
-        material: red,
-    });
-    scene.add_shape(Sphere {
-        center: Vector3::new(-1.5, 0.0, 0.0),
-        radius: 0.5,
-        material: white,
-    });
-
-    scene.add_shape(crate::shape::plane::Plane {
-        point: Vector3::new(0.0, -0.5, 0.0),
-        normal: Vector3::new(0.0, 1.0, 0.0),
-        material: white,
-    });
-
-    scene.add_shape(Sphere {
-        center: Vector3::new(-3.0, 2.0, -3.0),
-        radius: 0.5,
-        material: light,
-    });
-    scene.add_shape(Sphere {
-        center: Vector3::new(3.0, 2.0, -3.0),
-        radius: 0.5,
-        material: light,
-    });
-    scene.add_shape(Sphere {
-        center: Vector3::new(3.0, 2.0, 3.0),
-        radius: 0.5,
-        material: light,
-    });
-    scene.add_shape(Sphere {
-        center: Vector3::new(-3.0, 2.0, 3.0),
-        radius: 0.5,
-        material: light,
-    });
-
-    scene
-}
-
 pub struct RendererInput<'a, A: Accelerator> {
     scene: &'a Scene,
     accel: &'a A,
@@ -114,9 +45,20 @@ pub struct RendererInput<'a, A: Accelerator> {
     height: usize,
     tile_size: usize,
     thread_count: usize,
+    seed: u64,
+    filter: FilterKind,
+    filter_radius: Option<Float>,
+    aovs: bool,
+}
+
+/// Radiance film and the optional first-hit albedo and normal films
+pub struct Films {
+    color: Film,
+    aovs: Option<(Film, Film)>,
 }
 
 pub struct TileInfo {
+    index: usize,
     x: usize,
     y: usize,
     width: usize,
@@ -137,13 +79,20 @@ impl<I: Integrator> Renderer<I> {
         }
     }
 
-    unsafe fn render_tile<A: Accelerator>(&mut self, input: &RendererInput<A>, image: &RgbaImage, tile: TileInfo) {
+    fn render_tile<A: Accelerator>(&mut self, input: &RendererInput<A>, films: &Films, tile: TileInfo) {
+        // Seeding per tile keeps the image independent of which thread rendered what
+        self.rng = RandomGenerator::for_stream(input.seed, tile.index as u64);
+        let film = &films.color;
+        let mut film_tile = film.tile(tile.x, tile.y, tile.width, tile.height);
+        let mut aov_tiles = films.aovs.as_ref().map(|(albedo, normal)| (
+            albedo.tile(tile.x, tile.y, tile.width, tile.height),
+            normal.tile(tile.x, tile.y, tile.width, tile.height),
+        ));
+
         for y in tile.y..tile.y+tile.height {
             for x in tile.x..tile.x+tile.width {
-                let width = image.width() as Float;
-                let height = image.height() as Float;
-
-                let mut color = Color3::new(0.0, 0.0, 0.0);
+                let width = film.width() as Float;
+                let height = film.height() as Float;
 
                 for _ in 0..input.sample_count {
                     let jitter_u = self.rng.unit();
@@ -153,22 +102,41 @@ impl<I: Integrator> Renderer<I> {
                     let v = (y as Float + jitter_v) / height;
 
                     let ray = input.scene.camera.get_ray(u, v, &mut self.rng);
-                    color = color + self.integrator.integrate(input.scene, &ray, input.accel).color;
+                    let output = self.integrator.integrate(input.scene, &ray, input.accel, &mut self.rng);
+                    let position = Vector2::new(x as Float + jitter_u, y as Float + jitter_v);
+                    film_tile.add_sample(position, output.color);
+
+                    if let Some((albedo, normal)) = &mut aov_tiles {
+                        albedo.add_sample(position, output.albedo);
+                        let n = output.normal;
+                        normal.add_sample(position, Color3::new(n.x, n.y, n.z));
+                    }
                 }
-
-                image.set_pixel_unsafe(x, y, (color / input.sample_count as f64).into());
             }
         }
+
+        film.merge_tile(film_tile);
+        if let (Some((albedo, normal)), Some((albedo_tile, normal_tile))) = (&films.aovs, aov_tiles) {
+            albedo.merge_tile(albedo_tile);
+            normal.merge_tile(normal_tile);
+        }
     }
 }
 
-fn render<I: Integrator + Clone, A: Accelerator>(integrator: I, input: RendererInput<A>) -> RgbaImage {
-    let image = RgbaImage::new(input.width, input.height);
+fn render<I: Integrator + Clone, A: Accelerator>(integrator: I, input: RendererInput<A>) -> Films {
+    let filter = || input.filter.create(input.filter_radius);
+    let films = Films {
+        color: Film::new(input.width, input.height, filter()),
+        aovs: input.aovs.then(|| (
+            Film::new(input.width, input.height, filter()),
+            Film::new_signed(input.width, input.height, filter()),
+        )),
+    };
 
     let queue = Injector::new();
 
-    let tiles_x = input.width / input.tile_size + if input.width % input.tile_size == 0 { 0 } else { 1 };
-    let tiles_y = input.height / input.tile_size + if input.height % input.tile_size == 0 { 0 } else { 1 };
+    let tiles_x = input.width.div_ceil(input.tile_size);
+    let tiles_y = input.height.div_ceil(input.tile_size);
 
     for tile_y in 0..tiles_y {
         for tile_x in 0..tiles_x {
@@ -177,64 +145,149 @@ fn render<I: Integrator + Clone, A: Accelerator>(integrator: I, input: RendererI
             let width = input.tile_size.min(input.width - x);
             let height = input.tile_size.min(input.height - y);
 
-            queue.push(TileInfo { x, y, width, height });
+            queue.push(TileInfo { index: tile_y * tiles_x + tile_x, x, y, width, height });
         }
     }
 
-    let mut renderers = std::iter::repeat(Renderer::new(integrator))
-        .take(input.thread_count)
+    let mut renderers = std::iter::repeat_n(Renderer::new(integrator), input.thread_count)
         .collect::<Vec<_>>();
 
     crossbeam_utils::thread::scope(|scope| for _ in 0..input.thread_count {
-        let ref queue = queue;
-        let ref image = image;
-        let ref input = input;
+        let queue = &queue;
+        let films = &films;
+        let input = &input;
         let mut renderer = renderers.pop().unwrap();
         scope.spawn(move |_| {
             while let Steal::Success(tile) = queue.steal() {
                 println!("Rendering tile ({},{})", tile.x, tile.y);
-                unsafe {
-                    renderer.render_tile(&input, &image, tile);
-                }
+                renderer.render_tile(input, films, tile);
             }
             renderer
         });
     }).unwrap();
 
-    image
+    films
 }
 
-fn main() {
-    let path = if let Some(path) = std::env::args().nth(1) {
-        path
-    } else {
-        eprintln!("usage: disquiet <output.png>");
-        return;
-    };
+fn save(films: &Films, options: &Options) -> error::Result<()> {
+    let image = films.color.to_image();
 
-    println!("output path: {}", path);
+    // Linear formats are written unscaled, exposure only applies to display formats
+    let mut tone_mapping = options.tone_mapping;
+    if let Some(method) = options.auto_exposure {
+        tone_mapping.exposure += image.auto_exposure(method);
+    }
+    if options.print_exposure {
+        println!("exposure {:+.2} EV", tone_mapping.exposure);
+    }
 
-    let scene = build_scene();
-    let accel = ShapeVec::new(&scene);
+    match options.output_format {
+        OutputFormat::Png => image.to_rgba(&tone_mapping).save(&options.output),
+        OutputFormat::Exr => {
+            let mut exr = Exr::new(image.width(), image.height(), options.exr_pixel_type, options.exr_compression);
+            exr.add_layer("", &image)?;
+            if let Some((albedo, normal)) = &films.aovs {
+                exr.add_layer("albedo", &albedo.to_image())?;
+                exr.add_layer("normal", &normal.to_image())?;
+            }
+            exr.save(&options.output)
+        }
+        OutputFormat::Hdr => Hdr::save(&image, &options.output),
+        OutputFormat::Pfm => Pfm::save(&image, &options.output),
+    }
+}
 
-    let integrator = PathTracer::new(4);
+/// Renders with the integrator chosen by the options
+fn render_scene<A: Accelerator>(scene: &Scene, accel: &A, settings: &RenderSettings, options: &Options) -> Films {
     let input = RendererInput {
-        scene: &scene,
-        accel: &accel,
-        sample_count: 16,
-        width: 1920,
-        height: 1080,
-        tile_size: 64,
-        thread_count: 1,
+        scene,
+        accel,
+        sample_count: settings.sample_count,
+        width: settings.width,
+        height: settings.height,
+        tile_size: options.tile_size,
+        thread_count: options.thread_count,
+        seed: options.seed,
+        filter: options.filter,
+        filter_radius: options.filter_radius,
+        aovs: options.aovs,
+    };
+
+    match options.integrator {
+        IntegratorKind::PathTracer => render(PathTracer::new(settings.bounces, options.roulette_depth), input),
+        IntegratorKind::Albedo => render(PrimaryRayIntegrator::new(), input),
+    }
+}
+
+/// glTF files carry no render settings, so they render with the defaults
+fn load_gltf(path: &std::path::Path) -> error::Result<(Scene, RenderSettings)> {
+    let settings = RenderSettings::default();
+    let mut scene = Scene::new();
+    Gltf::load(path, &mut scene, Some(settings.width as Float / settings.height as Float))?;
+
+    Ok((scene, settings))
+}
+
+fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(error) => {
+            eprintln!("{}\nrun 'disquiet --help' for usage", error);
+            std::process::exit(2);
+        }
     };
 
+    let path = &options.scene;
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let loaded = match extension.as_deref() {
+        Some("pbrt") => Pbrt::load(path),
+        Some("gltf") | Some("glb") => load_gltf(path),
+        _ => SceneFile::load(path),
+    };
+
+    let (mut scene, mut settings) = match loaded {
+        Ok(scene) => scene,
+        Err(error) => {
+            eprintln!("failed to load {}: {}", path.display(), error);
+            std::process::exit(1);
+        }
+    };
+
+    settings.width = options.width.unwrap_or(settings.width);
+    settings.height = options.height.unwrap_or(settings.height);
+    settings.sample_count = options.sample_count.unwrap_or(settings.sample_count);
+    settings.bounces = options.bounces.unwrap_or(settings.bounces);
+
+    // The camera was set up for the scene's own resolution
+    if options.width.is_some() || options.height.is_some() {
+        scene.camera.set_aspect_ratio(settings.width as Float / settings.height as Float);
+    }
+
+    println!(
+        "rendering {}x{} with {} samples per pixel on {} threads",
+        settings.width, settings.height, settings.sample_count, options.thread_count,
+    );
+
+    // Building the acceleration structure counts towards the render time
     let render_start = Instant::now();
-    let image = render(integrator, input);
+    let films = match options.accelerator {
+        AcceleratorKind::Bvh => render_scene(&scene, &Bvh8::new(&scene), &settings, &options),
+        AcceleratorKind::SpatialBvh => render_scene(&scene, &Bvh8::with_spatial_splits(&scene), &settings, &options),
+        AcceleratorKind::KdTree => render_scene(&scene, &KdTree::new(&scene), &settings, &options),
+    };
     let render_end = Instant::now();
     let dt = render_end - render_start;
     println!("rendering took {} ms", dt.as_millis());
 
-    let save_path = "test.png";
-    println!("saving as {}", save_path);
-    image.save(save_path).unwrap();
+    println!("saving as {}", options.output.display());
+    if let Err(error) = save(&films, &options) {
+        eprintln!("failed to save {}: {}", options.output.display(), error);
+        std::process::exit(1);
+    }
 }
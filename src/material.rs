@@ -1,13 +1,41 @@
 use crate::color::Color3;
 use crate::math::*;
 use crate::random::RandomGenerator;
+use crate::shape::Hit;
+use crate::texture::Texture;
+use std::sync::Arc;
 
+/// Surface scattering model
+///
+/// The incoming direction is `hit.ray.direction` (pointing towards the surface),
+/// `wi` is the direction light is gathered from.
 pub trait Material : Send + Sync {
-    fn albedo(&self) -> Color3;
-    fn next_ray_direction(&self, n: Vector3, wo: Vector3, rng: &mut RandomGenerator) -> Vector3;
-    fn brdf(&self, n: Vector3, wi: Vector3, wo: Vector3) -> Color3;
-    fn pdf(&self, n: Vector3, wi: Vector3) -> Float;
+    fn albedo(&self, hit: &Hit) -> Color3;
+    fn next_ray_direction(&self, hit: &Hit, rng: &mut RandomGenerator) -> Vector3;
+    fn brdf(&self, hit: &Hit, wi: Vector3) -> Color3;
+    /// Density with respect to solid angle of `next_ray_direction` returning `wi`,
+    /// defined for any direction unless the material `is_delta`
+    fn pdf(&self, hit: &Hit, wi: Vector3) -> Float;
     fn emittance(&self) -> Color3;
+
+    /// Whether scattering only happens in discrete directions, so `brdf` can't be
+    /// evaluated for directions other than the ones returned by `next_ray_direction`
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+/// Samples a direction around `n` with probability proportional to the cosine
+fn sample_cosine(n: Vector3, rng: &mut RandomGenerator) -> Vector3 {
+    let u = rng.range(0.0, 1.0);
+    let v = rng.range(0.0, 1.0);
+
+    let phi = 2.0 * PI * u;
+    let cos_theta = 2.0 * v - 1.0;
+    let f = (1.0 - cos_theta * cos_theta).sqrt();
+    let sphere_point = Vector3::new(f * phi.cos(), f * phi.sin(), cos_theta);
+
+    (n + sphere_point).normalize()
 }
 
 pub struct Lambertian {
@@ -15,35 +43,312 @@ pub struct Lambertian {
 }
 
 impl Material for Lambertian {
-    fn albedo(&self) -> Color3 {
-        self.color.clone()
+    fn albedo(&self, _hit: &Hit) -> Color3 {
+        self.color
+    }
+
+    fn next_ray_direction(&self, hit: &Hit, rng: &mut RandomGenerator) -> Vector3 {
+        sample_cosine(hit.oriented_shading_normal(), rng)
+    }
+
+    fn brdf(&self, hit: &Hit, wi: Vector3) -> Color3 {
+        if hit.oriented_shading_normal().dot(wi) <= 0.0 {
+            return Color3::new(0.0, 0.0, 0.0);
+        }
+
+        self.color / PI
+    }
+
+    fn pdf(&self, hit: &Hit, wi: Vector3) -> Float {
+        max(hit.oriented_shading_normal().dot(wi), 0.0) / PI
+    }
+
+    fn emittance(&self) -> Color3 {
+        Color3::new(0.0, 0.0, 0.0)
+    }
+}
+
+/// Diffuse base with an energy-conserving Phong specular lobe, as described by MTL files
+pub struct Phong {
+    pub diffuse: Color3,
+    // Multiplies `diffuse` when present
+    pub diffuse_map: Option<Arc<Texture>>,
+    pub specular: Color3,
+    pub exponent: Float,
+}
+
+impl Phong {
+    fn diffuse_at(&self, uv: Vector2) -> Color3 {
+        match &self.diffuse_map {
+            Some(texture) => self.diffuse * texture.sample(uv),
+            None => self.diffuse,
+        }
+    }
+
+    /// Probability of sampling the diffuse lobe rather than the specular one
+    fn diffuse_probability(&self) -> Float {
+        let diffuse = self.diffuse.luminance();
+        let specular = self.specular.luminance();
+
+        if diffuse + specular > 0.0 {
+            diffuse / (diffuse + specular)
+        } else {
+            1.0
+        }
+    }
+
+    fn specular_lobe(&self, wi: Vector3, reflected: Vector3) -> Float {
+        max(reflected.dot(wi), 0.0).powf(self.exponent)
+    }
+}
+
+impl Material for Phong {
+    fn albedo(&self, hit: &Hit) -> Color3 {
+        self.diffuse_at(hit.uv)
+    }
+
+    fn next_ray_direction(&self, hit: &Hit, rng: &mut RandomGenerator) -> Vector3 {
+        let n = hit.oriented_shading_normal();
+        if rng.unit() < self.diffuse_probability() {
+            return sample_cosine(n, rng);
+        }
+
+        // Sample the cos^exponent lobe around the mirror direction
+        let reflected = hit.ray.direction.reflect(n);
+        let (tangent, bitangent) = reflected.coordinate_system();
+
+        let phi = 2.0 * PI * rng.unit();
+        let cos_alpha = rng.unit().powf(1.0 / (self.exponent + 1.0));
+        let sin_alpha = (1.0 - cos_alpha * cos_alpha).sqrt();
+
+        (tangent * (sin_alpha * phi.cos()) + bitangent * (sin_alpha * phi.sin()) + reflected * cos_alpha).normalize()
+    }
+
+    fn brdf(&self, hit: &Hit, wi: Vector3) -> Color3 {
+        let n = hit.oriented_shading_normal();
+        if n.dot(wi) <= 0.0 {
+            return Color3::new(0.0, 0.0, 0.0);
+        }
+
+        let normalization = (self.exponent + 2.0) / (2.0 * PI);
+        let specular = self.specular * (normalization * self.specular_lobe(wi, hit.ray.direction.reflect(n)));
+
+        self.diffuse_at(hit.uv) / PI + specular
+    }
+
+    fn pdf(&self, hit: &Hit, wi: Vector3) -> Float {
+        let n = hit.oriented_shading_normal();
+        let diffuse_probability = self.diffuse_probability();
+        let diffuse = max(n.dot(wi), 0.0) / PI;
+        let specular = (self.exponent + 1.0) / (2.0 * PI) * self.specular_lobe(wi, hit.ray.direction.reflect(n));
+
+        diffuse_probability * diffuse + (1.0 - diffuse_probability) * specular
+    }
+
+    fn emittance(&self) -> Color3 {
+        Color3::new(0.0, 0.0, 0.0)
+    }
+}
+
+/// Metallic-roughness model used by glTF: Lambertian base and a GGX specular lobe
+pub struct MetallicRoughness {
+    pub base_color: Color3,
+    // Multiplies `base_color` when present
+    pub base_color_map: Option<Arc<Texture>>,
+    pub metallic: Float,
+    pub roughness: Float,
+    pub emission: Color3,
+}
+
+impl MetallicRoughness {
+    fn base_color_at(&self, uv: Vector2) -> Color3 {
+        match &self.base_color_map {
+            Some(texture) => self.base_color * texture.sample(uv),
+            None => self.base_color,
+        }
+    }
+
+    /// GGX width, clamped to keep smooth surfaces numerically stable
+    fn alpha(&self) -> Float {
+        max(self.roughness * self.roughness, 1e-3)
+    }
+
+    /// Probability of sampling the specular lobe rather than the diffuse one
+    fn specular_probability(&self) -> Float {
+        0.5 + 0.5 * self.metallic
+    }
+
+    fn distribution(&self, cos_h: Float) -> Float {
+        let alpha2 = self.alpha() * self.alpha();
+        let d = cos_h * cos_h * (alpha2 - 1.0) + 1.0;
+        alpha2 / (PI * d * d)
+    }
+
+    /// Smith masking term for a single direction
+    fn masking(&self, cos: Float) -> Float {
+        let alpha2 = self.alpha() * self.alpha();
+        2.0 * cos / (cos + (alpha2 + (1.0 - alpha2) * cos * cos).sqrt())
+    }
+}
+
+impl Material for MetallicRoughness {
+    fn albedo(&self, hit: &Hit) -> Color3 {
+        self.base_color_at(hit.uv)
+    }
+
+    fn next_ray_direction(&self, hit: &Hit, rng: &mut RandomGenerator) -> Vector3 {
+        let n = hit.oriented_shading_normal();
+        if rng.unit() >= self.specular_probability() {
+            return sample_cosine(n, rng);
+        }
+
+        // Sample a microfacet normal proportionally to D(h) * cos(h) and mirror around it
+        let alpha = self.alpha();
+        let u = rng.unit();
+        let phi = 2.0 * PI * rng.unit();
+        let cos_theta = ((1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        let (tangent, bitangent) = n.coordinate_system();
+        let h = tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + n * cos_theta;
+
+        hit.ray.direction.reflect(h).normalize()
+    }
+
+    fn brdf(&self, hit: &Hit, wi: Vector3) -> Color3 {
+        let n = hit.oriented_shading_normal();
+        let v = -hit.ray.direction;
+        let cos_i = n.dot(wi);
+        let cos_o = n.dot(v);
+        if cos_i <= 0.0 || cos_o <= 0.0 {
+            return Color3::new(0.0, 0.0, 0.0);
+        }
+
+        let base_color = self.base_color_at(hit.uv);
+        let h = (wi + v).normalize();
+
+        // Schlick Fresnel with 4% reflectance for dielectrics
+        let f0 = Color3::new(0.04, 0.04, 0.04) * (1.0 - self.metallic) + base_color * self.metallic;
+        let fresnel = f0 + (Color3::new(1.0, 1.0, 1.0) - f0) * (1.0 - max(v.dot(h), 0.0)).powi(5);
+
+        let d = self.distribution(n.dot(h));
+        let g = self.masking(cos_i) * self.masking(cos_o);
+        let specular = fresnel * (d * g / (4.0 * cos_i * cos_o));
+
+        base_color * ((1.0 - self.metallic) / PI) + specular
+    }
+
+    fn pdf(&self, hit: &Hit, wi: Vector3) -> Float {
+        let n = hit.oriented_shading_normal();
+        let specular_probability = self.specular_probability();
+        let diffuse = max(n.dot(wi), 0.0) / PI;
+
+        let h = (wi - hit.ray.direction).normalize();
+        let cos_h = n.dot(h);
+        let specular = if cos_h > 0.0 {
+            self.distribution(cos_h) * cos_h / (4.0 * wi.dot(h).abs())
+        } else {
+            0.0
+        };
+
+        (1.0 - specular_probability) * diffuse + specular_probability * specular
+    }
+
+    fn emittance(&self) -> Color3 {
+        self.emission
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface
+///
+/// `eta` is the ratio of indices of refraction on the incident and transmitted sides.
+fn fresnel_dielectric(cos_i: Float, eta: Float) -> Float {
+    let sin2_t = eta * eta * max(1.0 - cos_i * cos_i, 0.0);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+/// Smooth glass that reflects or refracts according to the Fresnel equations
+///
+/// Scattering is a delta distribution, so `brdf` and `pdf` are only meaningful for
+/// directions returned by `next_ray_direction`, where `pdf` is the probability of
+/// choosing reflection or refraction.
+pub struct Dielectric {
+    pub reflectance: Color3,
+    pub transmittance: Color3,
+    pub ior: Float,
+}
+
+impl Dielectric {
+    /// Relative index of refraction and the cosine of the incident angle
+    fn incidence(&self, hit: &Hit) -> (Float, Float) {
+        let eta = if hit.front_face { 1.0 / self.ior } else { self.ior };
+        let cos_i = -hit.ray.direction.dot(hit.oriented_shading_normal());
+        (eta, clamp(cos_i, 0.0, 1.0))
     }
 
-    fn next_ray_direction(&self, n: Vector3, wo: Vector3, rng: &mut RandomGenerator) -> Vector3 {
-        let u = rng.range(0.0, 1.0);
-        let v = rng.range(0.0, 1.0);
+    fn reflection_probability(&self, hit: &Hit) -> Float {
+        let (eta, cos_i) = self.incidence(hit);
+        fresnel_dielectric(cos_i, eta)
+    }
+}
 
-        let phi = 2.0 * PI * u;
-        let cos_theta = 2.0 * v - 1.0;
-        let f = (1.0 - cos_theta * cos_theta).sqrt();
-        let sphere_point = Vector3::new(f * phi.cos(), f * phi.sin(), cos_theta);
+impl Material for Dielectric {
+    fn albedo(&self, _hit: &Hit) -> Color3 {
+        self.transmittance
+    }
+
+    fn next_ray_direction(&self, hit: &Hit, rng: &mut RandomGenerator) -> Vector3 {
+        let n = hit.oriented_shading_normal();
+        let wo = hit.ray.direction;
+
+        if rng.unit() < self.reflection_probability(hit) {
+            return wo.reflect(n);
+        }
 
-        let result = (n + sphere_point).normalize();
+        // Total internal reflection has a reflection probability of 1, so sin2_t < 1 here
+        let (eta, cos_i) = self.incidence(hit);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        let cos_t = (1.0 - sin2_t).sqrt();
 
-        result
+        (wo * eta + n * (eta * cos_i - cos_t)).normalize()
     }
 
-    fn brdf(&self, n: Vector3, wi: Vector3, wo: Vector3) -> Color3 {
-        self.color.clone() / PI
+    fn brdf(&self, hit: &Hit, wi: Vector3) -> Color3 {
+        let cos = hit.oriented_shading_normal().dot(wi);
+        let reflection_probability = self.reflection_probability(hit);
+
+        if cos > 0.0 {
+            self.reflectance * (reflection_probability / cos)
+        } else {
+            self.transmittance * ((1.0 - reflection_probability) / -cos)
+        }
     }
 
-    fn pdf(&self, n: Vector3, wi: Vector3) -> Float {
-        n.dot(wi) / PI
+    fn pdf(&self, hit: &Hit, wi: Vector3) -> Float {
+        let reflection_probability = self.reflection_probability(hit);
+
+        if hit.oriented_shading_normal().dot(wi) > 0.0 {
+            reflection_probability
+        } else {
+            1.0 - reflection_probability
+        }
     }
 
     fn emittance(&self) -> Color3 {
         Color3::new(0.0, 0.0, 0.0)
     }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
 }
 
 pub struct LightEmitter {
@@ -51,25 +356,29 @@ pub struct LightEmitter {
 }
 
 impl Material for LightEmitter {
-    fn albedo(&self) -> Color3 {
+    fn albedo(&self, _hit: &Hit) -> Color3 {
         self.color
     }
 
-    fn next_ray_direction(&self, n: Vector3, wo: Vector3, rng: &mut RandomGenerator) -> Vector3 {
+    fn next_ray_direction(&self, hit: &Hit, rng: &mut RandomGenerator) -> Vector3 {
         let mut wi = rng.unit_sphere();
-        if n.dot(wi) < 0.0 {
+        if hit.oriented_shading_normal().dot(wi) < 0.0 {
             wi = -wi;
         }
 
         wi
     }
 
-    fn brdf(&self, n: Vector3, wi: Vector3, wo: Vector3) -> Color3 {
+    fn brdf(&self, _hit: &Hit, _wi: Vector3) -> Color3 {
         Color3::new(0.0, 0.0, 0.0)
     }
 
-    fn pdf(&self, n: Vector3, wi: Vector3) -> Float {
-        1.0
+    fn pdf(&self, hit: &Hit, wi: Vector3) -> Float {
+        if hit.oriented_shading_normal().dot(wi) >= 0.0 {
+            1.0 / (2.0 * PI)
+        } else {
+            0.0
+        }
     }
 
     fn emittance(&self) -> Color3 {
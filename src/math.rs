@@ -1,19 +1,25 @@
 pub mod aabb;
 pub mod axis;
 pub mod functions;
+pub mod matrix;
 pub mod plane;
 pub mod vector;
 
 pub use self::aabb::*;
 pub use self::axis::*;
 pub use self::functions::*;
+pub use self::matrix::*;
 pub use self::plane::*;
 pub use self::vector::*;
-pub use std::f64::{EPSILON, MAX, consts::*};
+pub use std::f64::consts::*;
 
 pub type Float = f64;
 pub type Float8 = wide::f32x8;
 
+pub const EPSILON: Float = Float::EPSILON;
+pub const MAX: Float = Float::MAX;
+pub const INFINITY: Float = Float::INFINITY;
+
 pub trait FloatExt : Sized {
     fn clamp(self, min: Self, max: Self) -> Self;
 }
@@ -38,10 +44,70 @@ pub fn cmp_float(a: &Float, b: &Float) -> std::cmp::Ordering {
 pub struct Ray {
     pub origin: Vector3,
     pub direction: Vector3,
+    // Only hits with t_min < t < t_max are reported
+    pub t_min: Float,
+    pub t_max: Float,
 }
 
 impl Ray {
+    pub fn new(origin: Vector3, direction: Vector3) -> Ray {
+        Ray {
+            origin,
+            direction,
+            t_min: EPSILON,
+            t_max: INFINITY,
+        }
+    }
+
+    /// Returns a copy of the ray that ends at `t_max` or earlier
+    pub fn shortened(&self, t_max: Float) -> Ray {
+        Ray {
+            t_max: min(self.t_max, t_max),
+            ..self.clone()
+        }
+    }
+
+    pub fn contains(&self, t: Float) -> bool {
+        t > self.t_min && t < self.t_max
+    }
+
+    /// Component-wise reciprocal of the direction, used by slab tests
+    pub fn inverse_direction(&self) -> Vector3 {
+        Vector3::new(1.0 / self.direction.x, 1.0 / self.direction.y, 1.0 / self.direction.z)
+    }
+
     pub fn point_at(&self, t: Float) -> Vector3 {
         self.origin + self.direction * t
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ray_interval() {
+        let ray = Ray::new(Vector3::ZERO, Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!((ray.t_min, ray.t_max), (EPSILON, INFINITY));
+
+        // Both ends are excluded, so hits at the origin or at the end are rejected
+        assert!(!ray.contains(0.0));
+        assert!(!ray.contains(EPSILON));
+        assert!(ray.contains(2.0 * EPSILON));
+        assert!(ray.contains(1e300));
+        assert!(!ray.contains(INFINITY));
+        assert!(!ray.contains(Float::NAN));
+    }
+
+    #[test]
+    fn shortened_ray() {
+        let ray = Ray::new(Vector3::new(1.0, 2.0, 3.0), Vector3::new(0.0, 1.0, 0.0));
+        let short = ray.shortened(5.0);
+        assert_eq!((short.t_min, short.t_max), (EPSILON, 5.0));
+        assert!(short.contains(4.9) && !short.contains(5.0));
+
+        // Rays are never extended
+        assert_eq!(short.shortened(10.0).t_max, 5.0);
+        assert_eq!(short.shortened(2.0).t_max, 2.0);
+    }
+}
@@ -14,14 +14,53 @@ impl Aabb {
     }
 
     pub fn intersect(&self, rhs: &Aabb) -> bool {
-        let x_intersection = self.max.x > rhs.min.x || rhs.max.x > self.min.x;
-        let y_intersection = self.max.y > rhs.min.y || rhs.max.y > self.min.y;
-        let z_intersection = self.max.z > rhs.min.z || rhs.max.z > self.min.z;
+        let x_intersection = self.max.x > rhs.min.x && rhs.max.x > self.min.x;
+        let y_intersection = self.max.y > rhs.min.y && rhs.max.y > self.min.y;
+        let z_intersection = self.max.z > rhs.min.z && rhs.max.z > self.min.z;
         x_intersection && y_intersection && z_intersection
     }
 
-    pub fn intersect_ray(&self, ray: &Ray) -> bool {
-        unimplemented!()
+    /// Returns the parametric interval `(t_near, t_far)` where the ray is inside the box
+    ///
+    /// `t_near` is negative when the ray starts inside the box. Boxes that don't overlap
+    /// the ray's `t_min..t_max` interval are rejected.
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<(Float, Float)> {
+        self.intersect_ray_inv(ray, ray.inverse_direction())
+    }
+
+    /// Same as `intersect_ray`, but takes the inverse ray direction precomputed
+    /// with `Ray::inverse_direction`
+    pub fn intersect_ray_inv(&self, ray: &Ray, inv_direction: Vector3) -> Option<(Float, Float)> {
+        let mut t_near = -INFINITY;
+        let mut t_far = INFINITY;
+
+        for &axis in &Axis::ALL {
+            let origin = ray.origin[axis];
+            let inv_direction = inv_direction[axis];
+            if origin.is_nan() || inv_direction.is_nan() {
+                return None;
+            }
+
+            // Zero direction components give infinite t, which is what the slab test needs
+            let t0 = (self.min[axis] - origin) * inv_direction;
+            let t1 = (self.max[axis] - origin) * inv_direction;
+            let (t0, t1) = if inv_direction < 0.0 { (t1, t0) } else { (t0, t1) };
+
+            // 0 * inf is NaN when a parallel ray lies exactly on a slab boundary.
+            // Comparisons with NaN are false, so such a slab doesn't restrict the interval.
+            if t0 > t_near {
+                t_near = t0;
+            }
+            if t1 < t_far {
+                t_far = t1;
+            }
+        }
+
+        if t_near <= t_far && t_far >= ray.t_min && t_near <= ray.t_max {
+            Some((t_near, t_far))
+        } else {
+            None
+        }
     }
 
     pub fn min(&self) -> Vector3 {
@@ -48,7 +87,153 @@ impl Aabb {
         Aabb::new(min, max)
     }
 
+    /// Returns the overlapping part of two boxes, if there is one
+    pub fn intersection(&self, rhs: &Aabb) -> Option<Aabb> {
+        let min = self.min.max(rhs.min);
+        let max = self.max.min(rhs.max);
+
+        if min.x <= max.x && min.y <= max.y && min.z <= max.z {
+            Some(Aabb { min, max })
+        } else {
+            None
+        }
+    }
+
+    /// Cuts the box in two by an axis-aligned plane lying within the box
+    pub fn split(&self, axis: Axis, position: Float) -> (Aabb, Aabb) {
+        let mut left_max = self.max;
+        left_max[axis] = position;
+        let mut right_min = self.min;
+        right_min[axis] = position;
+
+        (Aabb::new(self.min, left_max), Aabb::new(right_min, self.max))
+    }
+
     pub fn center(&self) -> Vector3 {
         (self.min + self.max) / 2.0
     }
+
+    pub fn size(&self) -> Vector3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> Float {
+        let size = self.size();
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.min.x.is_finite() && self.min.y.is_finite() && self.min.z.is_finite() &&
+            self.max.x.is_finite() && self.max.y.is_finite() && self.max.z.is_finite()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    fn ray(origin: Vector3, direction: Vector3) -> Ray {
+        Ray::new(origin, direction)
+    }
+
+    #[test]
+    fn intersect_overlapping_boxes() {
+        let other = Aabb::new(Vector3::new(0.5, 0.5, 0.5), Vector3::new(2.0, 2.0, 2.0));
+        assert!(unit_box().intersect(&other));
+        assert!(other.intersect(&unit_box()));
+    }
+
+    #[test]
+    fn intersect_separated_boxes() {
+        let separated_x = Aabb::new(Vector3::new(2.0, -1.0, -1.0), Vector3::new(3.0, 1.0, 1.0));
+        let separated_z = Aabb::new(Vector3::new(-1.0, -1.0, -3.0), Vector3::new(1.0, 1.0, -2.0));
+        assert!(!unit_box().intersect(&separated_x));
+        assert!(!separated_x.intersect(&unit_box()));
+        assert!(!unit_box().intersect(&separated_z));
+    }
+
+    #[test]
+    fn ray_through_box() {
+        let ray = ray(Vector3::new(-5.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&ray), Some((4.0, 6.0)));
+    }
+
+    #[test]
+    fn ray_starting_inside_box() {
+        let ray = ray(Vector3::ZERO, Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(unit_box().intersect_ray(&ray), Some((-1.0, 1.0)));
+    }
+
+    #[test]
+    fn ray_missing_box() {
+        let ray = ray(Vector3::new(-5.0, 2.0, 0.0), Vector3::new(1.0, 0.1, 0.0).normalize());
+        assert_eq!(unit_box().intersect_ray(&ray), None);
+    }
+
+    #[test]
+    fn box_behind_ray() {
+        let ray = ray(Vector3::new(5.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&ray), None);
+    }
+
+    #[test]
+    fn box_beyond_t_max() {
+        let mut ray = ray(Vector3::new(-5.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        ray.t_max = 3.0;
+        assert_eq!(unit_box().intersect_ray(&ray), None);
+
+        ray.t_max = 5.0;
+        assert_eq!(unit_box().intersect_ray(&ray), Some((4.0, 6.0)));
+    }
+
+    #[test]
+    fn parallel_ray_inside_slab() {
+        let ray = ray(Vector3::new(-5.0, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&ray), Some((4.0, 6.0)));
+    }
+
+    #[test]
+    fn parallel_ray_outside_slab() {
+        let positive_zero = ray(Vector3::new(-5.0, 1.5, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&positive_zero), None);
+
+        let negative_zero = ray(Vector3::new(-5.0, 1.5, 0.0), Vector3::new(1.0, -0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&negative_zero), None);
+    }
+
+    #[test]
+    fn parallel_ray_on_slab_boundary() {
+        let ray = ray(Vector3::new(-5.0, 1.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&ray), Some((4.0, 6.0)));
+    }
+
+    #[test]
+    fn flat_box() {
+        let flat = Aabb::new(Vector3::new(-1.0, 0.0, -1.0), Vector3::new(1.0, 0.0, 1.0));
+        let ray = ray(Vector3::new(0.0, 2.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        assert_eq!(flat.intersect_ray(&ray), Some((2.0, 2.0)));
+    }
+
+    #[test]
+    fn nan_ray() {
+        let nan_direction = ray(Vector3::new(-5.0, 0.0, 0.0), Vector3::new(Float::NAN, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&nan_direction), None);
+
+        let nan_origin = ray(Vector3::new(-5.0, Float::NAN, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&nan_origin), None);
+    }
+
+    #[test]
+    fn precomputed_inverse_matches() {
+        let ray = ray(Vector3::new(-3.0, -2.0, 4.0), Vector3::new(1.0, 0.7, -1.2).normalize());
+        assert_eq!(
+            unit_box().intersect_ray_inv(&ray, ray.inverse_direction()),
+            unit_box().intersect_ray(&ray),
+        );
+        assert!(unit_box().intersect_ray(&ray).is_some());
+    }
 }
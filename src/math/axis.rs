@@ -8,7 +8,9 @@ pub enum Axis {
 }
 
 impl Axis {
-    pub fn to_unit_vector(&self) -> Vector3 {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    pub fn to_unit_vector(self) -> Vector3 {
         match self {
             Axis::X => Vector3::new(1.0, 0.0, 0.0),
             Axis::Y => Vector3::new(0.0, 1.0, 0.0),
@@ -0,0 +1,230 @@
+use crate::math::*;
+use std::ops::Mul;
+
+/// Row-major 4x4 matrix of an affine transform
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[Float; 4]; 4],
+}
+
+impl Matrix4 {
+    pub const IDENTITY: Matrix4 = Matrix4 {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub const fn new(m: [[Float; 4]; 4]) -> Matrix4 {
+        Matrix4 { m }
+    }
+
+    /// Builds a matrix from 16 values in column-major order, as stored by glTF
+    pub fn from_column_major(values: &[Float; 16]) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, &value) in values.iter().enumerate() {
+            m[i % 4][i / 4] = value;
+        }
+        Matrix4 { m }
+    }
+
+    pub fn translation(offset: Vector3) -> Matrix4 {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling(scale: Vector3) -> Matrix4 {
+        Matrix4::new([
+            [scale.x, 0.0, 0.0, 0.0],
+            [0.0, scale.y, 0.0, 0.0],
+            [0.0, 0.0, scale.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Rotation by `angle` radians around `axis`
+    pub fn rotation(axis: Vector3, angle: Float) -> Matrix4 {
+        let a = axis.normalize();
+        let (sin, cos) = angle.sin_cos();
+        let t = 1.0 - cos;
+
+        Matrix4::new([
+            [t * a.x * a.x + cos, t * a.x * a.y - sin * a.z, t * a.x * a.z + sin * a.y, 0.0],
+            [t * a.x * a.y + sin * a.z, t * a.y * a.y + cos, t * a.y * a.z - sin * a.x, 0.0],
+            [t * a.x * a.z - sin * a.y, t * a.y * a.z + sin * a.x, t * a.z * a.z + cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Rotation given by a unit quaternion `(x, y, z, w)`
+    pub fn from_quaternion(x: Float, y: Float, z: Float, w: Float) -> Matrix4 {
+        Matrix4::new([
+            [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w), 2.0 * (x * z + y * w), 0.0],
+            [2.0 * (x * y + z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w), 0.0],
+            [2.0 * (x * z - y * w), 2.0 * (y * z + x * w), 1.0 - 2.0 * (x * x + y * y), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    /// Inverse computed by Gauss-Jordan elimination, `None` for singular matrices
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inverse = Matrix4::IDENTITY.m;
+
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| cmp_float(&a[i][column].abs(), &a[j][column].abs()))
+                .unwrap_or(column);
+
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+
+            for row in 0..4 {
+                if row == column {
+                    continue;
+                }
+
+                let factor = a[row][column];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[column][j];
+                    inverse[row][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+
+        Some(Matrix4 { m: inverse })
+    }
+
+    pub fn transform_point(&self, p: Vector3) -> Vector3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+
+        if w == 1.0 {
+            Vector3::new(x, y, z)
+        } else {
+            Vector3::new(x, y, z) / w
+        }
+    }
+
+    pub fn transform_vector(&self, v: Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Transforms a normal, `self` must be the inverse of the transform applied to points
+    pub fn transform_normal(&self, n: Vector3) -> Vector3 {
+        self.transpose().transform_vector(n)
+    }
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Matrix4::IDENTITY
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Self;
+    fn mul(self, rhs: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Matrix4, b: &Matrix4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((a.m[i][j] - b.m[i][j]).abs() < 1e-12, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_of_affine_transform() {
+        let transform = Matrix4::translation(Vector3::new(1.0, -2.0, 3.0))
+            * Matrix4::rotation(Vector3::new(1.0, 1.0, 0.0), 0.7)
+            * Matrix4::scaling(Vector3::new(2.0, 0.5, -4.0));
+        let inverse = transform.inverse().unwrap();
+
+        assert_close(&(transform * inverse), &Matrix4::IDENTITY);
+        assert_close(&(inverse * transform), &Matrix4::IDENTITY);
+    }
+
+    #[test]
+    fn inverse_needs_pivoting() {
+        // Zero on the diagonal, swapping rows is required
+        let permutation = Matrix4::new([
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_close(&permutation.inverse().unwrap(), &permutation.transpose());
+    }
+
+    #[test]
+    fn singular_matrix() {
+        assert_eq!(Matrix4::scaling(Vector3::new(1.0, 0.0, 1.0)).inverse(), None);
+    }
+
+    #[test]
+    fn quaternion_matches_axis_angle() {
+        let axis = Vector3::new(1.0, 2.0, -2.0).normalize();
+        let angle: Float = 1.2;
+        let s = (angle / 2.0).sin();
+        let rotation = Matrix4::from_quaternion(axis.x * s, axis.y * s, axis.z * s, (angle / 2.0).cos());
+
+        assert_close(&rotation, &Matrix4::rotation(axis, angle));
+        assert_close(&Matrix4::from_quaternion(0.0, 0.0, 0.0, 1.0), &Matrix4::IDENTITY);
+    }
+
+    #[test]
+    fn quaternion_quarter_turn() {
+        // 90 degrees around +Z takes +X to +Y
+        let half = FRAC_1_SQRT_2;
+        let x = Matrix4::from_quaternion(0.0, 0.0, half, half).transform_vector(Vector3::X);
+        assert!(x.x.abs() < 1e-12 && (x.y - 1.0).abs() < 1e-12 && x.z.abs() < 1e-12);
+    }
+}
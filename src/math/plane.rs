@@ -15,10 +15,10 @@ impl Plane {
     }
 
     pub fn intersect_ray(&self, ray: &Ray) -> Option<Float> {
-        let d = self.point.dot(ray.direction);
+        let d = self.normal.dot(ray.direction);
         if d.abs() > EPSILON {
             let t = (self.point - ray.origin).dot(self.normal) / d;
-            if t >= EPSILON {
+            if ray.contains(t) {
                 Some(t)
             } else {
                 None
@@ -44,6 +44,24 @@ impl Vector3 {
         (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
     }
 
+    /// Returns two vectors that form an orthonormal basis together with this unit vector
+    pub fn coordinate_system(&self) -> (Vector3, Vector3) {
+        // Branchless construction by Duff et al.
+        let sign = (1.0 as Float).copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+
+        let tangent = Vector3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x);
+        let bitangent = Vector3::new(b, sign + self.y * self.y * a, -self.y);
+
+        (tangent, bitangent)
+    }
+
+    /// Mirrors a direction pointing towards the surface with normal `n`
+    pub fn reflect(&self, n: Vector3) -> Vector3 {
+        *self - n * (2.0 * self.dot(n))
+    }
+
     pub fn min(&self, rhs: Vector3) -> Vector3 {
         Vector3 {
             x: self.x.min(rhs.x),
@@ -126,6 +144,41 @@ impl IndexMut<Axis> for Vector3 {
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Vector2 {
+    pub x: Float,
+    pub y: Float,
+}
+
+impl Vector2 {
+    pub const ZERO: Vector2 = Vector2::new(0.0, 0.0);
+
+    pub const fn new(x: Float, y: Float) -> Vector2 {
+        Vector2 { x, y }
+    }
+}
+
+impl Add for Vector2 {
+    type Output = Vector2;
+    fn add(self, rhs: Vector2) -> Vector2 {
+        Vector2::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl Sub for Vector2 {
+    type Output = Vector2;
+    fn sub(self, rhs: Vector2) -> Vector2 {
+        Vector2::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl Mul<Float> for Vector2 {
+    type Output = Vector2;
+    fn mul(self, rhs: Float) -> Vector2 {
+        Vector2::new(self.x * rhs, self.y * rhs)
+    }
+}
+
 pub struct Vector3x8 {
     pub x: Float8,
     pub y: Float8,
@@ -0,0 +1,435 @@
+use crate::color::{ToneMapper, ToneMapping, TransferFunction};
+use crate::error::*;
+use crate::filter::FilterKind;
+use crate::image::AutoExposure;
+use crate::io::{ExrCompression, ExrPixelType};
+use crate::math::Float;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: disquiet [options] <scene>
+
+Renders a JSON (.json), pbrt-v3 (.pbrt) or glTF (.gltf, .glb) scene. Options
+override the settings stored in the scene file.
+
+options:
+  -o, --output <path>           output image, .png, .exr, .hdr or .pfm
+                                [default: render.png]
+      --width <pixels>          image width
+      --height <pixels>         image height
+  -s, --samples <count>         samples per pixel
+  -b, --bounces <count>         maximum number of rays per path
+      --roulette-depth <count>  rays per path before Russian roulette may end it
+                                [default: 3]
+  -t, --threads <count>         worker threads [default: all cores]
+  -i, --integrator <name>       'path' or 'albedo' [default: path]
+      --accelerator <name>      'bvh', 'sbvh' (BVH with spatial splits) or 'kd'
+                                [default: bvh]
+  -f, --filter <name>           'box', 'tent', 'gaussian', 'mitchell' or
+                                'blackman-harris' [default: gaussian]
+      --filter-radius <pixels>  filter radius [default: depends on the filter]
+      --tone-map <name>         'clamp', 'reinhard', 'reinhard-extended', 'hable',
+                                'aces' or 'agx' [default: aces]
+      --exposure <stops>        exposure adjustment before tone mapping, added to
+                                the automatic exposure if enabled [default: 0]
+      --auto-exposure <method>  'key[:<value>]' maps the log-average luminance to
+                                the key [default: 0.18], 'percentile[:<p>]' maps
+                                the p-th percentile to 1 [default: 95]
+      --print-exposure          print the exposure used for the output
+      --white-point <value>     luminance mapped to white by 'reinhard-extended'
+                                [default: 4]
+      --transfer <name>         'gamma' (2.2) or 'srgb' [default: gamma]
+      --seed <number>           random seed [default: 0]
+      --exr-pixel-type <type>   'half' or 'float' [default: half]
+      --exr-compression <name>  'none' or 'rle' [default: rle]
+      --aovs                    add first-hit 'albedo' and 'normal' layers to EXR
+                                output
+      --tile-size <pixels>      edge length of the tiles handed to threads [default: 64]
+  -h, --help                    print this help";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    // Tone-mapped 8-bit sRGB
+    Png,
+    // Linear radiance
+    Exr,
+    Hdr,
+    Pfm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IntegratorKind {
+    PathTracer,
+    // First-hit albedo, useful to check geometry and materials quickly
+    Albedo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AcceleratorKind {
+    Bvh,
+    // Slower to build, faster to trace scenes with large overlapping triangles
+    SpatialBvh,
+    KdTree,
+}
+
+/// Command-line options of the renderer
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub scene: PathBuf,
+    pub output: PathBuf,
+    pub output_format: OutputFormat,
+    pub exr_pixel_type: ExrPixelType,
+    pub exr_compression: ExrCompression,
+    // Albedo and normal layers, only written to EXR files
+    pub aovs: bool,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub sample_count: Option<usize>,
+    pub bounces: Option<usize>,
+    pub roulette_depth: usize,
+    pub thread_count: usize,
+    pub integrator: IntegratorKind,
+    pub accelerator: AcceleratorKind,
+    pub filter: FilterKind,
+    pub filter_radius: Option<Float>,
+    // Conversion to 8-bit colors, only used by display formats
+    pub tone_mapping: ToneMapping,
+    pub auto_exposure: Option<AutoExposure>,
+    pub print_exposure: bool,
+    pub seed: u64,
+    pub tile_size: usize,
+}
+
+fn argument_error(message: impl Into<String>) -> Error {
+    Error::ArgumentError(message.into())
+}
+
+fn positive(option: &str, value: &str) -> Result<usize> {
+    match value.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(argument_error(format!("{} expects a positive integer, found '{}'", option, value))),
+    }
+}
+
+/// Parses `key[:<value>]` or `percentile[:<p>]`
+fn auto_exposure(value: &str) -> Result<AutoExposure> {
+    let (method, parameter) = match value.split_once(':') {
+        Some((method, parameter)) => (method, Some(parameter)),
+        None => (value, None),
+    };
+
+    let parameter = match parameter.map(str::parse::<Float>) {
+        None => None,
+        Some(Ok(parameter)) if parameter.is_finite() => Some(parameter),
+        Some(_) => return Err(argument_error(format!("invalid --auto-exposure parameter in '{}'", value))),
+    };
+
+    match method {
+        "key" => match parameter.unwrap_or(AutoExposure::DEFAULT_KEY) {
+            key if key > 0.0 => Ok(AutoExposure::Key(key)),
+            _ => Err(argument_error(format!("--auto-exposure key must be positive, found '{}'", value))),
+        },
+        "percentile" => match parameter.unwrap_or(AutoExposure::DEFAULT_PERCENTILE) {
+            percentile if (0.0..=100.0).contains(&percentile) => Ok(AutoExposure::Percentile(percentile)),
+            _ => Err(argument_error(format!("--auto-exposure percentile must be within 0 and 100, found '{}'", value))),
+        },
+        _ => Err(argument_error(format!("unknown exposure method '{}', expected 'key' or 'percentile'", value))),
+    }
+}
+
+impl Options {
+    /// Parses the arguments following the program name, `None` if help was requested
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Options>> {
+        let mut scene = None;
+        let mut options = Options {
+            scene: PathBuf::new(),
+            output: PathBuf::from("render.png"),
+            output_format: OutputFormat::Png,
+            exr_pixel_type: ExrPixelType::Half,
+            exr_compression: ExrCompression::Rle,
+            aovs: false,
+            width: None,
+            height: None,
+            sample_count: None,
+            bounces: None,
+            roulette_depth: 3,
+            thread_count: std::thread::available_parallelism().map(|count| count.get()).unwrap_or(1),
+            integrator: IntegratorKind::PathTracer,
+            accelerator: AcceleratorKind::Bvh,
+            filter: FilterKind::Gaussian,
+            filter_radius: None,
+            tone_mapping: ToneMapping::default(),
+            auto_exposure: None,
+            print_exposure: false,
+            seed: 0,
+            tile_size: 64,
+        };
+
+        let mut white_point = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') || arg == "-" {
+                if scene.replace(PathBuf::from(&arg)).is_some() {
+                    return Err(argument_error(format!("unexpected argument '{}', only one scene can be rendered", arg)));
+                }
+                continue;
+            }
+
+            // Values follow either as the next argument or after '='
+            let (option, inline_value) = match arg.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => (option.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+
+            if option == "-h" || option == "--help" {
+                return Ok(None);
+            }
+            if arg == "--print-exposure" {
+                options.print_exposure = true;
+                continue;
+            }
+            if arg == "--aovs" {
+                options.aovs = true;
+                continue;
+            }
+
+            let value = match inline_value.or_else(|| args.next()) {
+                Some(value) => value,
+                None => return Err(argument_error(format!("{} expects a value", option))),
+            };
+
+            match option.as_str() {
+                "-o" | "--output" => options.output = PathBuf::from(value),
+                "--width" => options.width = Some(positive(&option, &value)?),
+                "--height" => options.height = Some(positive(&option, &value)?),
+                "-s" | "--samples" => options.sample_count = Some(positive(&option, &value)?),
+                "-b" | "--bounces" => options.bounces = Some(positive(&option, &value)?),
+                "--roulette-depth" => options.roulette_depth = positive(&option, &value)?,
+                "-t" | "--threads" => options.thread_count = positive(&option, &value)?,
+                "--tile-size" => options.tile_size = positive(&option, &value)?,
+                "--seed" => {
+                    options.seed = value.parse()
+                        .map_err(|_| argument_error(format!("--seed expects a non-negative integer, found '{}'", value)))?;
+                }
+                "-f" | "--filter" => {
+                    options.filter = FilterKind::from_name(&value).ok_or_else(|| {
+                        argument_error(format!("unknown filter '{}', expected {}", value, FilterKind::NAMES))
+                    })?;
+                }
+                "--filter-radius" => {
+                    options.filter_radius = match value.parse::<Float>() {
+                        Ok(radius) if radius > 0.0 && radius.is_finite() => Some(radius),
+                        _ => return Err(argument_error(format!("--filter-radius expects a positive number, found '{}'", value))),
+                    };
+                }
+                "--tone-map" => {
+                    options.tone_mapping.tone_mapper = ToneMapper::from_name(&value).ok_or_else(|| {
+                        argument_error(format!("unknown tone mapper '{}', expected {}", value, ToneMapper::NAMES))
+                    })?;
+                }
+                "--exposure" => {
+                    options.tone_mapping.exposure = match value.parse::<Float>() {
+                        Ok(exposure) if exposure.is_finite() => exposure,
+                        _ => return Err(argument_error(format!("--exposure expects a number, found '{}'", value))),
+                    };
+                }
+                "--auto-exposure" => options.auto_exposure = Some(auto_exposure(&value)?),
+                "--white-point" => {
+                    white_point = match value.parse::<Float>() {
+                        Ok(white) if white > 0.0 && white.is_finite() => Some(white),
+                        _ => return Err(argument_error(format!("--white-point expects a positive number, found '{}'", value))),
+                    };
+                }
+                "--transfer" => {
+                    options.tone_mapping.transfer = match value.as_str() {
+                        "gamma" => TransferFunction::Gamma,
+                        "srgb" => TransferFunction::Srgb,
+                        _ => return Err(argument_error(format!("unknown transfer function '{}', expected 'gamma' or 'srgb'", value))),
+                    };
+                }
+                "--exr-pixel-type" => {
+                    options.exr_pixel_type = match value.as_str() {
+                        "half" => ExrPixelType::Half,
+                        "float" => ExrPixelType::Float,
+                        _ => return Err(argument_error(format!("unknown EXR pixel type '{}', expected 'half' or 'float'", value))),
+                    };
+                }
+                "--exr-compression" => {
+                    options.exr_compression = match value.as_str() {
+                        "none" => ExrCompression::None,
+                        "rle" => ExrCompression::Rle,
+                        _ => return Err(argument_error(format!("unknown EXR compression '{}', expected 'none' or 'rle'", value))),
+                    };
+                }
+                "-i" | "--integrator" => {
+                    options.integrator = match value.as_str() {
+                        "path" => IntegratorKind::PathTracer,
+                        "albedo" => IntegratorKind::Albedo,
+                        _ => return Err(argument_error(format!("unknown integrator '{}', expected 'path' or 'albedo'", value))),
+                    };
+                }
+                "--accelerator" => {
+                    options.accelerator = match value.as_str() {
+                        "bvh" => AcceleratorKind::Bvh,
+                        "sbvh" => AcceleratorKind::SpatialBvh,
+                        "kd" => AcceleratorKind::KdTree,
+                        _ => return Err(argument_error(format!("unknown accelerator '{}', expected 'bvh', 'sbvh' or 'kd'", value))),
+                    };
+                }
+                _ => return Err(argument_error(format!("unknown option '{}'", option))),
+            }
+        }
+
+        if let (ToneMapper::ExtendedReinhard { white }, Some(white_point)) = (&mut options.tone_mapping.tone_mapper, white_point) {
+            *white = white_point;
+        }
+
+        options.scene = scene.ok_or_else(|| argument_error("missing scene file"))?;
+
+        let extension = options.output.extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        options.output_format = match extension.as_deref() {
+            Some("png") => OutputFormat::Png,
+            Some("exr") => OutputFormat::Exr,
+            Some("hdr") => OutputFormat::Hdr,
+            Some("pfm") => OutputFormat::Pfm,
+            _ => {
+                let output = options.output.display();
+                return Err(argument_error(format!("unsupported output format '{}', expected .png, .exr, .hdr or .pfm", output)));
+            }
+        };
+
+        if options.aovs && options.output_format != OutputFormat::Exr {
+            return Err(argument_error("--aovs needs an .exr output"));
+        }
+
+        Ok(Some(options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn options(args: &[&str]) -> Options {
+        parse(args).unwrap().unwrap()
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Err(Error::ArgumentError(message)) => message,
+            other => panic!("expected an argument error for {:?}, got {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let options = options(&["scene.json"]);
+        assert_eq!(options.scene, PathBuf::from("scene.json"));
+        assert_eq!(options.output, PathBuf::from("render.png"));
+        assert_eq!(options.output_format, OutputFormat::Png);
+        assert_eq!((options.width, options.sample_count, options.auto_exposure), (None, None, None));
+        assert_eq!(options.tone_mapping, ToneMapping::default());
+    }
+
+    #[test]
+    fn inline_and_separate_values() {
+        let separate = options(&["--width", "640", "-s", "8", "scene.json", "--seed", "42"]);
+        let inline = options(&["--width=640", "--samples=8", "scene.json", "--seed=42"]);
+
+        for options in [separate, inline] {
+            assert_eq!(options.width, Some(640));
+            assert_eq!(options.sample_count, Some(8));
+            assert_eq!(options.seed, 42);
+        }
+
+        // Only long options take inline values, and the value may itself contain '='
+        assert_eq!(options(&["--output=a=b.exr", "scene.json"]).output, PathBuf::from("a=b.exr"));
+        assert!(error(&["-s=8", "scene.json"]).contains("unknown option '-s=8'"));
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(error(&["scene.json", "--width"]), "--width expects a value");
+        assert_eq!(error(&["scene.json", "--height="]), "--height expects a positive integer, found ''");
+        assert!(error(&["scene.json", "--bounces", "0"]).contains("positive integer"));
+        assert!(error(&["scene.json", "--filter", "sinc"]).contains("unknown filter 'sinc'"));
+        assert!(error(&["scene.json", "--frobnicate", "1"]).contains("unknown option"));
+    }
+
+    #[test]
+    fn help() {
+        assert!(parse(&["--help"]).unwrap().is_none());
+        assert!(parse(&["scene.json", "-h"]).unwrap().is_none());
+        // Arguments before the help flag are still validated
+        assert!(parse(&["--width", "0", "-h"]).is_err());
+        assert!(parse(&["--samples=0", "--help"]).is_err());
+    }
+
+    #[test]
+    fn scene_count() {
+        assert_eq!(error(&[]), "missing scene file");
+        assert_eq!(error(&["--width", "64"]), "missing scene file");
+        assert!(error(&["a.json", "b.json"]).contains("only one scene"));
+        assert_eq!(options(&["-"]).scene, PathBuf::from("-"));
+    }
+
+    #[test]
+    fn auto_exposure_methods() {
+        let method = |value: &str| options(&["scene.json", "--auto-exposure", value]).auto_exposure;
+
+        assert_eq!(method("key"), Some(AutoExposure::Key(AutoExposure::DEFAULT_KEY)));
+        assert_eq!(method("key:0.5"), Some(AutoExposure::Key(0.5)));
+        assert_eq!(method("percentile"), Some(AutoExposure::Percentile(AutoExposure::DEFAULT_PERCENTILE)));
+        assert_eq!(method("percentile:99.5"), Some(AutoExposure::Percentile(99.5)));
+
+        for value in ["key:0", "key:-1", "key:abc", "key:inf", "percentile:101", "percentile:", "average"] {
+            error(&["scene.json", "--auto-exposure", value]);
+        }
+    }
+
+    #[test]
+    fn accelerators() {
+        let accelerator = |args: &[&str]| options(&[&["scene.json"], args].concat()).accelerator;
+
+        assert_eq!(accelerator(&[]), AcceleratorKind::Bvh);
+        assert_eq!(accelerator(&["--accelerator", "sbvh"]), AcceleratorKind::SpatialBvh);
+        assert_eq!(accelerator(&["--accelerator=kd"]), AcceleratorKind::KdTree);
+        assert!(error(&["scene.json", "--accelerator", "octree"]).contains("unknown accelerator 'octree'"));
+    }
+
+    #[test]
+    fn tone_mapping() {
+        let options = options(&["--tone-map", "reinhard-extended", "--white-point=8", "--exposure", "-1.5", "--transfer", "srgb", "--print-exposure", "scene.json"]);
+        assert_eq!(options.tone_mapping.tone_mapper, ToneMapper::ExtendedReinhard { white: 8.0 });
+        assert_eq!(options.tone_mapping.exposure, -1.5);
+        assert_eq!(options.tone_mapping.transfer, TransferFunction::Srgb);
+        assert!(options.print_exposure);
+    }
+
+    #[test]
+    fn output_formats() {
+        let format = |output: &str| options(&["scene.json", "-o", output]).output_format;
+
+        assert_eq!(format("out.png"), OutputFormat::Png);
+        assert_eq!(format("out.EXR"), OutputFormat::Exr);
+        assert_eq!(format("dir.v2/out.hdr"), OutputFormat::Hdr);
+        assert_eq!(format("out.pfm"), OutputFormat::Pfm);
+
+        assert!(error(&["scene.json", "-o", "out.jpg"]).contains("unsupported output format"));
+        assert!(error(&["scene.json", "-o", "out"]).contains("unsupported output format"));
+    }
+
+    #[test]
+    fn aovs_need_exr() {
+        assert!(options(&["scene.json", "--aovs", "-o", "out.exr"]).aovs);
+        assert!(!options(&["scene.json", "-o", "out.exr"]).aovs);
+        assert_eq!(error(&["scene.json", "--aovs"]), "--aovs needs an .exr output");
+    }
+}
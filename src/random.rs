@@ -8,21 +8,40 @@ pub struct RandomGenerator {
     generator: Pcg64Mcg,
 }
 
+const DEFAULT_SEED: u64 = 0xCAFEF00DD15EA5E5;
+
+/// SplitMix64 step, spreads nearby seeds over the whole state space
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E3779B97F4A7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^ (x >> 31)
+}
+
 impl RandomGenerator {
     pub fn new() -> Self {
+        Self::with_seed(DEFAULT_SEED)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        let high = mix(seed);
+        let low = mix(high);
         Self {
-            generator: Pcg64Mcg::new(0xCAFEF00DD15EA5E5),
+            generator: Pcg64Mcg::new((high as u128) << 64 | low as u128),
         }
     }
 
+    /// Independent generator for one of many streams derived from `seed`
+    pub fn for_stream(seed: u64, stream: u64) -> Self {
+        Self::with_seed(seed ^ mix(stream))
+    }
+
     pub fn unit(&mut self) -> Float {
         self.generator.gen_range(0.0, 1.0)
     }
 
     pub fn range(&mut self, min: Float, max: Float) -> Float {
-        let mut value = self.generator.gen_range(min, max);
-
-        value
+        self.generator.gen_range(min, max)
     }
 
     pub fn unit_sphere(&mut self) -> Vector3 {
@@ -3,11 +3,25 @@ use crate::color::*;
 use crate::image::*;
 use crate::material::*;
 use crate::shape::*;
-use crate::math::Vector3;
+use crate::math::*;
+use crate::random::RandomGenerator;
+
+// Point lights become small emissive spheres of this radius
+const POINT_LIGHT_RADIUS: Float = 0.05;
+
+/// Emissive shape, either a standalone shape or a triangle of a mesh
+enum Light {
+    Shape(usize),
+    MeshTriangle { mesh: usize, triangle: usize },
+}
 
 pub struct Scene {
     materials: Vec<Box<dyn Material>>,
     shapes: Vec<Box<dyn Shape>>,
+    meshes: Vec<TriangleMesh>,
+    lights: Vec<Light>,
+    // Running sum of the emitted power of `lights`, to pick them proportionally to it
+    light_power_cdf: Vec<Float>,
     pub world_color: Color3,
     pub sky: Option<Image>,
     pub camera: Camera,
@@ -20,16 +34,91 @@ impl Scene {
         Self {
             materials: Vec::new(),
             shapes: Vec::new(),
+            meshes: Vec::new(),
+            lights: Vec::new(),
+            light_power_cdf: Vec::new(),
             world_color: Color3::new(0.0, 0.0, 0.0),
             sky: None,
             camera: Camera::look_at(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0), Vector3::Y, 70.0, 16.0 / 9.0),
         }
     }
 
+    /// Adds a shape, which also becomes a light if its material is emissive
+    ///
+    /// The material must have been added before.
     pub fn add_shape<S: Shape + 'static>(&mut self, s: S) {
+        self.add_light(Light::Shape(self.shapes.len()), &s);
         self.shapes.push(Box::new(s));
     }
 
+    pub fn add_mesh(&mut self, mesh: TriangleMesh) {
+        for (triangle, shape) in mesh.triangles().enumerate() {
+            self.add_light(Light::MeshTriangle { mesh: self.meshes.len(), triangle }, shape);
+        }
+        self.meshes.push(mesh);
+    }
+
+    fn add_light(&mut self, light: Light, shape: &dyn Shape) {
+        let emittance = self.get_material(shape.material()).emittance();
+        let power = emittance.luminance() * shape.surface_area();
+
+        // Shapes of infinite area can't be sampled and are only found by tracing rays
+        if power > 0.0 && power.is_finite() {
+            let total = self.light_power_cdf.last().copied().unwrap_or(0.0);
+            self.lights.push(light);
+            self.light_power_cdf.push(total + power);
+        }
+    }
+
+    fn light_shape(&self, light: &Light) -> &dyn Shape {
+        match *light {
+            Light::Shape(index) => self.shapes[index].as_ref(),
+            Light::MeshTriangle { mesh, triangle } => self.meshes[mesh].triangle(triangle),
+        }
+    }
+
+    /// Probability of `sample_light` picking `shape`, 0 if it isn't a light
+    pub fn light_pdf(&self, shape: &dyn Shape) -> Float {
+        let total = match self.light_power_cdf.last() {
+            Some(&total) => total,
+            None => return 0.0,
+        };
+
+        let power = self.get_material(shape.material()).emittance().luminance() * shape.surface_area();
+        if power > 0.0 && power.is_finite() {
+            power / total
+        } else {
+            0.0
+        }
+    }
+
+    /// Picks an emissive shape with probability proportional to its power, returns it
+    /// with that probability, `None` if the scene has no lights
+    pub fn sample_light(&self, rng: &mut RandomGenerator) -> Option<(&dyn Shape, Float)> {
+        let total = *self.light_power_cdf.last()?;
+        let target = rng.unit() * total;
+
+        let index = self.light_power_cdf.partition_point(|&sum| sum <= target).min(self.lights.len() - 1);
+        let previous = if index > 0 { self.light_power_cdf[index - 1] } else { 0.0 };
+
+        Some((self.light_shape(&self.lights[index]), (self.light_power_cdf[index] - previous) / total))
+    }
+
+    /// Adds a light emitting `intensity` (in W/sr) in every direction from `position`
+    ///
+    /// Path tracing cannot hit a true point light, so it is approximated by a small sphere.
+    pub fn add_point_light(&mut self, position: Vector3, intensity: Color3) {
+        // A sphere of radiance L has intensity L * pi * r^2 in every direction
+        let radiance = intensity / (PI * POINT_LIGHT_RADIUS * POINT_LIGHT_RADIUS);
+        let material = self.add_material(LightEmitter { color: radiance });
+
+        self.add_shape(Sphere {
+            center: position,
+            radius: POINT_LIGHT_RADIUS,
+            material,
+        });
+    }
+
     pub fn add_material<M: Material + 'static>(&mut self, m: M) -> MaterialId {
         self.materials.push(Box::new(m));
         self.materials.len() - 1
@@ -39,7 +128,64 @@ impl Scene {
         self.materials[material_id].as_ref()
     }
 
+    pub fn meshes(&self) -> &[TriangleMesh] {
+        &self.meshes
+    }
+
     pub fn shapes(&self) -> impl Iterator<Item = &dyn Shape> {
-        self.shapes.iter().map(|o| o.as_ref())
+        let meshes = self.meshes.iter().flat_map(|mesh| mesh.triangles());
+        self.shapes.iter().map(|o| o.as_ref()).chain(meshes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_selection() {
+        let mut scene = Scene::new();
+        assert!(scene.sample_light(&mut RandomGenerator::new()).is_none());
+
+        let dim = scene.add_material(LightEmitter { color: Color3::new(1.0, 1.0, 1.0) });
+        let bright = scene.add_material(LightEmitter { color: Color3::new(8.0, 0.0, 8.0) });
+        let diffuse = scene.add_material(Lambertian { color: Color3::new(1.0, 1.0, 1.0) });
+
+        scene.add_shape(Sphere { center: Vector3::ZERO, radius: 1.0, material: dim });
+        scene.add_shape(Sphere { center: Vector3::X * 3.0, radius: 0.5, material: bright });
+        scene.add_shape(Sphere { center: Vector3::X * 6.0, radius: 1.0, material: diffuse });
+        scene.add_mesh(TriangleMesh::new(MeshData {
+            positions: vec![Vector3::ZERO, Vector3::X, Vector3::Y, Vector3::X + Vector3::Y],
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: vec![0, 1, 2, 2, 1, 3],
+            material: bright,
+        }).unwrap());
+
+        let shapes: Vec<&dyn Shape> = scene.shapes().collect();
+        let index = |light: &dyn Shape| shapes.iter().position(|&shape| std::ptr::addr_eq(shape, light)).unwrap();
+
+        // Proportional to emitted luminance times area
+        let luminance = scene.get_material(bright).emittance().luminance();
+        let powers = [4.0 * PI, luminance * PI, 0.0, luminance * 0.5, luminance * 0.5];
+        let total: Float = powers.iter().sum();
+        for (shape, power) in shapes.iter().zip(powers) {
+            assert!((scene.light_pdf(*shape) - power / total).abs() < 1e-12);
+        }
+
+        let count = 200_000;
+        let mut counts = [0; 5];
+        let mut rng = RandomGenerator::with_seed(3);
+        for _ in 0..count {
+            let (light, probability) = scene.sample_light(&mut rng).unwrap();
+            assert!((probability - scene.light_pdf(light)).abs() < 1e-12);
+            counts[index(light)] += 1;
+        }
+
+        for (shape, &hits) in shapes.iter().zip(&counts) {
+            let frequency = hits as Float / count as Float;
+            assert!((frequency - scene.light_pdf(*shape)).abs() < 0.005, "{} != {}", frequency, scene.light_pdf(*shape));
+        }
+        assert_eq!(counts[2], 0);
     }
 }
@@ -1,38 +1,153 @@
+pub mod mesh;
 pub mod plane;
 pub mod triangle;
 pub mod sphere;
 
+pub use self::mesh::*;
 // pub use self::plane::*;
 pub use self::triangle::*;
 pub use self::sphere::*;
 
 use crate::math::*;
+use crate::random::RandomGenerator;
 use crate::scene::MaterialId;
 
 pub trait Shape : Send + Sync {
-    fn hit(&self, ray: &Ray) -> Option<Hit>;
-    fn normal_at(&self, point: Vector3) -> Vector3;
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>>;
     fn material(&self) -> MaterialId;
     fn bounding_box(&self) -> Aabb;
     fn surface_area(&self) -> Float;
+
+    /// Splits the part of the shape that lies within `bounding_box` by an axis-aligned
+    /// plane and returns tight bounds of both halves
+    ///
+    /// The default implementation just cuts the box, shapes can override it to
+    /// provide tighter bounds.
+    fn split_bounding_box(&self, bounding_box: &Aabb, axis: Axis, position: Float) -> (Option<Aabb>, Option<Aabb>) {
+        if position <= bounding_box.min()[axis] {
+            (None, Some(bounding_box.clone()))
+        } else if position >= bounding_box.max()[axis] {
+            (Some(bounding_box.clone()), None)
+        } else {
+            let (left, right) = bounding_box.split(axis, position);
+            (Some(left), Some(right))
+        }
+    }
+
+    /// Samples a point uniformly over the surface, returns it with the outward normal there
+    ///
+    /// Shapes without a finite area, like planes, can't be sampled and return `None`.
+    fn sample_area(&self, _rng: &mut RandomGenerator) -> Option<(Vector3, Vector3)> {
+        None
+    }
+
+    /// Samples a point on the surface to gather light from at `reference`
+    ///
+    /// The default implementation samples uniformly by area, shapes can override it
+    /// to only sample the part that can be seen from `reference`.
+    fn sample(&self, reference: Vector3, rng: &mut RandomGenerator) -> Option<SurfaceSample> {
+        let (point, normal) = self.sample_area(rng)?;
+        let pdf = area_to_solid_angle(1.0 / self.surface_area(), reference, point, normal);
+
+        Some(SurfaceSample { point, normal, pdf })
+    }
+
+    /// Density with respect to solid angle of `sample` returning `point` with
+    /// surface normal `normal` when called for `reference`
+    fn pdf(&self, reference: Vector3, point: Vector3, normal: Vector3) -> Float {
+        area_to_solid_angle(1.0 / self.surface_area(), reference, point, normal)
+    }
+}
+
+/// Point sampled on a surface as seen from a reference point
+pub struct SurfaceSample {
+    pub point: Vector3,
+    pub normal: Vector3,
+    // Probability density with respect to solid angle at the reference point
+    pub pdf: Float,
 }
 
+/// Converts a density per unit area at `point` to a density per solid angle at `reference`
+pub fn area_to_solid_angle(pdf: Float, reference: Vector3, point: Vector3, normal: Vector3) -> Float {
+    let to_point = point - reference;
+    let distance_squared = to_point.len_squared();
+    let cos = normal.dot(to_point).abs() / distance_squared.sqrt();
+
+    pdf * distance_squared / cos
+}
+
+// Rays leaving a surface start this far from it (relative to the magnitude of the hit point)
+const RAY_OFFSET: Float = 1e-7;
+
+/// Ray/surface interaction
+///
+/// Normals point outside of the shape, `front_face` tells whether the ray came
+/// from outside.
 pub struct Hit<'shapes> {
     pub ray: Ray,
     pub t: Float,
     pub shape: &'shapes dyn Shape,
+    // True surface normal, used to offset rays leaving the surface
+    pub normal: Vector3,
+    // Normal used for shading, e.g. interpolated from vertex normals
+    pub shading_normal: Vector3,
+    // Surface parametrization and its partial derivatives
+    pub uv: Vector2,
+    pub dpdu: Vector3,
+    pub dpdv: Vector3,
+    // Barycentric coordinates of the hit within a triangle
+    pub barycentric: Vector2,
+    // Index of the hit triangle within a mesh
+    pub primitive: usize,
+    pub front_face: bool,
 }
 
 impl<'shapes> Hit<'shapes> {
-    pub fn new(ray: &Ray, t: Float, shape: &'shapes dyn Shape) -> Hit<'shapes> {
+    /// Creates a hit with the given outward normal and an arbitrary tangent frame
+    pub fn new(ray: &Ray, t: Float, shape: &'shapes dyn Shape, normal: Vector3) -> Hit<'shapes> {
+        let (dpdu, dpdv) = normal.coordinate_system();
+
         Hit {
             ray: ray.clone(),
             t,
             shape,
+            normal,
+            shading_normal: normal,
+            uv: Vector2::ZERO,
+            dpdu,
+            dpdv,
+            barycentric: Vector2::ZERO,
+            primitive: 0,
+            front_face: ray.direction.dot(normal) < 0.0,
         }
     }
 
     pub fn point(&self) -> Vector3 {
         self.ray.point_at(self.t)
     }
+
+    /// Shading normal flipped to the side of the surface the ray came from
+    pub fn oriented_shading_normal(&self) -> Vector3 {
+        if self.front_face {
+            self.shading_normal
+        } else {
+            -self.shading_normal
+        }
+    }
+
+    /// Starts a new ray at the hit point, offset along the geometric normal
+    /// to the side `direction` points to, so it doesn't hit the same surface again
+    pub fn spawn_ray(&self, direction: Vector3) -> Ray {
+        let point = self.point();
+        let magnitude = point.x.abs().max(point.y.abs()).max(point.z.abs());
+        let offset = self.normal * (RAY_OFFSET * (1.0 + magnitude));
+
+        let origin = if direction.dot(self.normal) > 0.0 {
+            point + offset
+        } else {
+            point - offset
+        };
+
+        Ray::new(origin, direction)
+    }
 }
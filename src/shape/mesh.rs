@@ -0,0 +1,269 @@
+use crate::error::*;
+use crate::math::*;
+use crate::random::RandomGenerator;
+use crate::scene::MaterialId;
+use crate::shape::*;
+use std::sync::Arc;
+
+fn mesh_error(message: String) -> Error {
+    Error::MeshError(message)
+}
+
+/// Vertex and index buffers shared by all triangles of a mesh
+pub struct MeshData {
+    pub positions: Vec<Vector3>,
+    // Either empty or one per position
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<Vector2>,
+    // Three indices per triangle
+    pub indices: Vec<u32>,
+    pub material: MaterialId,
+}
+
+impl MeshData {
+    /// Applies `transform` to positions and normals
+    pub fn transform(&mut self, transform: &Matrix4) {
+        let normal_transform = transform.inverse().unwrap_or(Matrix4::IDENTITY);
+
+        for position in &mut self.positions {
+            *position = transform.transform_point(*position);
+        }
+        for normal in &mut self.normals {
+            *normal = normal_transform.transform_normal(*normal).normalize();
+        }
+    }
+}
+
+/// Indexed triangle mesh
+///
+/// Accelerators hold references to its `MeshTriangle`s, which only store a pointer
+/// to the shared buffers and the triangle index.
+pub struct TriangleMesh {
+    data: Arc<MeshData>,
+    triangles: Vec<MeshTriangle>,
+}
+
+impl TriangleMesh {
+    /// Checks the buffers and splits the mesh into triangles
+    pub fn new(data: MeshData) -> Result<Self> {
+        let vertex_count = data.positions.len();
+        if !data.indices.len().is_multiple_of(3) {
+            return Err(mesh_error(format!("index count {} is not a multiple of 3", data.indices.len())));
+        }
+        if !data.normals.is_empty() && data.normals.len() != vertex_count {
+            return Err(mesh_error(format!("{} normals for {} positions", data.normals.len(), vertex_count)));
+        }
+        if !data.uvs.is_empty() && data.uvs.len() != vertex_count {
+            return Err(mesh_error(format!("{} texture coordinates for {} positions", data.uvs.len(), vertex_count)));
+        }
+        if let Some(&index) = data.indices.iter().find(|&&index| index as usize >= vertex_count) {
+            return Err(mesh_error(format!("vertex index {} out of range for {} positions", index, vertex_count)));
+        }
+
+        let data = Arc::new(data);
+        let triangles = (0..data.indices.len() as u32 / 3)
+            .map(|index| MeshTriangle {
+                mesh: data.clone(),
+                index,
+            })
+            .collect();
+
+        Ok(Self { data, triangles })
+    }
+
+    pub fn data(&self) -> &MeshData {
+        &self.data
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn triangle(&self, index: usize) -> &dyn Shape {
+        &self.triangles[index]
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item = &dyn Shape> {
+        self.triangles.iter().map(|triangle| triangle as &dyn Shape)
+    }
+}
+
+/// Single triangle of a `TriangleMesh`
+pub struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    index: u32,
+}
+
+impl MeshTriangle {
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    fn vertex_indices(&self) -> [usize; 3] {
+        let first = 3 * self.index as usize;
+        let indices = &self.mesh.indices[first..first + 3];
+        [indices[0] as usize, indices[1] as usize, indices[2] as usize]
+    }
+
+    pub fn vertices(&self) -> [Vector3; 3] {
+        let [a, b, c] = self.vertex_indices();
+        let positions = &self.mesh.positions;
+        [positions[a], positions[b], positions[c]]
+    }
+
+    pub fn normals(&self) -> Option<[Vector3; 3]> {
+        if self.mesh.normals.is_empty() {
+            return None;
+        }
+
+        let [a, b, c] = self.vertex_indices();
+        let normals = &self.mesh.normals;
+        Some([normals[a], normals[b], normals[c]])
+    }
+
+    pub fn uvs(&self) -> Option<[Vector2; 3]> {
+        if self.mesh.uvs.is_empty() {
+            return None;
+        }
+
+        let [a, b, c] = self.vertex_indices();
+        let uvs = &self.mesh.uvs;
+        Some([uvs[a], uvs[b], uvs[c]])
+    }
+}
+
+impl Shape for MeshTriangle {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let vertices = self.vertices();
+        let [a, b, c] = vertices;
+        let (t, u, v) = intersect_triangle(ray, a, b, c)?;
+
+        let mut hit = Hit::new(ray, t, self, triangle_normal(a, b, c));
+        fill_triangle_hit(&mut hit, u, v, vertices, self.normals(), self.uvs());
+        hit.primitive = self.index();
+        Some(hit)
+    }
+
+    fn material(&self) -> MaterialId {
+        self.mesh.material
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [a, b, c] = self.vertices();
+        triangle_bounding_box(a, b, c)
+    }
+
+    fn surface_area(&self) -> Float {
+        let [a, b, c] = self.vertices();
+        triangle_area(a, b, c)
+    }
+
+    fn sample_area(&self, rng: &mut RandomGenerator) -> Option<(Vector3, Vector3)> {
+        let [a, b, c] = self.vertices();
+        Some(sample_triangle(a, b, c, rng))
+    }
+
+    fn split_bounding_box(&self, bounding_box: &Aabb, axis: Axis, position: Float) -> (Option<Aabb>, Option<Aabb>) {
+        split_triangle_bounding_box(self.vertices(), bounding_box, axis, position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unit square in the XY plane at z = 1, made of two triangles
+    fn square() -> MeshData {
+        MeshData {
+            positions: vec![
+                Vector3::new(0.0, 0.0, 1.0),
+                Vector3::new(1.0, 0.0, 1.0),
+                Vector3::new(1.0, 1.0, 1.0),
+                Vector3::new(0.0, 1.0, 1.0),
+            ],
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: vec![0, 1, 2, 0, 2, 3],
+            material: 3,
+        }
+    }
+
+    fn error(data: MeshData) -> String {
+        match TriangleMesh::new(data) {
+            Err(Error::MeshError(message)) => message,
+            Err(error) => panic!("unexpected error {}", error),
+            Ok(_) => panic!("invalid mesh was accepted"),
+        }
+    }
+
+    #[test]
+    fn invalid_buffers() {
+        assert_eq!(error(MeshData { indices: vec![0, 1, 2, 3], ..square() }), "index count 4 is not a multiple of 3");
+        assert_eq!(error(MeshData { indices: vec![0, 1, 4], ..square() }), "vertex index 4 out of range for 4 positions");
+        assert_eq!(error(MeshData { normals: vec![Vector3::ZERO; 3], ..square() }), "3 normals for 4 positions");
+        assert_eq!(error(MeshData { uvs: vec![Vector2::ZERO; 5], ..square() }), "5 texture coordinates for 4 positions");
+
+        let empty = TriangleMesh::new(MeshData { positions: Vec::new(), indices: Vec::new(), ..square() }).unwrap();
+        assert_eq!(empty.triangle_count(), 0);
+    }
+
+    #[test]
+    fn triangle_bounds() {
+        let mut data = square();
+        data.positions.push(Vector3::new(3.0, -1.0, 2.0));
+        data.indices = vec![0, 1, 2, 1, 4, 2];
+        let mesh = TriangleMesh::new(data).unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+
+        let bounds = |index: usize| {
+            let bounding_box = mesh.triangle(index).bounding_box();
+            let (min, max) = (bounding_box.min(), bounding_box.max());
+            [min.x, min.y, min.z, max.x, max.y, max.z]
+        };
+        assert_eq!(bounds(0), [0.0, 0.0, 1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(bounds(1), [1.0, -1.0, 1.0, 3.0, 1.0, 2.0]);
+
+        assert_eq!(mesh.triangle(0).surface_area(), 0.5);
+        assert_eq!(mesh.triangles().map(|triangle| triangle.material()).collect::<Vec<_>>(), vec![3, 3]);
+    }
+
+    #[test]
+    fn shared_buffers() {
+        let mesh = TriangleMesh::new(square()).unwrap();
+
+        // Triangles only hold a reference to the mesh buffers
+        assert_eq!(Arc::strong_count(&mesh.data), 3);
+        assert!(mesh.triangles.iter().all(|triangle| Arc::ptr_eq(&triangle.mesh, &mesh.data)));
+
+        let ray = |x: Float, y: Float| Ray::new(Vector3::new(x, y, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let hit = |x: Float, y: Float| mesh.triangles().find_map(|triangle| triangle.hit(&ray(x, y)));
+
+        let lower = hit(0.75, 0.25).unwrap();
+        assert_eq!((lower.t, lower.primitive), (1.0, 0));
+        let upper = hit(0.25, 0.75).unwrap();
+        assert_eq!((upper.t, upper.primitive), (1.0, 1));
+        assert!(hit(1.5, 0.5).is_none());
+    }
+
+    #[test]
+    fn vertex_normals() {
+        let tilted = Vector3::new(1.0, 0.0, -1.0).normalize();
+        let down = Vector3::new(0.0, 0.0, -1.0);
+        let mesh = TriangleMesh::new(MeshData { normals: vec![down, tilted, tilted, down], ..square() }).unwrap();
+
+        // Halfway between the vertices at x = 0 and x = 1
+        let ray = Ray::new(Vector3::new(0.5, 0.25, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let hit = mesh.triangle(0).hit(&ray).unwrap();
+        let expected = (down * 0.5 + tilted * 0.5).normalize();
+        assert!((hit.shading_normal - expected).len() < 1e-12);
+
+        // Vertex normals face away from the counter-clockwise normal, which is flipped to match
+        assert!(hit.normal.z < 0.0);
+        assert!(hit.front_face);
+
+        let without_normals = TriangleMesh::new(square()).unwrap();
+        let hit = without_normals.triangle(0).hit(&ray).unwrap();
+        assert_eq!((hit.shading_normal.x, hit.shading_normal.y, hit.shading_normal.z), (0.0, 0.0, 1.0));
+        assert!(!hit.front_face);
+    }
+}
@@ -9,12 +9,16 @@ pub struct Plane {
 }
 
 impl Shape for Plane {
-    fn hit(&self, ray: &Ray) -> Option<Hit> {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
         let d = self.normal.dot(ray.direction);
         if d.abs() > EPSILON {
             let t = (self.point - ray.origin).dot(self.normal) / d;
-            if t > EPSILON {
-                Some(Hit::new(ray, t, self))
+            if ray.contains(t) {
+                // Planar mapping in an arbitrary tangent frame around `point`
+                let mut hit = Hit::new(ray, t, self, self.normal);
+                let offset = hit.point() - self.point;
+                hit.uv = Vector2::new(offset.dot(hit.dpdu), offset.dot(hit.dpdv));
+                Some(hit)
             } else {
                 None
             }
@@ -23,19 +27,35 @@ impl Shape for Plane {
         }
     }
 
-    fn normal_at(&self, point: Vector3) -> Vector3 {
-        self.normal
-    }
-
     fn material(&self) -> MaterialId {
         self.material
     }
 
     fn bounding_box(&self) -> Aabb {
-        unimplemented!()
+        let infinity = Vector3::new(INFINITY, INFINITY, INFINITY);
+        Aabb::new(-infinity, infinity)
     }
 
     fn surface_area(&self) -> Float {
-        unimplemented!()
+        INFINITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_outside_interval() {
+        let plane = Plane { point: Vector3::new(0.0, 1.0, 0.0), normal: Vector3::new(0.0, 1.0, 0.0), material: 0 };
+        let ray = Ray::new(Vector3::new(3.0, -1.0, 2.0), Vector3::new(0.0, 1.0, 0.0));
+
+        assert_eq!(plane.hit(&ray).unwrap().t, 2.0);
+        assert!(plane.hit(&ray.shortened(2.0)).is_none());
+        assert!(plane.hit(&Ray { t_min: 2.0, ..ray.clone() }).is_none());
+        assert!(plane.hit(&Ray::new(ray.origin, -ray.direction)).is_none());
+
+        // Parallel rays never hit
+        assert!(plane.hit(&Ray::new(ray.origin, Vector3::new(1.0, 0.0, 0.0))).is_none());
     }
 }
@@ -1,4 +1,5 @@
 use crate::math::*;
+use crate::random::RandomGenerator;
 use crate::scene::MaterialId;
 use crate::shape::*;
 
@@ -8,8 +9,23 @@ pub struct Sphere {
     pub material: MaterialId,
 }
 
+impl Sphere {
+    /// Whether `point` is inside the sphere or on its surface, where the cone of
+    /// directions towards the sphere degenerates
+    fn encloses(&self, point: Vector3) -> bool {
+        // Hit points on the surface can end up slightly outside due to rounding
+        (point - self.center).len_squared() <= self.radius * self.radius * (1.0 + 1e-6)
+    }
+
+    /// Cosine of the half-angle of the cone the sphere subtends from a point outside of it
+    fn cone_cos_max(&self, distance_squared: Float) -> Float {
+        let sin_max_squared = self.radius * self.radius / distance_squared;
+        max(1.0 - sin_max_squared, 0.0).sqrt()
+    }
+}
+
 impl Shape for Sphere {
-    fn hit(&self, ray: &Ray) -> Option<Hit> {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
         let oc = ray.origin - self.center;
         let b = oc.dot(ray.direction);
         let c = oc.dot(oc) - self.radius * self.radius;
@@ -21,23 +37,33 @@ impl Shape for Sphere {
         let h = h.sqrt();
 
         let t = -b - h;
-        if t > EPSILON {
-            return Some(Hit::new(ray, t, self));
-        }
+        let t = if ray.contains(t) { t } else { -b + h };
+
+        if ray.contains(t) {
+            let normal = (ray.point_at(t) - self.center) / self.radius;
+            let mut hit = Hit::new(ray, t, self, normal);
+
+            // Spherical coordinates with the pole on +Y, u runs around the equator
+            let phi = normal.z.atan2(normal.x);
+            let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+            let theta = normal.y.clamp(-1.0, 1.0).acos();
+            hit.uv = Vector2::new(phi / (2.0 * PI), theta / PI);
 
-        let t = -b + h;
+            // Tangents degenerate at the poles, keep the frame from `Hit::new` there
+            let (sin_theta, cos_theta) = theta.sin_cos();
+            if sin_theta > EPSILON {
+                let (sin_phi, cos_phi) = phi.sin_cos();
+                let radius = self.radius;
+                hit.dpdu = Vector3::new(-normal.z, 0.0, normal.x) * (2.0 * PI * radius);
+                hit.dpdv = Vector3::new(cos_theta * cos_phi, -sin_theta, cos_theta * sin_phi) * (PI * radius);
+            }
 
-        if t > EPSILON {
-            return Some(Hit::new(ray, t, self));
+            return Some(hit);
         }
 
         None
     }
 
-    fn normal_at(&self, point: Vector3) -> Vector3 {
-        (point - self.center).normalize()
-    }
-
     fn material(&self) -> MaterialId {
         self.material
     }
@@ -61,4 +87,187 @@ impl Shape for Sphere {
     fn surface_area(&self) -> Float {
         4.0 * PI * self.radius * self.radius
     }
+
+    fn sample_area(&self, rng: &mut RandomGenerator) -> Option<(Vector3, Vector3)> {
+        let cos_theta = 1.0 - 2.0 * rng.unit();
+        let sin_theta = max(1.0 - cos_theta * cos_theta, 0.0).sqrt();
+        let phi = 2.0 * PI * rng.unit();
+
+        let normal = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        Some((self.center + normal * self.radius, normal))
+    }
+
+    /// Samples the cone of directions subtended by the sphere, or its whole surface
+    /// when `reference` is inside
+    fn sample(&self, reference: Vector3, rng: &mut RandomGenerator) -> Option<SurfaceSample> {
+        let to_center = self.center - reference;
+        let distance_squared = to_center.len_squared();
+        let radius_squared = self.radius * self.radius;
+
+        if self.encloses(reference) {
+            let (point, normal) = self.sample_area(rng)?;
+            let pdf = self.pdf(reference, point, normal);
+            return Some(SurfaceSample { point, normal, pdf });
+        }
+
+        let distance = distance_squared.sqrt();
+        let cos_max = self.cone_cos_max(distance_squared);
+
+        let cos_theta = 1.0 - rng.unit() * (1.0 - cos_max);
+        let sin_theta_squared = max(1.0 - cos_theta * cos_theta, 0.0);
+        let sin_theta = sin_theta_squared.sqrt();
+        let phi = 2.0 * PI * rng.unit();
+
+        let w = to_center / distance;
+        let (u, v) = w.coordinate_system();
+        let direction = u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + w * cos_theta;
+
+        // Nearest intersection of the sampled direction with the sphere
+        let t = distance * cos_theta - max(radius_squared - distance_squared * sin_theta_squared, 0.0).sqrt();
+        let point = reference + direction * t;
+
+        Some(SurfaceSample {
+            point,
+            normal: (point - self.center).normalize(),
+            pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)),
+        })
+    }
+
+    fn pdf(&self, reference: Vector3, point: Vector3, normal: Vector3) -> Float {
+        if self.encloses(reference) {
+            area_to_solid_angle(1.0 / self.surface_area(), reference, point, normal)
+        } else {
+            1.0 / (2.0 * PI * (1.0 - self.cone_cos_max((self.center - reference).len_squared())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_sphere() -> Sphere {
+        Sphere { center: Vector3::ZERO, radius: 1.0, material: 0 }
+    }
+
+    fn ray(origin: Vector3, direction: Vector3, t_min: Float, t_max: Float) -> Ray {
+        Ray { t_min, t_max, ..Ray::new(origin, direction) }
+    }
+
+    #[test]
+    fn hits_outside_interval() {
+        let sphere = unit_sphere();
+        let origin = Vector3::new(0.0, 0.0, -3.0);
+        let direction = Vector3::new(0.0, 0.0, 1.0);
+
+        assert_eq!(sphere.hit(&ray(origin, direction, EPSILON, INFINITY)).unwrap().t, 2.0);
+        assert_eq!(sphere.hit(&ray(origin, direction, EPSILON, 3.9)).unwrap().t, 2.0);
+
+        // Both roots beyond t_max or below t_min
+        assert!(sphere.hit(&ray(origin, direction, EPSILON, 2.0)).is_none());
+        assert!(sphere.hit(&ray(origin, direction, 4.0, INFINITY)).is_none());
+        // Behind the ray
+        assert!(sphere.hit(&ray(origin, -direction, EPSILON, INFINITY)).is_none());
+    }
+
+    #[test]
+    fn far_root() {
+        let sphere = unit_sphere();
+
+        // From inside, the near root is behind the origin
+        let inside = ray(Vector3::new(0.0, 0.0, 0.5), Vector3::new(0.0, 0.0, 1.0), EPSILON, INFINITY);
+        let hit = sphere.hit(&inside).unwrap();
+        assert_eq!(hit.t, 0.5);
+        assert!(!hit.front_face);
+
+        // The near root is skipped once t_min passes it
+        let outside = ray(Vector3::new(0.0, 0.0, -3.0), Vector3::new(0.0, 0.0, 1.0), 2.5, INFINITY);
+        let hit = sphere.hit(&outside).unwrap();
+        assert_eq!(hit.t, 4.0);
+        assert!(!hit.front_face);
+        assert!(sphere.hit(&outside.shortened(4.0)).is_none());
+    }
+
+    /// Point on the unit sphere at the given surface coordinates
+    fn parametrization(u: Float, v: Float) -> Vector3 {
+        let (phi, theta) = (2.0 * PI * u, PI * v);
+        Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+    }
+
+    #[test]
+    fn surface_parametrization() {
+        let sphere = unit_sphere();
+
+        // Looking along +Z at the equator, 3/4 of the way around from +X
+        let hit = sphere.hit(&ray(Vector3::new(0.0, 0.0, -3.0), Vector3::new(0.0, 0.0, 1.0), EPSILON, INFINITY)).unwrap();
+        assert_eq!((hit.uv.x, hit.uv.y), (0.75, 0.5));
+        assert!((hit.dpdu - Vector3::new(2.0 * PI, 0.0, 0.0)).len() < 1e-12);
+        assert!((hit.dpdv - Vector3::new(0.0, -PI, 0.0)).len() < 1e-12);
+        assert!(hit.front_face);
+        assert_eq!(hit.primitive, 0);
+
+        // Tangents match finite differences of the parametrization everywhere else
+        let mut rng = RandomGenerator::with_seed(3);
+        for _ in 0..100 {
+            let direction = rng.unit_sphere();
+            let hit = sphere.hit(&ray(direction * -3.0, direction, EPSILON, INFINITY)).unwrap();
+            let (u, v) = (hit.uv.x, hit.uv.y);
+            let point = parametrization(u, v);
+            assert!((point - hit.point()).len() < 1e-9);
+            assert!((point - hit.normal).len() < 1e-9);
+
+            let h = 1e-6;
+            let dpdu = (parametrization(u + h, v) - parametrization(u - h, v)) / (2.0 * h);
+            let dpdv = (parametrization(u, v + h) - parametrization(u, v - h)) / (2.0 * h);
+            assert!((dpdu - hit.dpdu).len() < 1e-6, "{:?} != {:?}", dpdu, hit.dpdu);
+            assert!((dpdv - hit.dpdv).len() < 1e-6, "{:?} != {:?}", dpdv, hit.dpdv);
+        }
+    }
+
+    #[test]
+    fn sample_matches_pdf() {
+        let sphere = Sphere { center: Vector3::new(1.0, 2.0, 3.0), radius: 1.5, material: 0 };
+        let mut rng = RandomGenerator::with_seed(5);
+        let count = 100_000;
+
+        // Outside, on the surface and inside, with the solid angle covered by the sphere
+        let cos_max = (1.0 - (sphere.radius / 5.0).powi(2)).sqrt();
+        let references = [
+            (sphere.center + Vector3::new(0.0, -5.0, 0.0), 2.0 * PI * (1.0 - cos_max)),
+            (sphere.center + Vector3::new(0.0, 0.0, 1.5), 2.0 * PI),
+            (sphere.center + Vector3::new(0.3, 0.2, -0.4), 4.0 * PI),
+        ];
+
+        for (reference, solid_angle) in references {
+            // Inverse densities average to the solid angle the samples are spread over
+            let mut inverse_pdf_sum = 0.0;
+
+            for _ in 0..count {
+                let sample = sphere.sample(reference, &mut rng).unwrap();
+                let pdf = sphere.pdf(reference, sample.point, sample.normal);
+                assert!((sample.pdf - pdf).abs() <= pdf * 1e-9, "{} != {}", sample.pdf, pdf);
+                assert!(((sample.point - sphere.center).len() - sphere.radius).abs() < 1e-9);
+                assert!((sample.normal - (sample.point - sphere.center) / sphere.radius).len() < 1e-9);
+
+                // Samples of the cone are on the near side
+                if !sphere.encloses(reference) {
+                    let to_point = sample.point - reference;
+                    let t = sphere.hit(&Ray::new(reference, to_point.normalize())).unwrap().t;
+                    assert!((t - to_point.len()).abs() < 1e-9);
+                }
+
+                inverse_pdf_sum += 1.0 / sample.pdf;
+            }
+
+            let estimate = inverse_pdf_sum / count as Float;
+            assert!((estimate / solid_angle - 1.0).abs() < 0.02, "{} != {}", estimate, solid_angle);
+        }
+
+        // Fraction of uniformly distributed directions hitting the sphere from outside
+        let reference = references[0].0;
+        let hits = (0..count).filter(|_| sphere.hit(&Ray::new(reference, rng.unit_sphere())).is_some()).count();
+        let solid_angle = 4.0 * PI * hits as Float / count as Float;
+        let pdf = sphere.pdf(reference, sphere.center, Vector3::Y);
+        assert!((solid_angle * pdf - 1.0).abs() < 0.05, "{} != {}", solid_angle, 1.0 / pdf);
+    }
 }
@@ -1,4 +1,5 @@
 use crate::math::*;
+use crate::random::RandomGenerator;
 use crate::scene::MaterialId;
 use crate::shape::*;
 
@@ -16,51 +17,167 @@ pub struct Triangle {
     pub material: MaterialId,
 }
 
-impl Shape for Triangle {
-    fn hit(&self, ray: &Ray) -> Option<Hit> {
-        // Möller–Trumbore intersection algorithm
-        let edge1 = self.b - self.a;
-        let edge2 = self.c - self.a;
+/// Möller–Trumbore ray/triangle intersection, returns `t` and barycentric coordinates of the hit
+pub fn intersect_triangle(ray: &Ray, a: Vector3, b: Vector3, c: Vector3) -> Option<(Float, Float, Float)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
 
-        let pvec = ray.direction.cross(edge2);
-        let det = edge1.dot(pvec);
+    let pvec = ray.direction.cross(edge2);
+    let det = edge1.dot(pvec);
 
-        if det.abs() < EPSILON {
-            return None;
-        }
+    if det.abs() < EPSILON {
+        return None;
+    }
 
-        let inv_det = 1.0 / det;
-        let tvec = ray.origin - self.a;
-        let u = tvec.dot(pvec) * inv_det;
-        if u < 0.0 || u > 1.0 {
-            return None;
-        }
+    let inv_det = 1.0 / det;
+    let tvec = ray.origin - a;
+    let u = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let qvec = tvec.cross(edge1);
+    let v = ray.direction.dot(qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(qvec) * inv_det;
+    if !ray.contains(t) {
+        return None;
+    }
+
+    Some((t, u, v))
+}
+
+/// Geometric normal, oriented by the counter-clockwise winding of vertices
+pub fn triangle_normal(a: Vector3, b: Vector3, c: Vector3) -> Vector3 {
+    (b - a).cross(c - a).normalize()
+}
+
+/// Fills in surface data of a triangle hit with barycentric coordinates `(u, v)`
+///
+/// The shading normal is interpolated from vertex normals, and texture coordinates
+/// from vertex UVs (barycentric coordinates are used when there are none). The
+/// geometric normal is flipped to the hemisphere of the shading normal, so both
+/// agree on which side is outside.
+pub fn fill_triangle_hit(
+    hit: &mut Hit,
+    u: Float,
+    v: Float,
+    vertices: [Vector3; 3],
+    normals: Option<[Vector3; 3]>,
+    uvs: Option<[Vector2; 3]>,
+) {
+    let w = 1.0 - u - v;
+    hit.barycentric = Vector2::new(u, v);
+
+    let [uv0, uv1, uv2] = uvs.unwrap_or([Vector2::new(0.0, 0.0), Vector2::new(1.0, 0.0), Vector2::new(0.0, 1.0)]);
+    hit.uv = uv0 * w + uv1 * u + uv2 * v;
+
+    // Solve p0 - p2 = dpdu * du02 + dpdv * dv02 (and the same for p1 - p2)
+    let [p0, p1, p2] = vertices;
+    let duv02 = uv0 - uv2;
+    let duv12 = uv1 - uv2;
+    let dp02 = p0 - p2;
+    let dp12 = p1 - p2;
+    let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+
+    // Degenerate UVs keep the arbitrary tangents set up by `Hit::new`
+    if determinant.abs() > 1e-12 {
+        let inv_determinant = 1.0 / determinant;
+        hit.dpdu = (dp02 * duv12.y - dp12 * duv02.y) * inv_determinant;
+        hit.dpdv = (dp12 * duv02.x - dp02 * duv12.x) * inv_determinant;
+    }
 
-        let qvec = tvec.cross(edge1);
-        let v = ray.direction.dot(qvec) * inv_det;
-        if v < 0.0 || u + v > 1.0 {
-            return None;
+    if let Some([na, nb, nc]) = normals {
+        let shading_normal = na * w + nb * u + nc * v;
+        if shading_normal.len_squared() > 0.0 {
+            hit.shading_normal = shading_normal.normalize();
+            if hit.normal.dot(hit.shading_normal) < 0.0 {
+                hit.normal = -hit.normal;
+                hit.front_face = !hit.front_face;
+            }
         }
+    }
+}
+
+pub fn triangle_bounding_box(a: Vector3, b: Vector3, c: Vector3) -> Aabb {
+    let min = a.min(b).min(c);
+    let max = a.max(b).max(c);
+
+    Aabb::new(min, max)
+}
+
+pub fn triangle_area(a: Vector3, b: Vector3, c: Vector3) -> Float {
+    let ab = b - a;
+    let ac = c - a;
+    ab.cross(ac).len() * 0.5
+}
+
+/// Samples a point uniformly over the triangle, returns it with the geometric normal
+pub fn sample_triangle(a: Vector3, b: Vector3, c: Vector3, rng: &mut RandomGenerator) -> (Vector3, Vector3) {
+    // Warping the unit square this way keeps the density uniform
+    let s = rng.unit().sqrt();
+    let u = 1.0 - s;
+    let v = rng.unit() * s;
+
+    (a * u + b * v + c * (1.0 - u - v), triangle_normal(a, b, c))
+}
+
+/// Clips the triangle against an axis-aligned plane and bounds both parts within `bounding_box`
+pub fn split_triangle_bounding_box(
+    vertices: [Vector3; 3],
+    bounding_box: &Aabb,
+    axis: Axis,
+    position: Float,
+) -> (Option<Aabb>, Option<Aabb>) {
+    let extend = |aabb: Option<Aabb>, point: Vector3| {
+        let point = Aabb::new(point, point);
+        Some(aabb.map_or(point.clone(), |aabb| aabb.extend(point)))
+    };
+
+    let mut left = None;
+    let mut right = None;
 
-        let t = edge2.dot(qvec) * inv_det;
-        if t < EPSILON {
-            return None;
+    // Clip every edge against the plane
+    for i in 0..3 {
+        let v0 = vertices[i];
+        let v1 = vertices[(i + 1) % 3];
+        let p0 = v0[axis];
+        let p1 = v1[axis];
+
+        if p0 <= position {
+            left = extend(left, v0);
+        }
+        if p0 >= position {
+            right = extend(right, v0);
         }
 
-        Some(Hit::new(ray, t, self))
+        if (p0 < position && position < p1) || (p1 < position && position < p0) {
+            let t = (position - p0) / (p1 - p0);
+            let mut point = v0 + (v1 - v0) * t;
+            point[axis] = position;
+
+            left = extend(left, point);
+            right = extend(right, point);
+        }
     }
 
-    fn normal_at(&self, point: Vector3) -> Vector3 {
-        let p = point - self.a;
-        let u = self.b - self.a;
-        let v = self.c - self.a;
+    (
+        left.and_then(|left| left.intersection(bounding_box)),
+        right.and_then(|right| right.intersection(bounding_box)),
+    )
+}
 
-        // Orient normal towards the incoming ray direction
-        if u.dot(p) > 0.0 {
-            v.cross(u).normalize()
-        } else {
-            u.cross(v).normalize()
-        }
+impl Shape for Triangle {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let (t, u, v) = intersect_triangle(ray, self.a, self.b, self.c)?;
+
+        let vertices = [self.a, self.b, self.c];
+        let mut hit = Hit::new(ray, t, self, triangle_normal(self.a, self.b, self.c));
+        fill_triangle_hit(&mut hit, u, v, vertices, Some([self.na, self.nb, self.nc]), None);
+        Some(hit)
     }
 
     fn material(&self) -> MaterialId {
@@ -68,15 +185,127 @@ impl Shape for Triangle {
     }
 
     fn bounding_box(&self) -> Aabb {
-        let min = self.a.min(self.b).min(self.c);
-        let max = self.a.max(self.b).max(self.c);
-
-        Aabb::new(min, max)
+        triangle_bounding_box(self.a, self.b, self.c)
     }
 
     fn surface_area(&self) -> Float {
-        let ab = self.b - self.a;
-        let ac = self.c - self.a;
-        ab.cross(ac).len() * 0.5
+        triangle_area(self.a, self.b, self.c)
+    }
+
+    fn sample_area(&self, rng: &mut RandomGenerator) -> Option<(Vector3, Vector3)> {
+        Some(sample_triangle(self.a, self.b, self.c, rng))
+    }
+
+    fn split_bounding_box(&self, bounding_box: &Aabb, axis: Axis, position: Float) -> (Option<Aabb>, Option<Aabb>) {
+        split_triangle_bounding_box([self.a, self.b, self.c], bounding_box, axis, position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle(a: Vector3, b: Vector3, c: Vector3) -> Triangle {
+        let normal = triangle_normal(a, b, c);
+        Triangle { a, b, c, na: normal, nb: normal, nc: normal, material: 0 }
+    }
+
+    #[test]
+    fn hits_outside_interval() {
+        let triangle = triangle(Vector3::new(0.0, 0.0, 2.0), Vector3::new(2.0, 0.0, 2.0), Vector3::new(0.0, 2.0, 2.0));
+        let ray = Ray::new(Vector3::new(0.5, 0.5, 0.0), Vector3::new(0.0, 0.0, 1.0));
+
+        assert_eq!(triangle.hit(&ray).unwrap().t, 2.0);
+        assert!(triangle.hit(&ray.shortened(2.0)).is_none());
+        assert!(triangle.hit(&Ray { t_min: 2.0, ..ray.clone() }).is_none());
+        assert!(triangle.hit(&Ray::new(ray.origin, -ray.direction)).is_none());
+
+        // Outside the edges
+        assert!(triangle.hit(&Ray::new(Vector3::new(1.5, 1.5, 0.0), ray.direction)).is_none());
+        assert!(triangle.hit(&Ray::new(Vector3::new(-0.1, 0.5, 0.0), ray.direction)).is_none());
+    }
+
+    fn assert_close(actual: Vector3, expected: Vector3) {
+        assert!((actual - expected).len() < 1e-12, "{:?} != {:?}", actual, expected);
+    }
+
+    /// Hit of a ray going down the Z axis through `(x, y)`
+    fn hit_from_above(triangle: &Triangle, x: Float, y: Float) -> Hit<'_> {
+        triangle.hit(&Ray::new(Vector3::new(x, y, 1.0), Vector3::new(0.0, 0.0, -1.0))).unwrap()
+    }
+
+    #[test]
+    fn interpolated_normal() {
+        let mut triangle = triangle(Vector3::ZERO, Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        triangle.nb = Vector3::new(1.0, 0.0, 1.0).normalize();
+        triangle.nc = Vector3::new(0.0, 1.0, 1.0).normalize();
+
+        // Barycentric weights of b and c are the x and y coordinates here
+        let hit = hit_from_above(&triangle, 0.25, 0.5);
+        assert_eq!((hit.barycentric.x, hit.barycentric.y), (0.25, 0.5));
+
+        let expected = (triangle.na * 0.25 + triangle.nb * 0.25 + triangle.nc * 0.5).normalize();
+        assert_close(hit.shading_normal, expected);
+        assert_close(hit.normal, Vector3::new(0.0, 0.0, 1.0));
+        assert!(hit.front_face);
+
+        // Vertices reproduce their own normals
+        assert_close(hit_from_above(&triangle, 1.0, 0.0).shading_normal, triangle.nb);
+    }
+
+    #[test]
+    fn normals_against_winding() {
+        // Clockwise seen from above, but the vertex normals point up
+        let mut triangle = triangle(Vector3::ZERO, Vector3::new(0.0, 1.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let up = Vector3::new(0.0, 0.0, 1.0);
+        triangle.na = up;
+        triangle.nb = up;
+        triangle.nc = up;
+
+        // The geometric normal is flipped to the shading normal's side, the ray still hits the front
+        let hit = hit_from_above(&triangle, 0.25, 0.25);
+        assert_close(hit.normal, up);
+        assert_close(hit.shading_normal, up);
+        assert!(hit.front_face);
+        assert_close(hit.oriented_shading_normal(), up);
+
+        let below = triangle.hit(&Ray::new(Vector3::new(0.25, 0.25, -1.0), up)).unwrap();
+        assert!(!below.front_face);
+        assert_close(below.oriented_shading_normal(), -up);
+    }
+
+    #[test]
+    fn surface_parametrization() {
+        let (a, b, c) = (Vector3::new(1.0, 1.0, 0.0), Vector3::new(3.0, 1.0, 0.0), Vector3::new(1.0, 2.0, 0.0));
+        let triangle = triangle(a, b, c);
+
+        // Without vertex UVs the barycentric coordinates are used, so p = a + u (b - a) + v (c - a)
+        let hit = hit_from_above(&triangle, 2.0, 1.5);
+        assert_eq!((hit.barycentric.x, hit.barycentric.y), (0.5, 0.5));
+        assert_eq!((hit.uv.x, hit.uv.y), (0.5, 0.5));
+        assert_close(hit.dpdu, b - a);
+        assert_close(hit.dpdv, c - a);
+        assert_eq!(hit.primitive, 0);
+
+        // Hits from behind
+        let below = triangle.hit(&Ray::new(Vector3::new(2.0, 1.5, -1.0), Vector3::new(0.0, 0.0, 1.0))).unwrap();
+        assert!(hit.front_face && !below.front_face);
+        assert_close(below.normal, hit.normal);
+    }
+
+    #[test]
+    fn vertex_uvs() {
+        let vertices = [Vector3::ZERO, Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)];
+        let uvs = [Vector2::new(0.5, 0.5), Vector2::new(2.5, 0.5), Vector2::new(0.5, 4.5)];
+        let ray = Ray::new(Vector3::new(0.25, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0));
+
+        let triangle = triangle(vertices[0], vertices[1], vertices[2]);
+        let mut hit = Hit::new(&ray, 1.0, &triangle, Vector3::new(0.0, 0.0, 1.0));
+        fill_triangle_hit(&mut hit, 0.25, 0.5, vertices, None, Some(uvs));
+
+        // u grows twice and v four times as fast as the position
+        assert_eq!((hit.uv.x, hit.uv.y), (1.0, 2.5));
+        assert_close(hit.dpdu, Vector3::new(0.5, 0.0, 0.0));
+        assert_close(hit.dpdv, Vector3::new(0.0, 0.25, 0.0));
     }
 }
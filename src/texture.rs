@@ -0,0 +1,62 @@
+use crate::color::Color3;
+use crate::error::*;
+use crate::image::Image;
+use crate::io::Hdr;
+use crate::math::*;
+use std::path::Path;
+
+/// Image mapped onto surfaces through texture coordinates
+///
+/// Coordinates wrap around and `v` points up, as in OBJ files.
+pub struct Texture {
+    image: Image,
+}
+
+impl Texture {
+    pub fn new(image: Image) -> Self {
+        Self { image }
+    }
+
+    /// Loads a Radiance HDR or PNG image, PNGs are converted from sRGB to linear
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let is_hdr = path.extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"));
+
+        let image = if is_hdr {
+            Hdr::load(path)?
+        } else {
+            Image::load_png(path)?
+        };
+
+        Ok(Self::new(image))
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Bilinearly filtered color at `uv`
+    pub fn sample(&self, uv: Vector2) -> Color3 {
+        let width = self.image.width();
+        let height = self.image.height();
+
+        // Texel centers are at half-integer coordinates
+        let x = modulo(uv.x, 1.0) * width as Float - 0.5;
+        let y = modulo(1.0 - uv.y, 1.0) * height as Float - 0.5;
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let wrap = |value: Float, size: usize| modulo(value, size as Float) as usize % size;
+        let (x0, x1) = (wrap(x0, width), wrap(x0 + 1.0, width));
+        let (y0, y1) = (wrap(y0, height), wrap(y0 + 1.0, height));
+
+        let top = self.image.get_pixel(x0, y0) * (1.0 - fx) + self.image.get_pixel(x1, y0) * fx;
+        let bottom = self.image.get_pixel(x0, y1) * (1.0 - fx) + self.image.get_pixel(x1, y1) * fx;
+
+        top * (1.0 - fy) + bottom * fy
+    }
+}